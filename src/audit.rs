@@ -0,0 +1,247 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module implements the audit log, an append-only log of every ruleset DFW applied.
+//!
+//! Every applied ruleset is recorded as a single line containing a JSON object (see
+//! [`AuditRecord`](struct.AuditRecord.html)), which includes what triggered the processing, the
+//! hash of the configuration in use and a unified diff of the generated rules against the
+//! previously recorded ruleset.
+//!
+//! The first record written by a DFW process is diffed against an empty ruleset, i.e. it contains
+//! the complete ruleset.
+
+use diff;
+use errors::*;
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time;
+use types::DFW;
+
+/// Number of unchanged lines surrounding the changes in a hunk of the diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Event that triggered the processing of the rules.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// The initial processing after DFW started.
+    Startup,
+    /// Docker events concerning containers or networks were received.
+    DockerEvent,
+    /// The load interval ticked.
+    Interval,
+    /// DFW received a `SIGHUP`.
+    Sighup,
+    /// The configuration changed while it was watched.
+    ConfigChange,
+    /// A previous processing run failed and is retried.
+    Retry,
+}
+
+/// A single entry of the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Time the ruleset was applied at, in RFC 3339 format.
+    pub timestamp: String,
+    /// Event that triggered the processing.
+    pub trigger: Trigger,
+    /// IDs of the containers the Docker events concerned, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affected_containers: Option<Vec<String>>,
+    /// Hash of the configuration the rules were generated from, see
+    /// [`config_hash`](fn.config_hash.html).
+    pub config_hash: String,
+    /// Hash of the generated rules.
+    pub rules_hash: String,
+    /// Unified diff of the generated rules against the previous record, empty if the rules did
+    /// not change.
+    pub diff: String,
+}
+
+/// Limit after which the audit log is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Size in bytes the audit log may not exceed, `0` disables the rotation.
+    pub max_size: u64,
+    /// Number of rotated audit logs to keep, named `<path>.1` (most recent) up to `<path>.<keep>`.
+    pub keep: usize,
+}
+
+/// Append-only audit log, see the [module documentation](index.html).
+pub struct AuditLog {
+    path: PathBuf,
+    rotation: Rotation,
+    previous_rules: Vec<String>,
+}
+
+impl AuditLog {
+    /// Open the audit log at `path`, creating it if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P, rotation: Rotation) -> Result<AuditLog> {
+        let path = path.as_ref().to_path_buf();
+        open_append(&path)?;
+
+        Ok(AuditLog {
+            path: path,
+            rotation: rotation,
+            previous_rules: Vec::new(),
+        })
+    }
+
+    /// Record that the given rules were applied, returning the record written to the log.
+    pub fn record(
+        &mut self,
+        trigger: Trigger,
+        affected_containers: Option<Vec<String>>,
+        config_hash: &str,
+        rules: Vec<String>,
+    ) -> Result<AuditRecord> {
+        let record = AuditRecord {
+            timestamp: format!("{}", time::now().rfc3339()),
+            trigger: trigger,
+            affected_containers: affected_containers,
+            config_hash: config_hash.to_owned(),
+            rules_hash: sha256(rules.join("\n").as_bytes()),
+            diff: unified_diff(&self.previous_rules, &rules),
+        };
+
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        self.rotate_if_required(line.len() as u64)?;
+        open_append(&self.path)?.write_all(line.as_bytes())?;
+
+        self.previous_rules = rules;
+        Ok(record)
+    }
+
+    fn rotate_if_required(&self, additional_size: u64) -> Result<()> {
+        if self.rotation.max_size == 0 {
+            return Ok(());
+        }
+        let size = fs::metadata(&self.path)?.len();
+        if size == 0 || size + additional_size <= self.rotation.max_size {
+            return Ok(());
+        }
+
+        let rotated = |index: usize| PathBuf::from(format!("{}.{}", self.path.display(), index));
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        // Shift every rotated log by one, dropping the oldest.
+        for index in (1..self.rotation.keep).rev() {
+            if rotated(index).exists() {
+                fs::rename(rotated(index), rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format_err!("failed to open audit log {}: {}", path.display(), e))
+}
+
+/// Hash of the configuration, formatted as `sha256:<hex digest>`.
+///
+/// The hash is calculated over the configuration as loaded (i.e. after the environment variables
+/// have been interpolated), so changes that do not modify the configuration, like reformatting
+/// it, do not change the hash.
+pub fn config_hash(dfw: &DFW) -> Result<String> {
+    // Objects are serialized with sorted keys, which makes the hash independent of the order of
+    // the maps in the configuration.
+    let value = serde_json::to_value(dfw)?;
+    Ok(sha256(serde_json::to_string(&value)?.as_bytes()))
+}
+
+fn sha256(data: &[u8]) -> String {
+    let mut hash = String::from("sha256:");
+    for byte in Sha256::digest(data).iter() {
+        write!(hash, "{:02x}", byte).expect("writing to a string cannot fail");
+    }
+
+    hash
+}
+
+/// Create a unified diff of the lines `new` against the lines `old`, with three lines of context
+/// around every change.
+///
+/// Returns an empty string if the lines are equal.
+pub fn unified_diff(old: &[String], new: &[String]) -> String {
+    // Every line of the diff, with the line numbers in `old` and `new` it starts at.
+    let mut lines = Vec::new();
+    let (mut old_line, mut new_line) = (0, 0);
+    for result in diff::slice(old, new) {
+        let (tag, line) = match result {
+            diff::Result::Left(line) => ('-', line),
+            diff::Result::Both(line, _) => (' ', line),
+            diff::Result::Right(line) => ('+', line),
+        };
+        lines.push((tag, line, old_line, new_line));
+        if tag != '+' {
+            old_line += 1;
+        }
+        if tag != '-' {
+            new_line += 1;
+        }
+    }
+
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|&(_, line)| line.0 != ' ')
+        .map(|(index, _)| index)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Group the changes into hunks, changes whose contexts overlap share a hunk.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for change in changes {
+        let start = change.saturating_sub(DIFF_CONTEXT);
+        let end = (change + DIFF_CONTEXT + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(hunk) if start <= hunk.1 => hunk.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut diff = String::from("--- previous\n+++ current\n");
+    for (start, end) in hunks {
+        let hunk = &lines[start..end];
+        let old_count = hunk.iter().filter(|line| line.0 != '+').count();
+        let new_count = hunk.iter().filter(|line| line.0 != '-').count();
+        // An empty range starts at the line before it, as in GNU diff.
+        let range_start = |line: usize, count: usize| if count == 0 { line } else { line + 1 };
+        writeln!(
+            diff,
+            "@@ -{},{} +{},{} @@",
+            range_start(hunk[0].2, old_count),
+            old_count,
+            range_start(hunk[0].3, new_count),
+            new_count
+        )
+        .expect("writing to a string cannot fail");
+        for &(tag, line, _, _) in hunk {
+            writeln!(diff, "{}{}", tag, line).expect("writing to a string cannot fail");
+        }
+    }
+
+    diff
+}
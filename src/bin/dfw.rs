@@ -22,6 +22,8 @@ extern crate dfw;
 extern crate failure;
 extern crate iptables as ipt;
 extern crate libc;
+extern crate notify;
+extern crate serde_json;
 extern crate shiplift;
 extern crate signal_hook;
 #[macro_use]
@@ -31,21 +33,39 @@ extern crate time;
 extern crate url;
 
 use channel::{Receiver, Sender};
-use clap::{App, Arg, ArgGroup, ArgMatches};
-use dfw::iptables::{IPTables, IPTablesDummy, IPTablesRestore, IPVersion};
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use dfw::audit::{self, AuditLog, Rotation, Trigger};
+use dfw::dfwfw;
+use dfw::docker::{Connection, DockerOptions, Engine};
+use dfw::errors::ConfigError;
+use dfw::inventory::{DockerInventory, Inventory, StaticInventory};
+use dfw::iptables::{
+    self, IPTables, IPTablesDummy, IPTablesRecorder, IPTablesRestore, IPTablesSave, IPVersion,
+};
+use dfw::logging::{self, LogDestination, LogFormat};
+use dfw::ruleset::Family;
+use dfw::schema;
+#[cfg(unix)]
+use dfw::systemd;
 use dfw::types::DFW;
 use dfw::util::*;
-use dfw::{ContainerFilter, ProcessDFW, ProcessingOptions};
+use dfw::{ContainerFilter, ProcessDFW, ProcessingCache, ProcessingOptions};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
 use shiplift::builder::{EventFilter, EventFilterType, EventsOptions};
 use shiplift::Docker;
 use slog::Logger;
-use sloggers::terminal::{Destination, TerminalLoggerBuilder};
 use sloggers::types::Severity;
-use sloggers::Build;
 #[allow(unused_imports, deprecated)]
 use std::ascii::AsciiExt;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 mod errors {
     use failure::Error;
@@ -74,25 +94,121 @@ arg_enum! {
     }
 }
 
-fn load_config(matches: &ArgMatches) -> Result<DFW> {
-    let toml: DFW = if matches.is_present("config-file") {
-        load_file(matches.value_of("config-file").unwrap())?
+/// Liveness of a monitoring thread.
+///
+/// The thread beats at least once per interval while it is able to do its work. A thread that
+/// stopped, or that is stuck (e.g. on a stalled connection), stops beating.
+#[derive(Clone)]
+struct Heartbeat {
+    interval: Duration,
+    started_at: Instant,
+    // Milliseconds since `started_at`.
+    last_beat: Arc<AtomicU64>,
+}
+
+impl Heartbeat {
+    fn new(interval: Duration) -> Heartbeat {
+        Heartbeat {
+            interval: interval,
+            started_at: Instant::now(),
+            last_beat: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn beat(&self) {
+        self.last_beat.store(self.elapsed(), Ordering::Relaxed);
+    }
+
+    /// Check if the thread beat within the last two intervals.
+    fn is_fresh(&self) -> bool {
+        // The thread may beat after the time was taken, its beat is newer than `elapsed` then.
+        let age = self
+            .elapsed()
+            .saturating_sub(self.last_beat.load(Ordering::Relaxed));
+        age <= 2 * self.interval.as_millis() as u64
+    }
+
+    fn elapsed(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn config_source(matches: &ArgMatches) -> Result<ConfigSource> {
+    Ok(if matches.is_present("config-file") {
+        ConfigSource::File(matches.value_of("config-file").unwrap().to_owned())
     } else if matches.is_present("config-path") {
-        load_path(matches.value_of("config-path").unwrap())?
+        ConfigSource::Path(matches.value_of("config-path").unwrap().to_owned())
     } else {
         // This statement should be unreachable, since clap verifies that either config-file or
         // config-path is populated.
         // If we reach this anyway, bail.
         bail!("neither config-file nor config-path specified");
+    })
+}
+
+fn load_config(matches: &ArgMatches) -> Result<DFW> {
+    config_source(matches)?.load()
+}
+
+fn docker_connection(matches: &ArgMatches) -> Result<Connection> {
+    DockerOptions {
+        url: matches.value_of("docker-url").map(|s| s.to_owned()),
+        tls_ca: matches.value_of("docker-tls-ca").map(PathBuf::from),
+        tls_cert: matches.value_of("docker-tls-cert").map(PathBuf::from),
+        tls_key: matches.value_of("docker-tls-key").map(PathBuf::from),
+    }
+    .resolve()
+}
+
+fn connect_docker(matches: &ArgMatches, logger: &Logger) -> Result<Docker> {
+    let connection = docker_connection(matches)?;
+    trace!(logger, "Connecting to docker";
+           o!("docker_url" => &connection.url,
+              "tls" => connection.tls.is_some()));
+    let docker = connection.connect()?;
+    // Check if the docker instance is reachable
+    trace!(logger, "Pinging docker");
+    docker.ping()?;
+
+    Ok(docker)
+}
+
+fn detect_engine(matches: &ArgMatches, docker: &Docker, logger: &Logger) -> Result<Engine> {
+    let engine = match matches.value_of("engine") {
+        Some("auto") | None => Engine::detect(&docker_connection(matches)?, docker)?,
+        Some(engine) => engine.parse()?,
+    };
+    debug!(logger, "Using container engine";
+           o!("engine" => format!("{:?}", engine)));
+
+    Ok(engine)
+}
+
+fn get_processing_options(matches: &ArgMatches) -> Result<ProcessingOptions> {
+    let container_filter = match matches.value_of("container-filter") {
+        Some("all") => ContainerFilter::All,
+        Some("running") => ContainerFilter::Running,
+        Some(_) | None => bail!("wrong or no container filter specified"),
     };
 
-    Ok(toml)
+    Ok(ProcessingOptions {
+        container_filter,
+        strict: matches.is_present("strict"),
+    })
 }
 
 fn spawn_burst_monitor(
     burst_timeout: u64,
-    s_trigger: Sender<()>,
-    r_event: Receiver<()>,
+    s_trigger: Sender<Option<Vec<String>>>,
+    r_event: Receiver<Option<String>>,
+    heartbeat: Heartbeat,
     logger: &Logger,
 ) -> thread::JoinHandle<()> {
     let logger = logger.new(o!("thread" => "burst_monitor"));
@@ -111,6 +227,11 @@ fn spawn_burst_monitor(
             r_dummy
         };
         let mut after: Receiver<Instant> = dummy.clone();
+        let heartbeat_tick = channel::tick(heartbeat.interval);
+        // IDs of the containers affected by the events received during the current burst. This is
+        // `None` if any of the events did not identify a container, in which case all rules have
+        // to be processed.
+        let mut affected_containers: Option<Vec<String>> = Some(Vec::new());
 
         loop {
             // The `unused_assignments` warning for the following variable is wrong, since
@@ -121,15 +242,29 @@ fn spawn_burst_monitor(
             let mut trigger: Trigger = Trigger::None;
 
             select! {
-                recv(r_event) -> _ => {
-                    trace!(logger, "Received docker event");
+                recv(r_event) -> container_id => {
+                    trace!(logger, "Received docker event";
+                           o!("container_id" => format!("{:?}", container_id)));
                     trigger = Trigger::Event;
+                    match container_id {
+                        Ok(Some(container_id)) => {
+                            if let Some(ref mut affected_containers) = affected_containers {
+                                if !affected_containers.contains(&container_id) {
+                                    affected_containers.push(container_id);
+                                }
+                            }
+                        }
+                        _ => affected_containers = None,
+                    }
                 },
                 recv(after) -> _ => {
-                    trace!(logger, "After timer ran out, sending trigger");
+                    trace!(logger, "After timer ran out, sending trigger";
+                           o!("affected_containers" => format!("{:?}", affected_containers)));
                     trigger = Trigger::After;
-                    s_trigger.send(());
-                }
+                    s_trigger.send(affected_containers.replace(Vec::new()));
+                    heartbeat.beat();
+                },
+                recv(heartbeat_tick) -> _ => heartbeat.beat(),
             }
 
             trace!(logger, "Resetting after channel";
@@ -144,23 +279,32 @@ fn spawn_burst_monitor(
 }
 
 fn spawn_event_monitor(
-    docker_url: Option<String>,
-    s_event: Sender<()>,
+    docker: Docker,
+    engine: Engine,
+    s_event: Sender<Option<String>>,
+    heartbeat: Heartbeat,
     logger: &Logger,
 ) -> thread::JoinHandle<()> {
     let logger = logger.new(o!("thread" => "event_monitor"));
 
     thread::spawn(move || {
-        let docker = match docker_url {
-            Some(docker_url) => Docker::host(docker_url.parse().unwrap()),
-            None => Docker::new(),
-        };
+        // The events are requested in windows of the heartbeat interval, which ends the stream
+        // regularly even if no events occur. A stalled stream stops the heartbeat.
+        let window = ::std::cmp::max(heartbeat.interval.as_secs(), 1);
+        let mut since = unix_time();
         loop {
-            trace!(logger, "Waiting for events");
+            heartbeat.beat();
+            let until = since + window;
+            trace!(logger, "Waiting for events";
+                   o!("since" => since,
+                      "until" => until));
+            let mut triggered = false;
             for event in docker
                 .events(
                     &EventsOptions::builder()
                         .filter(vec![EventFilter::Type(EventFilterType::Container)])
+                        .since(&since)
+                        .until(&until)
                         .build(),
                 )
                 .unwrap()
@@ -168,22 +312,255 @@ fn spawn_event_monitor(
                 trace!(logger, "Received event";
                        o!("event" => format!("{:?}", &event)));
                 match event.status {
-                    Some(ref status) => match &**status {
-                        "create" | "destroy" | "start" | "restart" | "die" | "stop" => {
-                            trace!(logger, "Trigger channel about event";
-                                       o!("event" => format!("{:?}", event)));
-                            s_event.send(());
-                            break;
-                        }
-                        _ => continue,
-                    },
-                    None => continue,
+                    Some(ref status) if engine.triggers_processing(status) => {
+                        trace!(logger, "Trigger channel about event";
+                               o!("event" => format!("{:?}", event)));
+                        s_event.send(event.id.clone());
+                        triggered = true;
+                        break;
+                    }
+                    _ => continue,
                 }
             }
+            since = if triggered { unix_time() } else { until };
         }
     })
 }
 
+fn spawn_config_watcher(
+    config_source: ConfigSource,
+    debounce: u64,
+    s_config: Sender<()>,
+    heartbeat: Heartbeat,
+    logger: &Logger,
+) -> Result<thread::JoinHandle<()>> {
+    let logger = logger.new(o!("thread" => "config_watcher"));
+
+    let (s_notify, r_notify) = mpsc::channel();
+    let mut watcher = notify::watcher(s_notify, Duration::from_millis(debounce))?;
+    let watch_directory = config_source.watch_directory();
+    watcher.watch(&watch_directory, RecursiveMode::NonRecursive)?;
+    trace!(logger, "Watching configuration";
+           o!("directory" => watch_directory.display().to_string()));
+
+    Ok(thread::spawn(move || {
+        // The watcher stops watching once it is dropped, it has to live as long as the thread.
+        let _watcher = watcher;
+        loop {
+            heartbeat.beat();
+            let event = match r_notify.recv_timeout(heartbeat.interval) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            trace!(logger, "Received file event";
+                   o!("event" => format!("{:?}", event)));
+            let affected = match event {
+                DebouncedEvent::Create(ref path)
+                | DebouncedEvent::Write(ref path)
+                | DebouncedEvent::Chmod(ref path)
+                | DebouncedEvent::Remove(ref path) => config_source.is_affected_by(path),
+                DebouncedEvent::Rename(ref from, ref to) => {
+                    config_source.is_affected_by(from) || config_source.is_affected_by(to)
+                }
+                DebouncedEvent::Rescan => true,
+                DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => false,
+                DebouncedEvent::Error(ref e, _) => {
+                    warn!(logger, "Error while watching configuration";
+                          o!("error" => format!("{}", e)));
+                    false
+                }
+            };
+            if affected {
+                trace!(logger, "Trigger channel about configuration change");
+                s_config.send(());
+            }
+        }
+    }))
+}
+
+fn render(matches: &ArgMatches, render_matches: &ArgMatches, root_logger: &Logger) -> Result<()> {
+    let toml = load_config(render_matches)?;
+    debug!(root_logger, "Configuration loaded";
+           o!("config" => format!("{:#?}", toml)));
+
+    let inventory_file = render_matches.value_of("inventory").unwrap();
+    let mut contents = String::new();
+    File::open(inventory_file)?.read_to_string(&mut contents)?;
+    let inventory = StaticInventory::from_json(&contents)?;
+    debug!(root_logger, "Inventory loaded";
+           o!("inventory_file" => inventory_file));
+
+    let processing_options = get_processing_options(matches)?;
+    let ipt4 = IPTablesRestore::new(IPVersion::IPv4)?;
+    let ipt6 = IPTablesRestore::new(IPVersion::IPv6)?;
+    let process = ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        root_logger,
+    )?;
+
+    match render_matches.value_of("format") {
+        Some("iptables-restore") => {
+            process.generate()?;
+            // Only the IPv4 rules are committed during processing, so these are the rules that
+            // would be applied.
+            for line in ipt4.get_rules() {
+                println!("{}", line);
+            }
+        }
+        Some("json") => println!("{}", process.ruleset()?.to_json()?),
+        Some(_) | None => bail!("wrong or no format specified"),
+    }
+
+    Ok(())
+}
+
+/// Print the differences between the generated and the installed rules, returning whether there
+/// are any.
+fn diff(matches: &ArgMatches, diff_matches: &ArgMatches, root_logger: &Logger) -> Result<bool> {
+    let toml = load_config(diff_matches)?;
+    debug!(root_logger, "Configuration loaded";
+           o!("config" => format!("{:#?}", toml)));
+
+    let docker = connect_docker(matches, root_logger)?;
+    let engine = detect_engine(matches, &docker, root_logger)?;
+    let inventory = DockerInventory::new(&docker).with_engine(engine);
+    let processing_options = get_processing_options(matches)?;
+    let ruleset = ProcessDFW::new(
+        &inventory,
+        &toml,
+        &IPTablesDummy,
+        &IPTablesDummy,
+        &processing_options,
+        root_logger,
+    )?
+    .ruleset()?;
+
+    let mut families = vec![(Family::IPv4, IPVersion::IPv4)];
+    // The `iptables-restore` backend doesn't commit the IPv6 rules yet, they would never match
+    // the installed ones.
+    match value_t!(matches.value_of("iptables-backend"), IPTablesBackend)? {
+        IPTablesBackend::IPTablesRestore => info!(
+            root_logger,
+            "The iptables-restore backend doesn't apply IPv6 rules, only comparing IPv4 rules"
+        ),
+        _ => families.push((Family::IPv6, IPVersion::IPv6)),
+    }
+
+    let mut changed = false;
+    for &(family, ip_version) in &families {
+        let installed = IPTablesSave::parse(&iptables::save(ip_version)?)?;
+        let changes = ruleset.diff(family, &installed)?;
+        let mut table = None;
+        for change in &changes {
+            if table != Some(&change.table) {
+                println!("# {} {}", family, change.table);
+                table = Some(&change.table);
+            }
+            println!("{}", change);
+        }
+        changed |= !changes.is_empty();
+    }
+
+    Ok(changed)
+}
+
+fn import_dfwfw(import_matches: &ArgMatches, root_logger: &Logger) -> Result<()> {
+    let dfwfw_file = import_matches.value_of("dfwfw-file").unwrap();
+    let mut contents = String::new();
+    File::open(dfwfw_file)?.read_to_string(&mut contents)?;
+
+    let conversion = dfwfw::convert(&contents)?;
+    for warning in &conversion.warnings {
+        warn!(root_logger, "Could not convert DFWFW configuration";
+              o!("warning" => warning));
+    }
+    let toml = conversion.to_toml()?;
+
+    match import_matches.value_of("output") {
+        Some(output) => {
+            File::create(output)?.write_all(toml.as_bytes())?;
+            info!(root_logger, "Converted configuration written";
+                  o!("output" => output,
+                     "warnings" => conversion.warnings.len()));
+        }
+        None => print!("{}", toml),
+    }
+
+    Ok(())
+}
+
+/// Check that the rules for the configuration can be generated, without applying them.
+fn validate_config(
+    inventory: &Inventory,
+    toml: &DFW,
+    processing_options: &ProcessingOptions,
+    logger: &Logger,
+) -> Result<()> {
+    ProcessDFW::new(
+        inventory,
+        toml,
+        &IPTablesDummy,
+        &IPTablesDummy,
+        processing_options,
+        logger,
+    )?
+    .generate()
+}
+
+fn print_schema(schema_matches: &ArgMatches, root_logger: &Logger) -> Result<()> {
+    let json = serde_json::to_string_pretty(&schema::dfw_schema())?;
+
+    match schema_matches.value_of("output") {
+        Some(output) => {
+            File::create(output)?.write_all(json.as_bytes())?;
+            info!(root_logger, "Configuration schema written";
+                  o!("output" => output));
+        }
+        None => println!("{}", json),
+    }
+
+    Ok(())
+}
+
+fn dump_inventory(
+    matches: &ArgMatches,
+    dump_matches: &ArgMatches,
+    root_logger: &Logger,
+) -> Result<()> {
+    let docker = connect_docker(matches, root_logger)?;
+    let engine = detect_engine(matches, &docker, root_logger)?;
+    let inventory = StaticInventory::capture(&DockerInventory::new(&docker).with_engine(engine))?;
+    let json = inventory.to_json()?;
+
+    match dump_matches.value_of("output") {
+        Some(output) => {
+            File::create(output)?.write_all(json.as_bytes())?;
+            info!(root_logger, "Inventory snapshot written";
+                  o!("output" => output));
+        }
+        None => println!("{}", json),
+    }
+
+    Ok(())
+}
+
+/// Send a notification to systemd. Failing to notify systemd is logged, but doesn't stop DFW.
+#[cfg(unix)]
+fn notify_systemd(state: &str, logger: &Logger) {
+    match systemd::notify(state) {
+        Ok(true) => trace!(logger, "Notified systemd"; o!("state" => state)),
+        Ok(false) => {}
+        Err(e) => warn!(logger, "Failed to notify systemd";
+                        o!("state" => state,
+                           "error" => format!("{}", e))),
+    }
+}
+
 #[cfg(unix)]
 fn run<'a>(
     matches: &ArgMatches<'a>,
@@ -194,13 +571,20 @@ fn run<'a>(
            o!("version" => crate_version!(),
               "started_at" => format!("{}", time::now().rfc3339())));
 
-    let docker = match matches.value_of("docker-url") {
-        Some(docker_url) => Docker::host(docker_url.parse()?),
-        None => Docker::new(),
+    let docker = connect_docker(matches, root_logger)?;
+    let engine = detect_engine(matches, &docker, root_logger)?;
+    let inventory = DockerInventory::new(&docker).with_engine(engine);
+
+    let monitor_events = !matches.is_present("disable-event-monitoring");
+    trace!(root_logger, "Monitoring events: {}", monitor_events;
+           o!("monitor_events" => monitor_events));
+    // The client for the event monitoring thread is created up front, creating a client might
+    // modify the environment of the process, which is not safe once other threads are running.
+    let events_docker = if monitor_events {
+        Some(connect_docker(matches, root_logger)?)
+    } else {
+        None
     };
-    // Check if the docker instance is reachable
-    trace!(root_logger, "Pinging docker");
-    docker.ping()?;
 
     // Create a dummy channel
     let load_interval = value_t!(matches.value_of("load-interval"), u64)?;
@@ -225,24 +609,19 @@ fn run<'a>(
         }
     };
 
-    let container_filter = match matches.value_of("container-filter") {
-        Some("all") => ContainerFilter::All,
-        Some("running") => ContainerFilter::Running,
-        Some(_) | None => bail!("wrong or no container filter specified"),
-    };
-    let processing_options = ProcessingOptions { container_filter };
-
-    let monitor_events = !matches.is_present("disable-event-monitoring");
-    trace!(root_logger, "Monitoring events: {}", monitor_events;
-           o!("monitor_events" => monitor_events));
+    let processing_options = get_processing_options(matches)?;
 
     let run_once = matches.is_present("run-once");
     trace!(root_logger, "Run once: {}", run_once;
            o!("run_once" => run_once));
 
-    let toml = load_config(&matches)?;
+    let toml = RefCell::new(load_config(&matches)?);
     debug!(root_logger, "Initial configuration loaded";
-           o!("config" => format!("{:#?}", toml)));
+           o!("config" => format!("{:#?}", toml.borrow())));
+
+    let watch_config = matches.is_present("watch-config");
+    trace!(root_logger, "Watch configuration: {}", watch_config;
+           o!("watch_config" => watch_config));
 
     let dry_run = matches.is_present("dry-run");
     let iptables_backend = value_t!(matches.value_of("iptables-backend"), IPTablesBackend)?;
@@ -262,63 +641,199 @@ fn run<'a>(
         }
     };
 
-    let processing_logger = root_logger.new(o!());
-    let process: Box<Fn() -> Result<()>> = match value_t!(matches.value_of("load-mode"), LoadMode)?
-    {
-        LoadMode::Once => {
-            trace!(root_logger, "Creating process closure according to load mode";
-                   o!("load_mode" => "once"));
-            Box::new(|| {
-                ProcessDFW::new(
-                    &docker,
-                    &toml,
-                    &*ipt4,
-                    &*ipt6,
-                    &processing_options,
-                    &processing_logger,
-                )?
-                .process()
-                .map_err(From::from)
-            })
+    // Keep a copy of the applied rules for the audit log.
+    let ipt4 = IPTablesRecorder::new(&*ipt4, IPVersion::IPv4)?;
+    let ipt6 = IPTablesRecorder::new(&*ipt6, IPVersion::IPv6)?;
+    let audit_log = match matches.value_of("audit-log") {
+        Some(_) if dry_run => {
+            info!(
+                root_logger,
+                "Dry run, the rules are not recorded in the audit log"
+            );
+            None
         }
-        LoadMode::Always => {
-            trace!(root_logger, "Creating process closure according to load mode";
-                   o!("load_mode" => "always"));
-            Box::new(|| {
-                let toml = load_config(&matches)?;
-                debug!(root_logger, "Reloaded configuration before processing";
-                       o!("config" => format!("{:#?}", toml)));
-
-                ProcessDFW::new(
-                    &docker,
-                    &toml,
-                    &*ipt4,
-                    &*ipt6,
-                    &processing_options,
-                    &processing_logger,
-                )?
-                .process()
-                .map_err(From::from)
-            })
+        Some(audit_log_file) => {
+            let rotation = Rotation {
+                max_size: value_t!(matches.value_of("audit-log-max-size"), u64)?,
+                keep: value_t!(matches.value_of("audit-log-keep"), usize)?,
+            };
+            trace!(root_logger, "Opening audit log";
+                   o!("audit_log" => audit_log_file,
+                      "max_size" => rotation.max_size,
+                      "keep" => rotation.keep));
+            Some(RefCell::new(AuditLog::open(audit_log_file, rotation)?))
         }
+        None => None,
     };
+
+    let processing_logger = root_logger.new(o!());
+    let processing_cache = ProcessingCache::new();
+    // The process closures return the hash of the configuration the rules were generated from.
+    let process: Box<Fn(Option<Vec<String>>) -> Result<String>> =
+        match value_t!(matches.value_of("load-mode"), LoadMode)? {
+            LoadMode::Once => {
+                trace!(root_logger, "Creating process closure according to load mode";
+                       o!("load_mode" => "once"));
+                Box::new(|affected_containers| {
+                    let toml = toml.borrow();
+                    ProcessDFW::new(
+                        &inventory,
+                        &toml,
+                        &ipt4,
+                        &ipt6,
+                        &processing_options,
+                        &processing_logger,
+                    )?
+                    .with_cache(&processing_cache, affected_containers)
+                    .process()?;
+
+                    audit::config_hash(&toml)
+                })
+            }
+            LoadMode::Always => {
+                trace!(root_logger, "Creating process closure according to load mode";
+                       o!("load_mode" => "always"));
+                Box::new(|affected_containers| {
+                    // An invalid configuration must not stop the daemon, the last configuration
+                    // that could be loaded stays in effect until the configuration is fixed.
+                    match load_config(&matches) {
+                        Ok(new_toml) => {
+                            debug!(root_logger, "Reloaded configuration before processing";
+                                   o!("config" => format!("{:#?}", new_toml)));
+                            toml.replace(new_toml);
+                        }
+                        Err(e) => {
+                            error!(root_logger,
+                                   "Failed to reload configuration, processing the last good one";
+                                   o!("error" => format!("{}", e)));
+                        }
+                    }
+                    let toml = toml.borrow();
+
+                    ProcessDFW::new(
+                        &inventory,
+                        &toml,
+                        &ipt4,
+                        &ipt6,
+                        &processing_options,
+                        &processing_logger,
+                    )?
+                    .with_cache(&processing_cache, affected_containers)
+                    .process()?;
+
+                    audit::config_hash(&toml)
+                })
+            }
+        };
     trace!(
         root_logger,
         "Load mode: {:?}",
         matches.value_of("load-mode")
     );
+    // Record every applied ruleset in the audit log and report the outcome of every processing run
+    // to systemd.
+    let process = |trigger: Trigger, affected_containers: Option<Vec<String>>| -> Result<()> {
+        let result = process(affected_containers.clone());
+        // Only the committed rules are recorded, rules of a failed run are discarded.
+        let mut rules = ipt4.take_rules();
+        rules.extend(ipt6.take_rules());
+        if let (Ok(config_hash), Some(audit_log)) = (&result, &audit_log) {
+            // The rules are applied at this point, failing to record them doesn't fail the run.
+            match audit_log
+                .borrow_mut()
+                .record(trigger, affected_containers, config_hash, rules)
+            {
+                Ok(record) => {
+                    debug!(root_logger, "Recorded applied rules in audit log";
+                           o!("trigger" => format!("{:?}", record.trigger),
+                              "config_hash" => record.config_hash,
+                              "rules_hash" => record.rules_hash));
+                }
+                Err(e) => {
+                    error!(root_logger, "Failed to record applied rules in audit log";
+                           o!("error" => format!("{}", e)));
+                }
+            }
+        }
+        let status = match result {
+            Ok(_) => format!("Rules applied at {}", time::now().rfc3339()),
+            Err(ref e) => format!("Failed to apply rules: {}", e),
+        };
+        notify_systemd(&systemd::status(&status), root_logger);
+        result.map(|_| ())
+    };
 
     info!(root_logger, "Application started";
           "version" => crate_version!(),
           "started_at" => format!("{}", time::now().rfc3339()));
 
+    // Failed processing runs are retried after an increasing delay. Only configuration errors stop
+    // DFW, since the configuration has to be fixed before the rules can be applied.
+    let mut backoff = Backoff::new(
+        Duration::from_secs(value_t!(matches.value_of("retry-initial-delay"), u64)?),
+        Duration::from_secs(value_t!(matches.value_of("retry-max-delay"), u64)?),
+        value_t!(matches.value_of("retry-max-attempts"), u32)?,
+    );
+    let mut retry_timer = channel::never();
+    // systemd is told that DFW is ready once the rules were applied for the first time, which might
+    // only be the case after retrying a failed start-up.
+    let ready = Cell::new(false);
+    let notify_ready = || {
+        if !ready.replace(true) {
+            notify_systemd(systemd::READY, root_logger);
+        }
+    };
+    let try_process = |trigger: Trigger,
+                       affected_containers: Option<Vec<String>>,
+                       backoff: &mut Backoff,
+                       retry_timer: &mut Receiver<Instant>|
+     -> Result<()> {
+        let e = match process(trigger, affected_containers) {
+            Ok(()) => {
+                backoff.reset();
+                *retry_timer = channel::never();
+                notify_ready();
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        if e.downcast_ref::<ConfigError>().is_some() {
+            return Err(e);
+        }
+
+        match backoff.next_delay() {
+            Some(delay) => {
+                warn!(root_logger, "Processing failed, retrying";
+                      o!("error" => format!("{}", e),
+                         "attempt" => backoff.attempts(),
+                         "delay" => format!("{:?}", delay)));
+                *retry_timer = channel::after(delay);
+            }
+            None => {
+                error!(root_logger, "Processing failed, giving up retrying until the next trigger";
+                       o!("error" => format!("{}", e),
+                          "attempts" => backoff.attempts()));
+                backoff.reset();
+                *retry_timer = channel::never();
+            }
+        }
+
+        Ok(())
+    };
+
     // Initial processing
     debug!(root_logger, "Start first processing");
-    process()?;
+    let exit_after_first_run = run_once || (!monitor_events && load_interval == 0 && !watch_config);
+    if exit_after_first_run {
+        process(Trigger::Startup, None)?;
+        notify_ready();
+    } else {
+        try_process(Trigger::Startup, None, &mut backoff, &mut retry_timer)?;
+    }
 
-    if run_once || (!monitor_events && load_interval == 0) {
-        // Either run-once is specified or both events are not monitored and rules aren't processed
-        // regularly -- process once, then exit.
+    if exit_after_first_run {
+        // Either run-once is specified or events are not monitored, rules aren't processed
+        // regularly and the configuration isn't watched -- process once, then exit.
         info!(root_logger,
               "Run once specified (or load-interval is zero and events aren't monitored), exiting";
               o!("version" => crate_version!(),
@@ -326,23 +841,31 @@ fn run<'a>(
         ::std::process::exit(0);
     }
 
-    let event_trigger = if monitor_events {
+    // Threads that have to keep running, the watchdog is not pinged anymore once one of them
+    // stopped beating. Without a watchdog, the heartbeats are not checked.
+    let watchdog_interval = systemd::watchdog_interval();
+    let heartbeat_interval = watchdog_interval.unwrap_or_else(|| Duration::from_secs(60));
+    let mut heartbeats = Vec::new();
+
+    let event_trigger = if let Some(events_docker) = events_docker {
         // Setup event monitoring
         trace!(root_logger, "Setup event monitoring channel";
                o!("monitor_events" => monitor_events));
 
         let (s_trigger, r_trigger) = channel::bounded(0);
         let (s_event, r_event) = channel::bounded(0);
-        let docker_url = matches.value_of("docker-url").map(|s| s.to_owned());
         let burst_timeout = value_t!(matches.value_of("burst-timeout"), u64)?;
 
         trace!(root_logger, "Start burst monitoring thread";
                o!("burst_timeout" => burst_timeout));
-        spawn_burst_monitor(burst_timeout, s_trigger, r_event, root_logger);
+        let heartbeat = Heartbeat::new(heartbeat_interval);
+        heartbeats.push(("burst_monitor", heartbeat.clone()));
+        spawn_burst_monitor(burst_timeout, s_trigger, r_event, heartbeat, root_logger);
 
-        trace!(root_logger, "Start event monitoring thread";
-               o!("docker_url" => &docker_url));
-        spawn_event_monitor(docker_url, s_event, root_logger);
+        trace!(root_logger, "Start event monitoring thread");
+        let heartbeat = Heartbeat::new(heartbeat_interval);
+        heartbeats.push(("event_monitor", heartbeat.clone()));
+        spawn_event_monitor(events_docker, engine, s_event, heartbeat, root_logger);
 
         // Note: we need both spawned threads for the entirety of the programs lifetime. As such we
         // do not bother cleaning them up, but rather let the OS handle the cleanup once we exit the
@@ -359,15 +882,120 @@ fn run<'a>(
         r_dummy
     };
 
+    let config_trigger = if watch_config {
+        let (s_config, r_config) = channel::bounded(1);
+        let watch_debounce = value_t!(matches.value_of("watch-debounce"), u64)?;
+
+        trace!(root_logger, "Start configuration watching thread";
+               o!("watch_debounce" => watch_debounce));
+        let heartbeat = Heartbeat::new(heartbeat_interval);
+        heartbeats.push(("config_watcher", heartbeat.clone()));
+        spawn_config_watcher(
+            config_source(matches)?,
+            watch_debounce,
+            s_config,
+            heartbeat,
+            root_logger,
+        )?;
+
+        r_config
+    } else {
+        trace!(root_logger, "Creating dummy channel";
+               o!("watch_config" => watch_config));
+        let (s_dummy, r_dummy) = channel::bounded(0);
+        // Leak the send-channel so that it never gets closed and `recv` never synchronizes.
+        ::std::mem::forget(s_dummy);
+
+        r_dummy
+    };
+
+    let watchdog = match watchdog_interval {
+        Some(watchdog_interval) => {
+            trace!(root_logger, "Creating watchdog tick channel";
+                   o!("watchdog_interval" => format!("{:?}", watchdog_interval)));
+            channel::tick(watchdog_interval)
+        }
+        None => {
+            trace!(
+                root_logger,
+                "Creating dummy channel, watchdog is not enabled"
+            );
+            let (s_dummy, r_dummy) = channel::bounded(0);
+            // Leak the send-channel so that it never gets closed and `recv` never synchronizes.
+            ::std::mem::forget(s_dummy);
+
+            r_dummy
+        }
+    };
+
     loop {
+        // The timer is replaced within the select, so it can't be borrowed by it.
+        let retry = retry_timer.clone();
         select! {
+            recv(retry) -> _ => {
+                info!(root_logger, "Retrying failed processing";
+                      o!("attempt" => backoff.attempts()));
+                try_process(Trigger::Retry, None, &mut backoff, &mut retry_timer)?;
+            },
+            recv(watchdog) -> _ => {
+                // Pinging from the main loop covers a hanging main loop. If one of the monitoring
+                // threads died or is stuck, systemd restarts DFW once the watchdog timeout is
+                // reached.
+                match heartbeats.iter().find(|heartbeat| !heartbeat.1.is_fresh()) {
+                    Some(&(thread, _)) => {
+                        error!(root_logger, "A monitoring thread stopped responding, not pinging \
+                                             the watchdog";
+                               o!("thread" => thread));
+                    }
+                    None => notify_systemd(systemd::WATCHDOG, root_logger),
+                }
+            },
+            recv(config_trigger) -> _ => {
+                info!(root_logger, "Configuration changed, validating it");
+                // An invalid configuration must not stop the daemon, the last good configuration
+                // stays in effect until the configuration is fixed.
+                match load_config(matches).and_then(|new_toml| {
+                    validate_config(&inventory, &new_toml, &processing_options, &processing_logger)
+                        .map(|_| new_toml)
+                }) {
+                    Ok(new_toml) => {
+                        info!(root_logger, "Configuration is valid, starting processing");
+                        let last_good_toml = toml.replace(new_toml);
+                        match process(Trigger::ConfigChange, None) {
+                            Ok(()) => notify_ready(),
+                            Err(e) => {
+                                error!(root_logger,
+                                       "Failed to apply configuration, restoring the last good one";
+                                       o!("error" => format!("{}", e)));
+                                toml.replace(last_good_toml);
+                                try_process(
+                                    Trigger::ConfigChange,
+                                    None,
+                                    &mut backoff,
+                                    &mut retry_timer,
+                                )?;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!(root_logger, "Invalid configuration, keeping the last good one";
+                               o!("error" => format!("{}", e)));
+                    }
+                }
+            },
             recv(load_interval_chan) -> _ => {
                 info!(root_logger, "Load interval ticked, starting processing");
-                process()?;
+                try_process(Trigger::Interval, None, &mut backoff, &mut retry_timer)?;
             },
-            recv(event_trigger) -> _ => {
-                info!(root_logger, "Received Docker events, starting processing");
-                process()?;
+            recv(event_trigger) -> affected_containers => {
+                info!(root_logger, "Received Docker events, starting processing";
+                      o!("affected_containers" => format!("{:?}", affected_containers)));
+                try_process(
+                    Trigger::DockerEvent,
+                    affected_containers.unwrap_or(None),
+                    &mut backoff,
+                    &mut retry_timer,
+                )?;
             },
             recv(r_signal) -> signal => {
                 match signal.expect("received an error instead of a signal") {
@@ -380,7 +1008,7 @@ fn run<'a>(
                     libc::SIGHUP => {
                         info!(root_logger, "Received HUP-signal, starting processing";
                               o!("signal" => format!("{:?}", signal)));
-                        process()?;
+                        try_process(Trigger::Sighup, None, &mut backoff, &mut retry_timer)?;
                     }
                     _ => { bail!("got unexpected signal '{:?}'", signal); }
                 }
@@ -388,6 +1016,7 @@ fn run<'a>(
         }
     }
 
+    notify_systemd(systemd::STOPPING, root_logger);
     info!(root_logger, "Application exiting";
           o!("version" => crate_version!(),
              "exited_at" => format!("{}", time::now().rfc3339())));
@@ -395,134 +1024,351 @@ fn run<'a>(
     Ok(())
 }
 
+fn config_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("config-file")
+            .takes_value(true)
+            .short("c")
+            .long("config-file")
+            .value_name("FILE")
+            .help("Set the configuration file"),
+    )
+    .arg(
+        Arg::with_name("config-path")
+            .takes_value(true)
+            .long("config-path")
+            .value_name("PATH")
+            .help("Set a path with multiple TOML, YAML or JSON configuration files"),
+    )
+    .group(
+        ArgGroup::with_name("config")
+            .args(&["config-file", "config-path"])
+            .multiple(false)
+            .required(true),
+    )
+}
+
 fn get_arg_matches<'a>() -> ArgMatches<'a> {
-    App::new("dfw")
-        .version(crate_version!())
-        .author(crate_authors!())
-        .about("Docker Firewall Framework, in Rust")
-        .arg(
-            Arg::with_name("log-level")
-                .takes_value(true)
-                .long("log-level")
-                .value_name("SEVERITY")
-                .possible_values(&["trace", "debug", "info", "warning", "error", "critical"])
-                .default_value("info")
-                .help("Define the log level"),
-        )
-        .arg(
-            Arg::with_name("config-file")
-                .takes_value(true)
-                .short("c")
-                .long("config-file")
-                .value_name("FILE")
-                .help("Set the configuration file"),
-        )
-        .arg(
-            Arg::with_name("config-path")
-                .takes_value(true)
-                .long("config-path")
-                .value_name("PATH")
-                .help("Set a path with multiple TOML configuration files"),
-        )
-        .group(
-            ArgGroup::with_name("config")
-                .args(&["config-file", "config-path"])
-                .multiple(false)
-                .required(true),
-        )
-        .arg(
-            Arg::with_name("docker-url")
-                .takes_value(true)
-                .short("d")
-                .long("docker-url")
-                .value_name("URL")
-                .help("Set the url to the Docker instance (e.g. unix:///tmp/docker.sock)"),
-        )
-        .arg(
-            Arg::with_name("load-interval")
-                .takes_value(true)
-                .default_value("0")
-                .short("i")
-                .long("load-interval")
-                .value_name("INTERVAL")
-                .help("Interval between rule processing runs, in seconds (0 = disabled)"),
-        )
-        .arg(
-            Arg::with_name("load-mode")
-                .takes_value(true)
-                .short("m")
-                .long("load-mode")
-                .value_name("MODE")
-                .possible_values(
-                    LoadMode::variants()
-                        .iter()
-                        .map(|s| s.to_ascii_lowercase())
-                        .collect::<Vec<_>>()
-                        .iter()
-                        .map(|s| &**s)
-                        .collect::<Vec<_>>()
-                        .as_slice(),
-                )
-                .default_value("once")
-                .help("Define if the config-files get loaded once, or before every run"),
-        )
-        .arg(
-            Arg::with_name("burst-timeout")
-                .takes_value(true)
-                .default_value("500")
-                .long("burst-timeout")
-                .value_name("TIMEOUT")
-                .help(
-                    "Time to wait after a event was received before processing the rules, in \
+    config_args(
+        App::new("dfw")
+            .version(crate_version!())
+            .author(crate_authors!())
+            .about("Docker Firewall Framework, in Rust")
+            .setting(AppSettings::SubcommandsNegateReqs),
+    )
+    .arg(
+        Arg::with_name("log-level")
+            .takes_value(true)
+            .long("log-level")
+            .value_name("SEVERITY")
+            .possible_values(&["trace", "debug", "info", "warning", "error", "critical"])
+            .default_value("info")
+            .help("Define the log level"),
+    )
+    .arg(
+        Arg::with_name("log-format")
+            .takes_value(true)
+            .long("log-format")
+            .value_name("FORMAT")
+            .possible_values(&["terminal", "json"])
+            .default_value("terminal")
+            .help("Define the format of the log records"),
+    )
+    .arg(
+        Arg::with_name("log-destination")
+            .takes_value(true)
+            .long("log-destination")
+            .value_name("DESTINATION")
+            .possible_values(&["stderr", "syslog", "file"])
+            .default_value("stderr")
+            .requires_if("file", "log-file")
+            .help("Define where to log to"),
+    )
+    .arg(
+        Arg::with_name("log-file")
+            .takes_value(true)
+            .long("log-file")
+            .value_name("FILE")
+            .help("Set the file to log to, if the log destination is `file`"),
+    )
+    .arg(
+        Arg::with_name("docker-url")
+            .takes_value(true)
+            .short("d")
+            .long("docker-url")
+            .value_name("URL")
+            .help(
+                "Set the url to the Docker instance (e.g. unix:///tmp/docker.sock), overrides \
+                 `DOCKER_HOST`",
+            ),
+    )
+    .arg(
+        Arg::with_name("engine")
+            .takes_value(true)
+            .default_value("auto")
+            .possible_values(&["auto", "docker", "podman"])
+            .long("engine")
+            .value_name("ENGINE")
+            .help("Set the container engine serving the Docker API, detected by default"),
+    )
+    .arg(
+        Arg::with_name("docker-tls-ca")
+            .takes_value(true)
+            .long("docker-tls-ca")
+            .value_name("FILE")
+            .requires_all(&["docker-tls-cert", "docker-tls-key"])
+            .help("Set the CA certificate to verify the Docker instance with"),
+    )
+    .arg(
+        Arg::with_name("docker-tls-cert")
+            .takes_value(true)
+            .long("docker-tls-cert")
+            .value_name("FILE")
+            .requires("docker-tls-key")
+            .help(
+                "Set the client certificate to authenticate to the Docker instance with, \
+                 overrides `DOCKER_CERT_PATH`",
+            ),
+    )
+    .arg(
+        Arg::with_name("docker-tls-key")
+            .takes_value(true)
+            .long("docker-tls-key")
+            .value_name("FILE")
+            .requires("docker-tls-cert")
+            .help("Set the key of the client certificate"),
+    )
+    .arg(
+        Arg::with_name("load-interval")
+            .takes_value(true)
+            .default_value("0")
+            .short("i")
+            .long("load-interval")
+            .value_name("INTERVAL")
+            .help("Interval between rule processing runs, in seconds (0 = disabled)"),
+    )
+    .arg(
+        Arg::with_name("load-mode")
+            .takes_value(true)
+            .short("m")
+            .long("load-mode")
+            .value_name("MODE")
+            .possible_values(
+                LoadMode::variants()
+                    .iter()
+                    .map(|s| s.to_ascii_lowercase())
+                    .collect::<Vec<_>>()
+                    .iter()
+                    .map(|s| &**s)
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            )
+            .default_value("once")
+            .help("Define if the config-files get loaded once, or before every run"),
+    )
+    .arg(
+        Arg::with_name("watch-config")
+            .takes_value(false)
+            .long("watch-config")
+            .help("Watch the configuration for changes, validate and apply it without restarting"),
+    )
+    .arg(
+        Arg::with_name("watch-debounce")
+            .takes_value(true)
+            .default_value("1000")
+            .long("watch-debounce")
+            .value_name("TIMEOUT")
+            .help(
+                "Time to wait after the configuration changed before reloading it, in \
+                 milliseconds",
+            ),
+    )
+    .arg(
+        Arg::with_name("burst-timeout")
+            .takes_value(true)
+            .default_value("500")
+            .long("burst-timeout")
+            .value_name("TIMEOUT")
+            .help(
+                "Time to wait after a event was received before processing the rules, in \
                      milliseconds",
-                ),
+            ),
+    )
+    .arg(
+        Arg::with_name("retry-initial-delay")
+            .takes_value(true)
+            .default_value("1")
+            .long("retry-initial-delay")
+            .value_name("DELAY")
+            .help("Time to wait before retrying a failed processing run, in seconds"),
+    )
+    .arg(
+        Arg::with_name("retry-max-delay")
+            .takes_value(true)
+            .default_value("300")
+            .long("retry-max-delay")
+            .value_name("DELAY")
+            .help(
+                "Maximum time to wait before retrying a failed processing run, the delay doubles \
+                 with every retry, in seconds",
+            ),
+    )
+    .arg(
+        Arg::with_name("retry-max-attempts")
+            .takes_value(true)
+            .default_value("10")
+            .long("retry-max-attempts")
+            .value_name("COUNT")
+            .help("Number of retries of a failed processing run (0 = don't retry)"),
+    )
+    .arg(
+        Arg::with_name("container-filter")
+            .takes_value(true)
+            .long("container-filter")
+            .value_name("FILTER")
+            .possible_values(&["all", "running"])
+            .default_value("running")
+            .help("Filter the containers to be included during processing"),
+    )
+    .arg(
+        Arg::with_name("strict")
+            .takes_value(false)
+            .long("strict")
+            .help("Abort processing if a rule fails, instead of skipping the rule"),
+    )
+    .arg(
+        Arg::with_name("disable-event-monitoring")
+            .takes_value(false)
+            .long("disable-event-monitoring")
+            .help("Disable Docker event monitoring"),
+    )
+    .arg(
+        Arg::with_name("run-once")
+            .takes_value(false)
+            .long("run-once")
+            .help("Process rules once, then exit."),
+    )
+    .arg(
+        Arg::with_name("iptables-backend")
+            .takes_value(true)
+            .long("iptables-backend")
+            .value_name("BACKEND")
+            .possible_values(
+                IPTablesBackend::variants()
+                    .iter()
+                    .map(|s| s.to_ascii_lowercase())
+                    .collect::<Vec<_>>()
+                    .iter()
+                    .map(|s| &**s)
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            )
+            .default_value("iptables")
+            .help("Choose the iptables backend to use"),
+    )
+    .arg(
+        Arg::with_name("audit-log")
+            .takes_value(true)
+            .long("audit-log")
+            .value_name("FILE")
+            .help("Record every applied ruleset in the given audit log"),
+    )
+    .arg(
+        Arg::with_name("audit-log-max-size")
+            .takes_value(true)
+            .default_value("10485760")
+            .long("audit-log-max-size")
+            .value_name("BYTES")
+            .help("Size after which the audit log is rotated, in bytes (0 = never rotate)"),
+    )
+    .arg(
+        Arg::with_name("audit-log-keep")
+            .takes_value(true)
+            .default_value("5")
+            .long("audit-log-keep")
+            .value_name("COUNT")
+            .help("Number of rotated audit logs to keep"),
+    )
+    .arg(
+        Arg::with_name("dry-run")
+            .takes_value(false)
+            .long("dry-run")
+            .help("Don't touch iptables, just show what would be done"),
+    )
+    .subcommand(
+        config_args(
+            SubCommand::with_name("render")
+                .about("Render the rules for an inventory snapshot without applying them"),
         )
         .arg(
-            Arg::with_name("container-filter")
+            Arg::with_name("inventory")
                 .takes_value(true)
-                .long("container-filter")
-                .value_name("FILTER")
-                .possible_values(&["all", "running"])
-                .default_value("running")
-                .help("Filter the containers to be included during processing"),
-        )
-        .arg(
-            Arg::with_name("disable-event-monitoring")
-                .takes_value(false)
-                .long("disable-event-monitoring")
-                .help("Disable Docker event monitoring"),
-        )
-        .arg(
-            Arg::with_name("run-once")
-                .takes_value(false)
-                .long("run-once")
-                .help("Process rules once, then exit."),
+                .required(true)
+                .long("inventory")
+                .value_name("FILE")
+                .help("Set the inventory snapshot, as created by `dfw inventory dump`"),
         )
         .arg(
-            Arg::with_name("iptables-backend")
+            Arg::with_name("format")
                 .takes_value(true)
-                .long("iptables-backend")
-                .value_name("BACKEND")
-                .possible_values(
-                    IPTablesBackend::variants()
-                        .iter()
-                        .map(|s| s.to_ascii_lowercase())
-                        .collect::<Vec<_>>()
-                        .iter()
-                        .map(|s| &**s)
-                        .collect::<Vec<_>>()
-                        .as_slice(),
-                )
-                .default_value("iptables")
-                .help("Choose the iptables backend to use"),
-        )
-        .arg(
-            Arg::with_name("dry-run")
-                .takes_value(false)
-                .long("dry-run")
-                .help("Don't touch iptables, just show what would be done"),
-        )
-        .get_matches()
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["iptables-restore", "json"])
+                .default_value("iptables-restore")
+                .help("Choose the output format of the rendered rules"),
+        ),
+    )
+    .subcommand(config_args(SubCommand::with_name("diff").about(
+        "Show how the generated rules differ from the installed rules, exiting with 2 if they do",
+    )))
+    .subcommand(
+        SubCommand::with_name("inventory")
+            .about("Manage inventory snapshots")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("dump")
+                    .about("Capture an inventory snapshot from the Docker instance")
+                    .arg(
+                        Arg::with_name("output")
+                            .takes_value(true)
+                            .short("o")
+                            .long("output")
+                            .value_name("FILE")
+                            .help("Write the snapshot to a file instead of stdout"),
+                    ),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("import-dfwfw")
+            .about("Convert a DFWFW configuration into a DFW configuration")
+            .arg(
+                Arg::with_name("dfwfw-file")
+                    .required(true)
+                    .value_name("FILE")
+                    .help("Set the DFWFW configuration file (JSON)"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .takes_value(true)
+                    .short("o")
+                    .long("output")
+                    .value_name("FILE")
+                    .help("Write the converted configuration to a file instead of stdout"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("schema")
+            .about("Print the JSON Schema of the configuration")
+            .arg(
+                Arg::with_name("output")
+                    .takes_value(true)
+                    .short("o")
+                    .long("output")
+                    .value_name("FILE")
+                    .help("Write the schema to a file instead of stdout"),
+            ),
+    )
+    .get_matches()
 }
 fn main() {
     // Parse arguments
@@ -541,15 +1387,43 @@ fn main() {
     // Setup logging
     let log_level =
         value_t!(matches.value_of("log-level"), Severity).expect("Unknown severity specified");
-    let root_logger = TerminalLoggerBuilder::new()
-        .format(sloggers::types::Format::Full)
-        .level(log_level)
-        .destination(Destination::Stderr)
-        .build()
-        .expect("Failed to setup logging");
-
-    if let Err(ref e) = run(&matches, &r_signal, &root_logger) {
-        error!(root_logger, "Encountered error";
-               o!("error" => format!("{}", e)));
+    let log_format =
+        value_t!(matches.value_of("log-format"), LogFormat).expect("Unknown log format specified");
+    let log_destination = match matches.value_of("log-destination") {
+        Some("syslog") => LogDestination::Syslog,
+        Some("file") => LogDestination::File(matches.value_of("log-file").unwrap().into()),
+        _ => LogDestination::Stderr,
+    };
+    let root_logger = match logging::build_logger(log_format, &log_destination, log_level) {
+        Ok(root_logger) => root_logger,
+        Err(e) => {
+            eprintln!("Failed to setup logging: {}", e);
+            ::std::process::exit(1);
+        }
+    };
+
+    let result = match matches.subcommand() {
+        ("render", Some(render_matches)) => render(&matches, render_matches, &root_logger),
+        ("diff", Some(diff_matches)) => match diff(&matches, diff_matches, &root_logger) {
+            Ok(true) => ::std::process::exit(2),
+            result => result.map(|_| ()),
+        },
+        ("schema", Some(schema_matches)) => print_schema(schema_matches, &root_logger),
+        ("import-dfwfw", Some(import_matches)) => import_dfwfw(import_matches, &root_logger),
+        ("inventory", Some(inventory_matches)) => match inventory_matches.subcommand() {
+            ("dump", Some(dump_matches)) => dump_inventory(&matches, dump_matches, &root_logger),
+            _ => unreachable!("clap requires an inventory subcommand"),
+        },
+        _ => run(&matches, &r_signal, &root_logger),
+    };
+
+    if let Err(ref e) = result {
+        match e.downcast_ref::<ConfigError>() {
+            // Configuration errors are meant to be read by whoever edits the configuration.
+            Some(config_error) => eprint!("{}", config_error.render()),
+            None => error!(root_logger, "Encountered error";
+                           o!("error" => format!("{}", e))),
+        }
+        ::std::process::exit(1);
     }
 }
@@ -0,0 +1,386 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module resolves the reusable definitions of a configuration.
+//!
+//! The `definitions` section of a configuration can define named groups of ports, addresses and
+//! interfaces, as well as aliases for networks and containers. Every definition can either be a
+//! single value or a list of values:
+//!
+//! ```toml
+//! [definitions.ports]
+//! web = ["80/tcp", "443/tcp"]
+//!
+//! [definitions.addresses]
+//! office = ["192.0.2.0/24", "198.51.100.0/24"]
+//!
+//! [definitions.interfaces]
+//! uplink = "eth0"
+//!
+//! [definitions.networks]
+//! frontend = "myproject_frontend"
+//!
+//! [definitions.containers]
+//! proxy = "myproject_traefik_1"
+//! ```
+//!
+//! A definition is referenced by setting a field to `"@<name>"`. The kind of the definition is
+//! given by the field:
+//!
+//! | Field                                                       | Kind         |
+//! |-------------------------------------------------------------|--------------|
+//! | `expose_port`                                               | `ports`      |
+//! | `external_network_interface`, `external_network_interfaces` | `interfaces` |
+//! | `network`, `src_network`, `dst_network`                     | `networks`   |
+//! | `src_container`, `dst_container`                            | `containers` |
+//!
+//! Fields accepting a list of values can also reference definitions within the list, the
+//! referenced values are inserted into the list:
+//!
+//! ```toml
+//! [[wider_world_to_container.rules]]
+//! network = "@frontend"
+//! dst_container = "@proxy"
+//! expose_port = ["@web", "8080/tcp"]
+//! external_network_interface = "@uplink"
+//! ```
+//!
+//! Definitions can also be referenced within any string using `@{<kind>.<name>}`. A list of
+//! values is joined with commas, which makes this useful to pass address groups to iptables:
+//!
+//! ```toml
+//! [[container_to_host.rules]]
+//! network = "@frontend"
+//! filter = "-s @{addresses.office}"
+//! action = "ACCEPT"
+//! ```
+//!
+//! The definitions are resolved when the configuration is loaded through
+//! [`util::load_file`](../util/fn.load_file.html) or [`util::load_path`](../util/fn.load_path.html),
+//! referencing an undefined name is an error.
+
+use errors::*;
+use serde_json::Value;
+use std::collections::HashMap as Map;
+
+/// Name of the configuration section holding the definitions.
+pub const DEFINITIONS_SECTION: &'static str = "definitions";
+
+const KINDS: &'static [&'static str] =
+    &["ports", "addresses", "interfaces", "networks", "containers"];
+
+type Definitions = Map<String, Map<String, Value>>;
+
+/// Resolve all references to definitions within the given configuration, removing the
+/// `definitions` section.
+pub fn resolve(config: &mut Value) -> Result<()> {
+    let definitions = match *config {
+        Value::Object(ref mut object) => object.remove(DEFINITIONS_SECTION),
+        _ => None,
+    };
+    let definitions = match definitions {
+        Some(definitions) => parse_definitions(definitions)?,
+        None => Definitions::new(),
+    };
+
+    resolve_value(config, "", None, &definitions)
+}
+
+fn parse_definitions(definitions: Value) -> Result<Definitions> {
+    let definitions = match definitions {
+        Value::Object(definitions) => definitions,
+        _ => bail!("section `{}` has to be a map", DEFINITIONS_SECTION),
+    };
+
+    let mut parsed = Definitions::new();
+    for (kind, values) in definitions {
+        if !KINDS.contains(&&*kind) {
+            bail!(
+                "unknown definition kind `{}`, expected one of: {}",
+                kind,
+                KINDS.join(", ")
+            );
+        }
+        let values = match values {
+            Value::Object(values) => values,
+            _ => bail!("definitions `{}` have to be a map", kind),
+        };
+        parsed.insert(kind, values.into_iter().collect());
+    }
+
+    Ok(parsed)
+}
+
+/// Kind of definition a field can reference, and whether the field accepts a list of values.
+type FieldKind = (&'static str, bool);
+
+/// Get the kind of definition the given field can reference.
+fn field_kind(field: &str) -> Option<FieldKind> {
+    match field {
+        "expose_port" => Some(("ports", true)),
+        "external_network_interfaces" => Some(("interfaces", true)),
+        "external_network_interface" => Some(("interfaces", false)),
+        "network" | "src_network" | "dst_network" => Some(("networks", false)),
+        "src_container" | "dst_container" => Some(("containers", false)),
+        _ => None,
+    }
+}
+
+fn lookup<'a>(
+    definitions: &'a Definitions,
+    kind: &str,
+    name: &str,
+    key_path: &str,
+) -> Result<&'a Value> {
+    definitions
+        .get(kind)
+        .and_then(|values| values.get(name))
+        .ok_or_else(|| format_err!("undefined {} `{}` referenced in `{}`", kind, name, key_path))
+}
+
+fn resolve_value(
+    value: &mut Value,
+    key_path: &str,
+    kind: Option<FieldKind>,
+    definitions: &Definitions,
+) -> Result<()> {
+    let resolved = match *value {
+        Value::Object(ref mut object) => {
+            for (key, value) in object.iter_mut() {
+                let key_path = if key_path.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{}.{}", key_path, key)
+                };
+                resolve_value(value, &key_path, field_kind(key), definitions)?;
+            }
+            None
+        }
+        Value::Array(ref mut array) => {
+            let mut resolved = Vec::with_capacity(array.len());
+            for (index, mut element) in array.drain(..).enumerate() {
+                let key_path = format!("{}[{}]", key_path, index);
+                match (kind, reference_name(&element)) {
+                    (Some((kind, true)), Some(name)) => {
+                        match lookup(definitions, kind, &name, &key_path)? {
+                            Value::Array(values) => resolved.extend(values.iter().cloned()),
+                            value => resolved.push(value.clone()),
+                        }
+                    }
+                    _ => {
+                        // Nested values, e.g. rules, are resolved according to their own fields.
+                        resolve_value(&mut element, &key_path, None, definitions)?;
+                        resolved.push(element);
+                    }
+                }
+            }
+            Some(Value::Array(resolved))
+        }
+        Value::String(ref string) => match (kind, reference_name(value)) {
+            (Some((kind, multiple)), Some(name)) => {
+                let resolved = lookup(definitions, kind, &name, key_path)?;
+                if !multiple && !resolved.is_string() {
+                    bail!(
+                        "{} `{}` referenced in `{}` has to be a single string",
+                        kind,
+                        name,
+                        key_path
+                    );
+                }
+                Some(resolved.clone())
+            }
+            _ => Some(Value::String(interpolate(string, key_path, definitions)?)),
+        },
+        _ => None,
+    };
+
+    if let Some(resolved) = resolved {
+        *value = resolved;
+    }
+
+    Ok(())
+}
+
+/// Get the name of the definition the value references, if it is a reference in the form
+/// `@<name>`.
+fn reference_name(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref string) if string.starts_with('@') && !string.starts_with("@{") => {
+            Some(string[1..].to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Replace all references in the form `@{<kind>.<name>}` within the string.
+fn interpolate(string: &str, key_path: &str, definitions: &Definitions) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = string;
+    while let Some(start) = rest.find("@{") {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("unterminated reference in `{}`: {}", key_path, string),
+        };
+
+        let reference = &rest[start + 2..end];
+        let mut split = reference.splitn(2, '.');
+        let (kind, name) = match (split.next(), split.next()) {
+            (Some(kind), Some(name)) if KINDS.contains(&kind) => (kind, name),
+            _ => bail!(
+                "invalid reference `@{{{}}}` in `{}`, expected `@{{<kind>.<name>}}` with kind one \
+                 of: {}",
+                reference,
+                key_path,
+                KINDS.join(", ")
+            ),
+        };
+        result.push_str(&join_values(
+            lookup(definitions, kind, name, key_path)?,
+            key_path,
+        )?);
+
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn join_values(value: &Value, key_path: &str) -> Result<String> {
+    match *value {
+        Value::String(ref string) => Ok(string.to_owned()),
+        Value::Number(ref number) => Ok(number.to_string()),
+        Value::Array(ref values) => Ok(values
+            .iter()
+            .map(|value| join_values(value, key_path))
+            .collect::<Result<Vec<_>>>()?
+            .join(",")),
+        _ => bail!(
+            "definition referenced in `{}` can't be inserted into a string",
+            key_path
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn resolve_str(config: &str) -> Result<Value> {
+        let mut config: Value = serde_json::from_str(config).unwrap();
+        resolve(&mut config)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn resolve_whole_value() {
+        let resolved = resolve_str(
+            r#"{
+                "definitions": {
+                    "networks": { "frontend": "project_frontend" },
+                    "ports": { "web": ["80/tcp", "443/tcp"] }
+                },
+                "wider_world_to_container": {
+                    "rules": [{
+                        "network": "@frontend",
+                        "expose_port": "@web"
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved,
+            serde_json::from_str::<Value>(
+                r#"{
+                    "wider_world_to_container": {
+                        "rules": [{
+                            "network": "project_frontend",
+                            "expose_port": ["80/tcp", "443/tcp"]
+                        }]
+                    }
+                }"#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn resolve_list_element() {
+        let resolved = resolve_str(
+            r#"{
+                "definitions": { "ports": { "web": ["80/tcp", "443/tcp"] } },
+                "expose_port": ["@web", 8080]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved["expose_port"],
+            json_value(r#"["80/tcp", "443/tcp", 8080]"#)
+        );
+    }
+
+    #[test]
+    fn resolve_inline() {
+        let resolved = resolve_str(
+            r#"{
+                "definitions": { "addresses": { "office": ["192.0.2.0/24", "198.51.100.0/24"] } },
+                "filter": "-s @{addresses.office} -p tcp"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved["filter"],
+            json_value(r#""-s 192.0.2.0/24,198.51.100.0/24 -p tcp""#)
+        );
+    }
+
+    #[test]
+    fn resolve_undefined() {
+        let error = resolve_str(r#"{ "rules": [{ "src_container": "@unknown" }] }"#)
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(
+            error,
+            "undefined containers `unknown` referenced in `rules[0].src_container`"
+        );
+    }
+
+    #[test]
+    fn resolve_single_value() {
+        let error = resolve_str(
+            r#"{
+                "definitions": { "networks": { "both": ["frontend", "backend"] } },
+                "rules": [{ "network": "@both" }]
+            }"#,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert_eq!(
+            error,
+            "networks `both` referenced in `rules[0].network` has to be a single string"
+        );
+    }
+
+    #[test]
+    fn resolve_unknown_kind() {
+        assert!(resolve_str(r#"{ "definitions": { "unknown": {} } }"#).is_err());
+        assert!(resolve_str(r#"{ "filter": "-s @{unknown.name}" }"#).is_err());
+    }
+
+    fn json_value(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+}
@@ -0,0 +1,452 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module converts [DFWFW][dfwfw] configurations into DFW configurations.
+//!
+//! DFWFW is configured through a single JSON-file, most of its sections have a direct equivalent
+//! in DFW:
+//!
+//! | DFWFW                        | DFW                                    |
+//! |------------------------------|----------------------------------------|
+//! | `external_network_interface` | `defaults.external_network_interfaces` |
+//! | `initialization`             | `initialization.v4`                    |
+//! | `container_to_container`     | `container_to_container`               |
+//! | `container_to_wider_world`   | `container_to_wider_world`             |
+//! | `container_to_host`          | `container_to_host`                    |
+//! | `wider_world_to_container`   | `wider_world_to_container`             |
+//! | `container_dnat`             | `container_dnat`                       |
+//!
+//! DFWFW selects networks and containers using expressions like `Name == web` or
+//! `Name =~ ^web$`, while DFW only accepts exact names. Expressions comparing the name for
+//! equality, and regular expressions matching a single name, are converted to that name. A
+//! selector consisting of a single name without any operators is taken as is.
+//!
+//! Everything that can't be converted is skipped and reported as a warning, most notably:
+//!
+//! * rules selecting networks or containers by regular expressions matching multiple names, or by
+//!   other fields than the name, or by any other kind of expression,
+//! * rules missing fields DFW requires,
+//! * the `container_internals` and `container_aliases` sections.
+//!
+//! [dfwfw]: https://github.com/irsl/dfwfw
+
+use errors::*;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map as JsonMap, Value};
+use std::collections::HashMap as Map;
+use toml;
+use types::*;
+
+/// The result of converting a DFWFW configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    /// The converted configuration.
+    pub dfw: DFW,
+    /// Descriptions of the parts of the DFWFW configuration that could not be converted.
+    pub warnings: Vec<String>,
+}
+
+impl Conversion {
+    /// Serialize the converted configuration to TOML, listing the warnings in a leading comment.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut result = String::new();
+        if !self.warnings.is_empty() {
+            result.push_str(
+                "# The following parts of the DFWFW configuration were not converted:\n#\n",
+            );
+            for warning in &self.warnings {
+                result.push_str(&format!("# * {}\n", warning));
+            }
+            result.push('\n');
+        }
+        result.push_str(&toml::to_string_pretty(&toml::Value::try_from(&self.dfw)?)?);
+
+        Ok(result)
+    }
+}
+
+/// Convert a DFWFW JSON configuration into a DFW configuration.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::dfwfw::convert;
+/// let conversion = convert(r#"{
+///     "container_to_container": {
+///         "default_policy": "DROP",
+///         "rules": [
+///             {
+///                 "network": "Name == backend",
+///                 "src_container": "Name =~ ^web$",
+///                 "dst_container": "Name =~ ^db-.*",
+///                 "action": "ACCEPT"
+///             }
+///         ]
+///     }
+/// }"#).unwrap();
+///
+/// // DFW can't match multiple containers by a regular expression, the rule is skipped.
+/// let container_to_container = conversion.dfw.container_to_container.unwrap();
+/// assert_eq!(container_to_container.default_policy, "DROP");
+/// assert_eq!(container_to_container.rules, Some(vec![]));
+/// assert_eq!(conversion.warnings.len(), 1);
+/// ```
+pub fn convert(json: &str) -> Result<Conversion> {
+    let dfwfw = match serde_json::from_str(json)? {
+        Value::Object(dfwfw) => dfwfw,
+        _ => bail!("DFWFW configuration is not a JSON object"),
+    };
+
+    let mut converter = Converter {
+        warnings: Vec::new(),
+    };
+    let dfw = converter.convert(dfwfw)?;
+
+    Ok(Conversion {
+        dfw: dfw,
+        warnings: converter.warnings,
+    })
+}
+
+/// Reason for skipping a part of the configuration.
+type Skip = String;
+
+/// Initialization rules keyed by the table.
+type InitializationRules = Map<String, Vec<String>>;
+
+struct Converter {
+    warnings: Vec<String>,
+}
+
+impl Converter {
+    fn warn(&mut self, key_path: &str, message: &str) {
+        self.warnings.push(format!("`{}`: {}", key_path, message));
+    }
+
+    fn convert(&mut self, dfwfw: JsonMap<String, Value>) -> Result<DFW> {
+        let mut dfw = JsonMap::new();
+        for (key, value) in dfwfw {
+            let converted = match &*key {
+                "external_network_interface" => match value {
+                    Value::String(interface) => Some((
+                        "defaults",
+                        json!({ "external_network_interfaces": [interface] }),
+                    )),
+                    _ => {
+                        self.warn(&key, "skipped, has to be a string");
+                        None
+                    }
+                },
+                "initialization" => self
+                    .convert_initialization(value)
+                    .map(|initialization| ("initialization", initialization)),
+                "container_to_container" => self
+                    .convert_section::<ContainerToContainer, ContainerToContainerRule>(
+                        &key,
+                        value,
+                        &[
+                            "network",
+                            "src_container",
+                            "dst_container",
+                            "filter",
+                            "action",
+                        ],
+                    )
+                    .map(|section| ("container_to_container", section)),
+                "container_to_wider_world" => self
+                    .convert_section::<ContainerToWiderWorld, ContainerToWiderWorldRule>(
+                        &key,
+                        value,
+                        &[
+                            "network",
+                            "src_container",
+                            "filter",
+                            "action",
+                            "external_network_interface",
+                        ],
+                    )
+                    .map(|section| ("container_to_wider_world", section)),
+                "container_to_host" => self
+                    .convert_section::<ContainerToHost, ContainerToHostRule>(
+                        &key,
+                        value,
+                        &["network", "src_container", "filter", "action"],
+                    )
+                    .map(|section| ("container_to_host", section)),
+                "wider_world_to_container" => self
+                    .convert_section::<WiderWorldToContainer, WiderWorldToContainerRule>(
+                        &key,
+                        value,
+                        &[
+                            "network",
+                            "dst_container",
+                            "expose_port",
+                            "external_network_interface",
+                        ],
+                    )
+                    .map(|section| ("wider_world_to_container", section)),
+                "container_dnat" => self
+                    .convert_section::<ContainerDNAT, ContainerDNATRule>(
+                        &key,
+                        value,
+                        &[
+                            "src_network",
+                            "src_container",
+                            "dst_network",
+                            "dst_container",
+                            "expose_port",
+                        ],
+                    )
+                    .map(|section| ("container_dnat", section)),
+                "container_internals" | "container_aliases" => {
+                    self.warn(&key, "skipped, DFW has no equivalent section");
+                    None
+                }
+                "log_path" | "docker_socket" => {
+                    self.warn(
+                        &key,
+                        "ignored, configure this through the command line of DFW",
+                    );
+                    None
+                }
+                _ => {
+                    self.warn(&key, "skipped, unknown section");
+                    None
+                }
+            };
+
+            if let Some((section, value)) = converted {
+                dfw.insert(section.to_owned(), value);
+            }
+        }
+
+        Ok(serde_json::from_value(Value::Object(dfw))?)
+    }
+
+    fn convert_initialization(&mut self, value: Value) -> Option<Value> {
+        // DFWFW only manages iptables, its initialization rules are keyed by the table.
+        match serde_json::from_value::<InitializationRules>(value) {
+            Ok(v4) => Some(json!({ "v4": v4 })),
+            Err(e) => {
+                self.warn("initialization", &format!("skipped, {}", e));
+                None
+            }
+        }
+    }
+
+    /// Convert a section consisting of a `default_policy` and `rules`, checking the result against
+    /// the section type `S` and the rule type `R`.
+    fn convert_section<S, R>(
+        &mut self,
+        key: &str,
+        value: Value,
+        rule_fields: &[&str],
+    ) -> Option<Value>
+    where
+        S: DeserializeOwned,
+        R: DeserializeOwned,
+    {
+        let mut section = match value {
+            Value::Object(section) => section,
+            _ => {
+                self.warn(key, "skipped, has to be an object");
+                return None;
+            }
+        };
+
+        for unknown in section
+            .keys()
+            .filter(|k| *k != "default_policy" && *k != "rules")
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            self.warn(&format!("{}.{}", key, unknown), "ignored, unknown field");
+            section.remove(&unknown);
+        }
+
+        if let Some(rules) = section.remove("rules") {
+            let rules = match rules {
+                Value::Array(rules) => rules,
+                _ => {
+                    self.warn(&format!("{}.rules", key), "skipped, has to be a list");
+                    Vec::new()
+                }
+            };
+            let mut converted = Vec::new();
+            for (index, rule) in rules.into_iter().enumerate() {
+                let key_path = format!("{}.rules[{}]", key, index);
+                match self.convert_rule::<R>(&key_path, rule, rule_fields) {
+                    Ok(rule) => converted.push(rule),
+                    Err(reason) => self.warn(&key_path, &format!("skipped, {}", reason)),
+                }
+            }
+            section.insert("rules".to_owned(), Value::Array(converted));
+        }
+
+        let section = Value::Object(section);
+        match serde_json::from_value::<S>(section.clone()) {
+            Ok(_) => Some(section),
+            Err(e) => {
+                self.warn(key, &format!("skipped, {}", e));
+                None
+            }
+        }
+    }
+
+    fn convert_rule<R>(
+        &mut self,
+        key_path: &str,
+        rule: Value,
+        rule_fields: &[&str],
+    ) -> ::std::result::Result<Value, Skip>
+    where
+        R: DeserializeOwned,
+    {
+        let mut rule = match rule {
+            Value::Object(rule) => rule,
+            _ => return Err("has to be an object".to_owned()),
+        };
+
+        for unknown in rule
+            .keys()
+            .filter(|k| !rule_fields.contains(&&***k))
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            self.warn(
+                &format!("{}.{}", key_path, unknown),
+                "ignored, unknown field",
+            );
+            rule.remove(&unknown);
+        }
+
+        for (key, value) in rule.iter_mut() {
+            let is_selector = key.ends_with("network") || key.ends_with("container");
+            if let (true, &mut Value::String(ref mut spec)) = (is_selector, value) {
+                *spec = convert_selector(key, spec)?;
+            }
+        }
+
+        let rule = Value::Object(rule);
+        serde_json::from_value::<R>(rule.clone()).map_err(|e| e.to_string())?;
+
+        Ok(rule)
+    }
+}
+
+/// Convert a DFWFW network or container selector, e.g. `Name =~ ^web$`, into a name.
+fn convert_selector(key: &str, spec: &str) -> ::std::result::Result<String, Skip> {
+    let spec = spec.trim();
+    if is_name(spec) {
+        return Ok(spec.to_owned());
+    }
+    let index = match spec.find("==").or_else(|| spec.find("=~")) {
+        Some(index) => index,
+        None => return Err(format!("`{}` has the unsupported selector `{}`", key, spec)),
+    };
+    let (field, operator, value) = (
+        spec[..index].trim(),
+        &spec[index..index + 2],
+        spec[index + 2..].trim(),
+    );
+
+    if field != "Name" {
+        return Err(format!(
+            "`{}` selects by `{}`, DFW can only select by name",
+            key, field
+        ));
+    }
+
+    let name = match operator {
+        "==" => Some(value.to_owned()),
+        _ => literal_regex(value),
+    };
+    match name {
+        Some(ref name) if is_name(name) => Ok(name.clone()),
+        Some(_) if operator == "==" => Err(format!(
+            "`{}` compares with `{}`, DFW can only select by a single name",
+            key, value
+        )),
+        _ => Err(format!(
+            "`{}` uses the regular expression `{}`, DFW can only match exact names",
+            key, value
+        )),
+    }
+}
+
+/// Check whether the value is a plain network or container name, i.e. a single token without
+/// whitespace or the operators of a selector expression.
+fn is_name(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
+
+/// Get the name a regular expression matches, if it matches exactly one name.
+fn literal_regex(regex: &str) -> Option<String> {
+    if !regex.starts_with('^') || !regex.ends_with('$') || regex.len() < 2 {
+        return None;
+    }
+
+    let mut name = String::new();
+    let mut chars = regex[1..regex.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped.is_ascii_punctuation() => name.push(escaped),
+                _ => return None,
+            },
+            '.' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$' => {
+                return None
+            }
+            c => name.push(c),
+        }
+    }
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selector_name() {
+        assert_eq!(
+            convert_selector("network", "backend"),
+            Ok("backend".to_owned())
+        );
+        assert_eq!(
+            convert_selector("network", "Name == backend"),
+            Ok("backend".to_owned())
+        );
+        assert_eq!(
+            convert_selector("src_container", "Name =~ ^web_1$"),
+            Ok("web_1".to_owned())
+        );
+        assert_eq!(
+            convert_selector("src_container", "Name =~ ^project\\.web$"),
+            Ok("project.web".to_owned())
+        );
+    }
+
+    #[test]
+    fn selector_unsupported() {
+        assert!(convert_selector("src_container", "Name =~ ^web").is_err());
+        assert!(convert_selector("src_container", "Name =~ ^web-.*$").is_err());
+        assert!(convert_selector("src_container", "Name =~ web").is_err());
+        assert!(convert_selector("src_container", "Id == 3f4e").is_err());
+        assert!(convert_selector("src_container", "Name != web").is_err());
+        assert!(convert_selector("src_container", "Name !~ ^web$").is_err());
+        assert!(convert_selector("src_container", "Label:role == web").is_err());
+        assert!(convert_selector("src_container", "Name == web && Id == 3f4e").is_err());
+        assert!(convert_selector("src_container", "Name =~ ^web 1$").is_err());
+        assert!(convert_selector("network", "backend frontend").is_err());
+    }
+}
@@ -0,0 +1,407 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module resolves how to connect to the Docker daemon.
+//!
+//! Like the Docker client, DFW honors the environment variables `DOCKER_HOST`,
+//! `DOCKER_TLS_VERIFY` and `DOCKER_CERT_PATH`, which can be overridden through
+//! [`DockerOptions`](struct.DockerOptions.html):
+//!
+//! * `DOCKER_HOST` is the URL of the daemon, `unix:///var/run/docker.sock` by default.
+//! * If `DOCKER_TLS_VERIFY` or `DOCKER_CERT_PATH` is set, the connection uses TLS, authenticating
+//!   with `cert.pem` and `key.pem` from `DOCKER_CERT_PATH` (`~/.docker` by default).
+//! * If `DOCKER_TLS_VERIFY` is set, the daemon is verified against `ca.pem` from
+//!   `DOCKER_CERT_PATH`.
+//!
+//! URLs with the `tcp` scheme are connected to over HTTPS if TLS is used, over HTTP otherwise.
+//!
+//! Besides Docker, DFW supports Podman through its Docker-compatible API, see
+//! [`Engine`](enum.Engine.html).
+
+use errors::*;
+use libc;
+use serde_json::{self, Value};
+use shiplift::Docker;
+use std::env;
+use std::ffi::{CString, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::symlink;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+/// URL of the Docker daemon if neither `DOCKER_HOST` nor a URL is given.
+pub const DEFAULT_HOST: &'static str = "unix:///var/run/docker.sock";
+
+/// Port of the Docker daemon for `tcp` URLs without a port, if TLS is used.
+const DEFAULT_TLS_PORT: u16 = 2376;
+
+/// Port of the Docker daemon for `tcp` URLs without a port, if TLS is not used.
+const DEFAULT_PORT: u16 = 2375;
+
+/// Serializes modifications of the environment while creating TLS clients.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Container engine serving the Docker API.
+///
+/// The engines differ in details DFW depends on, like the naming of the bridge interfaces and the
+/// statuses of the container events.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    /// The Docker daemon.
+    #[default]
+    Docker,
+    /// Podman, serving its Docker-compatible API.
+    Podman,
+}
+
+impl FromStr for Engine {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "docker" => Ok(Engine::Docker),
+            "podman" => Ok(Engine::Podman),
+            _ => bail!("unsupported engine '{}'", s),
+        }
+    }
+}
+
+impl Engine {
+    /// Detect the engine serving the API at the given connection, see
+    /// [`from_version_info`](#method.from_version_info).
+    ///
+    /// The version information of connections using TLS can only be read through the client,
+    /// which doesn't provide the components of the engine. The engine is determined by its
+    /// [version](#method.from_version) in that case.
+    pub fn detect(connection: &Connection, docker: &Docker) -> Result<Engine> {
+        Ok(match connection.tls {
+            Some(_) => Engine::from_version(&docker.version()?.Version),
+            None => Engine::from_version_info(&connection.version()?),
+        })
+    }
+
+    /// Determine the engine from the information returned by the `/version` endpoint of the API.
+    ///
+    /// Podman lists itself as the component `Podman Engine`. If the components are missing, e.g.
+    /// for Docker before 17.06, the engine is determined by its [version](#method.from_version).
+    pub fn from_version_info(version: &Value) -> Engine {
+        let components = match version["Components"].as_array() {
+            Some(components) if !components.is_empty() => components,
+            _ => return Engine::from_version(version["Version"].as_str().unwrap_or("")),
+        };
+        if components
+            .iter()
+            .any(|component| component["Name"] == "Podman Engine")
+        {
+            Engine::Podman
+        } else {
+            Engine::Docker
+        }
+    }
+
+    /// Determine the engine from the version it reports through the Docker API.
+    ///
+    /// Podman reports its own version, which can be told apart from the versions of Docker: these
+    /// are either `1.x` or, since Docker 17.03, based on the year of the release. Podman provides
+    /// the Docker-compatible API since version 2.
+    pub fn from_version(version: &str) -> Engine {
+        let major = version
+            .split('.')
+            .next()
+            .and_then(|major| major.parse::<u32>().ok());
+        match major {
+            Some(2..=16) => Engine::Podman,
+            _ => Engine::Docker,
+        }
+    }
+
+    /// Whether a container event with the given status changes the containers the rules are
+    /// generated for.
+    pub fn triggers_processing(self, status: &str) -> bool {
+        match self {
+            Engine::Docker => matches!(
+                status,
+                "create" | "destroy" | "start" | "restart" | "die" | "stop"
+            ),
+            // Podman reports removed containers as `remove` and, depending on the version,
+            // stopped processes as `died` instead of `die`.
+            Engine::Podman => matches!(
+                status,
+                "create" | "remove" | "start" | "restart" | "die" | "died" | "stop"
+            ),
+        }
+    }
+}
+
+/// Options overriding the environment variables of the Docker client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DockerOptions {
+    /// URL of the Docker daemon, overrides `DOCKER_HOST`.
+    pub url: Option<String>,
+    /// CA certificate to verify the daemon with.
+    pub tls_ca: Option<PathBuf>,
+    /// Client certificate to authenticate with.
+    pub tls_cert: Option<PathBuf>,
+    /// Private key of the client certificate.
+    pub tls_key: Option<PathBuf>,
+}
+
+/// TLS files used to connect to the Docker daemon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tls {
+    /// CA certificate to verify the daemon with. The daemon is not verified if this is `None`.
+    pub ca: Option<PathBuf>,
+    /// Client certificate to authenticate with.
+    pub cert: PathBuf,
+    /// Private key of the client certificate.
+    pub key: PathBuf,
+}
+
+/// Resolved connection to the Docker daemon, see
+/// [`DockerOptions::resolve`](struct.DockerOptions.html#method.resolve).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    /// URL of the Docker daemon, with the scheme `unix`, `http` or `https`.
+    pub url: String,
+    /// TLS files, if the connection uses TLS.
+    pub tls: Option<Tls>,
+}
+
+impl DockerOptions {
+    /// Resolve the connection according to the options and the environment of the process.
+    pub fn resolve(&self) -> Result<Connection> {
+        self.resolve_with(&|name| env::var(name).ok())
+    }
+
+    /// Resolve the connection according to the options and the environment variables returned by
+    /// `lookup`.
+    pub fn resolve_with<F>(&self, lookup: &F) -> Result<Connection>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let lookup = |name: &str| lookup(name).filter(|value| !value.is_empty());
+
+        let url = self
+            .url
+            .clone()
+            .or_else(|| lookup("DOCKER_HOST"))
+            .unwrap_or_else(|| DEFAULT_HOST.to_owned());
+        let parsed_url =
+            Url::parse(&url).map_err(|e| format_err!("invalid Docker URL '{}': {}", url, e))?;
+
+        let tls = if self.tls_ca.is_some() || self.tls_cert.is_some() || self.tls_key.is_some() {
+            Some(Tls {
+                ca: self.tls_ca.clone(),
+                cert: self
+                    .tls_cert
+                    .clone()
+                    .ok_or_else(|| format_err!("TLS requires a client certificate"))?,
+                key: self
+                    .tls_key
+                    .clone()
+                    .ok_or_else(|| format_err!("TLS requires the key of the client certificate"))?,
+            })
+        } else {
+            let verify = lookup("DOCKER_TLS_VERIFY").is_some();
+            match lookup("DOCKER_CERT_PATH") {
+                None if !verify => None,
+                cert_path => {
+                    let cert_path = cert_path
+                        .map(PathBuf::from)
+                        .or_else(|| lookup("HOME").map(|home| Path::new(&home).join(".docker")))
+                        .ok_or_else(|| {
+                            format_err!(
+                                "DOCKER_CERT_PATH is not set and the home directory is unknown"
+                            )
+                        })?;
+                    Some(Tls {
+                        ca: if verify {
+                            Some(cert_path.join("ca.pem"))
+                        } else {
+                            None
+                        },
+                        cert: cert_path.join("cert.pem"),
+                        key: cert_path.join("key.pem"),
+                    })
+                }
+            }
+        };
+
+        let scheme = match (parsed_url.scheme(), &tls) {
+            // TLS does not apply to Unix sockets.
+            ("unix", _) => {
+                return Ok(Connection {
+                    url: url,
+                    tls: None,
+                })
+            }
+            ("tcp", &Some(_)) | ("https", &Some(_)) => "https",
+            ("tcp", &None) | ("http", &None) => "http",
+            ("http", &Some(_)) => bail!("Docker URL '{}' uses HTTP, but TLS is configured", url),
+            ("https", &None) => bail!("Docker URL '{}' uses HTTPS, but TLS is not configured", url),
+            (scheme, _) => bail!("unsupported scheme '{}' of Docker URL '{}'", scheme, url),
+        };
+        let host = parsed_url
+            .host_str()
+            .ok_or_else(|| format_err!("Docker URL '{}' has no host", url))?;
+        let default_port = if tls.is_some() {
+            DEFAULT_TLS_PORT
+        } else {
+            DEFAULT_PORT
+        };
+        let url = format!(
+            "{}://{}:{}",
+            scheme,
+            host,
+            parsed_url.port().unwrap_or(default_port)
+        );
+
+        Ok(Connection { url: url, tls: tls })
+    }
+}
+
+impl Connection {
+    /// Get the information returned by the `/version` endpoint of the daemon.
+    ///
+    /// Contrary to the client, this includes all fields, e.g. the components of the engine.
+    /// Connections using TLS are not supported.
+    pub fn version(&self) -> Result<Value> {
+        let url = Url::parse(&self.url)?;
+        let timeout = Some(Duration::from_secs(30));
+        let response = match (url.scheme(), &self.tls) {
+            ("unix", _) => {
+                let stream = UnixStream::connect(url.path())?;
+                stream.set_read_timeout(timeout)?;
+                http_get(stream, "/version")?
+            }
+            ("http", &None) => {
+                let host = url
+                    .host_str()
+                    .ok_or_else(|| format_err!("Docker URL '{}' has no host", self.url))?;
+                let stream = TcpStream::connect((host, url.port().unwrap_or(DEFAULT_PORT)))?;
+                stream.set_read_timeout(timeout)?;
+                http_get(stream, "/version")?
+            }
+            _ => bail!(
+                "reading the version of the daemon at '{}' is not supported",
+                self.url
+            ),
+        };
+
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    /// Create a client connecting to the Docker daemon.
+    ///
+    /// This does not contact the daemon yet, but the TLS files are loaded.
+    ///
+    /// Creating a TLS client temporarily modifies the environment of the process, clients should
+    /// be created before spawning threads that might read the environment.
+    pub fn connect(&self) -> Result<Docker> {
+        let tls = match self.tls {
+            Some(ref tls) => tls,
+            None => return Ok(Docker::host(self.url.parse()?)),
+        };
+
+        // shiplift panics if it can't read the files, make sure it can.
+        let mut files = vec![&tls.cert, &tls.key];
+        files.extend(tls.ca.as_ref());
+        for file in files {
+            File::open(file)
+                .map_err(|e| format_err!("failed to read TLS file {}: {}", file.display(), e))?;
+        }
+
+        // shiplift reads `cert.pem`, `key.pem` and, if `DOCKER_TLS_VERIFY` is set, `ca.pem` from
+        // the directory in `DOCKER_CERT_PATH` when creating the client. The files are linked into
+        // a private temporary directory with these names for the time the client is created.
+        let _lock = ENV_LOCK
+            .lock()
+            .map_err(|_| format_err!("environment lock is poisoned"))?;
+        let cert_path = private_temp_dir("dfw-docker-tls")?;
+
+        let result = link_tls_files(tls, &cert_path).and_then(|()| {
+            let previous_cert_path = env::var_os("DOCKER_CERT_PATH");
+            let previous_tls_verify = env::var_os("DOCKER_TLS_VERIFY");
+            env::set_var("DOCKER_CERT_PATH", &cert_path);
+            if tls.ca.is_some() {
+                env::set_var("DOCKER_TLS_VERIFY", "1");
+            } else {
+                env::remove_var("DOCKER_TLS_VERIFY");
+            }
+
+            let docker = self.url.parse().map(Docker::host);
+
+            restore_var("DOCKER_CERT_PATH", previous_cert_path);
+            restore_var("DOCKER_TLS_VERIFY", previous_tls_verify);
+            Ok(docker?)
+        });
+        fs::remove_dir_all(&cert_path)?;
+
+        result
+    }
+}
+
+/// Request `path` from the daemon, returning the body of the response.
+fn http_get<S: Read + Write>(mut stream: S, path: &str) -> Result<String> {
+    // HTTP/1.0 makes the daemon close the connection after the response, without chunking the
+    // body.
+    write!(stream, "GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = match response.find("\r\n\r\n") {
+        Some(index) => (&response[..index], &response[index + 4..]),
+        None => bail!("invalid response to {} from the daemon", path),
+    };
+    let status = head.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!("request of {} failed: {}", path, status);
+    }
+
+    Ok(body.to_owned())
+}
+
+/// Create a directory only accessible by the current user, with an unpredictable name starting
+/// with `prefix`.
+fn private_temp_dir(prefix: &str) -> Result<PathBuf> {
+    let template = env::temp_dir().join(format!("{}-XXXXXX", prefix));
+    let mut template = CString::new(template.as_os_str().as_bytes())?.into_bytes_with_nul();
+    // `mkdtemp` replaces the `X`s in place and creates the directory with mode 0700.
+    let directory = unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if directory.is_null() {
+        return Err(io::Error::last_os_error().into());
+    }
+    template.pop();
+
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+fn link_tls_files(tls: &Tls, cert_path: &Path) -> Result<()> {
+    symlink(fs::canonicalize(&tls.cert)?, cert_path.join("cert.pem"))?;
+    symlink(fs::canonicalize(&tls.key)?, cert_path.join("key.pem"))?;
+    if let Some(ref ca) = tls.ca {
+        symlink(fs::canonicalize(ca)?, cert_path.join("ca.pem"))?;
+    }
+
+    Ok(())
+}
+
+fn restore_var(name: &str, value: Option<OsString>) {
+    match value {
+        Some(value) => env::set_var(name, value),
+        None => env::remove_var(name),
+    }
+}
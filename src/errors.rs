@@ -12,7 +12,9 @@
 
 #![allow(missing_docs)]
 
-use failure::Error;
+use failure::{Error, Fail};
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Fail)]
 pub enum DFWError {
@@ -20,4 +22,94 @@ pub enum DFWError {
     TraitMethodUnimplemented { method: String },
 }
 
+/// Error within a configuration, locating the offending value as precisely as possible.
+///
+/// The line and column are 1-based. The rule is the 0-based index of the rule within the
+/// section in the file, i.e. `0` is the first `[[<section>.rules]]` of the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigError {
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub section: Option<String>,
+    pub rule: Option<usize>,
+    pub key_path: Option<String>,
+    pub source_line: Option<String>,
+}
+
+impl ConfigError {
+    /// Render the error over multiple lines, including the offending line of the file if it is
+    /// known.
+    pub fn render(&self) -> String {
+        // The gutter is as wide as the line number shown in it.
+        let number = self.line.map(|line| line.to_string()).unwrap_or_default();
+        let padding = " ".repeat(number.len().max(1));
+
+        let mut rendered = format!("error: {}\n", self.message);
+        if let Some(ref file) = self.file {
+            rendered.push_str(&format!("{}--> {}\n", padding, self.location(file)));
+        }
+        if let Some(ref source_line) = self.source_line {
+            rendered.push_str(&format!("{} |\n", padding));
+            rendered.push_str(&format!("{} | {}\n", number, source_line));
+            if let Some(column) = self.column {
+                rendered.push_str(&format!(
+                    "{} | {}^\n",
+                    padding,
+                    " ".repeat(column.saturating_sub(1))
+                ));
+            }
+        }
+        if let Some(ref key_path) = self.key_path {
+            rendered.push_str(&format!("{} = in `{}`\n", padding, key_path));
+        }
+        rendered
+    }
+
+    fn location(&self, file: &Path) -> String {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", file.display(), line, column),
+            (Some(line), None) => format!("{}:{}", file.display(), line),
+            _ => file.display().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}: ", self.location(file))?;
+        }
+        if let Some(ref key_path) = self.key_path {
+            write!(f, "in `{}`: ", key_path)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Fail for ConfigError {}
+
+/// Error while processing a single rule of the configuration.
+///
+/// The rule is the 0-based index of the rule within the section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError {
+    pub section: String,
+    pub rule: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rule {} of section `{}`: {}",
+            self.rule, self.section, self.message
+        )
+    }
+}
+
+impl Fail for RuleError {}
+
 pub type Result<E> = ::std::result::Result<E, Error>;
@@ -0,0 +1,304 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module abstracts the source of the containers and networks the rules are created for.
+//!
+//! The [`Inventory`](trait.Inventory.html) trait is implemented by
+//! [`DockerInventory`](struct.DockerInventory.html), which queries a running Docker daemon, and
+//! by [`StaticInventory`](struct.StaticInventory.html), which holds all information in memory and
+//! can be (de)serialized from and to JSON. The latter allows rule processing to run without
+//! access to a Docker daemon.
+
+use docker::Engine;
+use errors::*;
+use process::ContainerFilter;
+use serde_json;
+use shiplift::builder::{ContainerFilter as ContainerFilterShiplift, ContainerListOptions};
+use shiplift::Docker;
+use std::collections::HashMap as Map;
+
+/// Source of the containers and networks, and of the network memberships of the containers.
+pub trait Inventory {
+    /// Get all containers matching the given filter.
+    fn containers(&self, filter: &ContainerFilter) -> Result<Vec<Container>>;
+
+    /// Get all networks.
+    fn networks(&self) -> Result<Vec<Network>>;
+
+    /// Get the endpoints of all containers attached to the network with the given ID, keyed by
+    /// container ID.
+    fn endpoints(&self, network_id: &str) -> Result<Map<String, Endpoint>>;
+
+    /// Get the endpoint of a single container within the network with the given ID, if the
+    /// container is attached to the network.
+    fn endpoint(&self, network_id: &str, container_id: &str) -> Result<Option<Endpoint>> {
+        Ok(self.endpoints(network_id)?.remove(container_id))
+    }
+
+    /// Get the container engine the containers and networks belong to.
+    fn engine(&self) -> Engine;
+}
+
+/// A container known to the inventory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    /// ID of the container.
+    pub id: String,
+    /// Names of the container. Docker prefixes these with a slash, e.g. `/container_name`.
+    pub names: Vec<String>,
+    /// Labels of the container.
+    #[serde(default)]
+    pub labels: Map<String, String>,
+    /// Whether the container is running.
+    #[serde(default = "default_running")]
+    pub running: bool,
+}
+
+/// A network known to the inventory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    /// ID of the network.
+    pub id: String,
+    /// Name of the network.
+    pub name: String,
+    /// Driver of the network, e.g. `bridge`.
+    #[serde(default)]
+    pub driver: String,
+    /// Driver options of the network, e.g. `com.docker.network.bridge.name`.
+    #[serde(default)]
+    pub options: Map<String, String>,
+    /// Labels of the network.
+    #[serde(default)]
+    pub labels: Map<String, String>,
+}
+
+/// The membership of a container in a network, holding the addresses of the container.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    /// IPv4 address of the container in CIDR notation, e.g. `172.17.0.2/16`.
+    pub ipv4_address: String,
+    /// IPv6 address of the container in CIDR notation, empty if IPv6 is not enabled.
+    #[serde(default)]
+    pub ipv6_address: String,
+}
+
+fn default_running() -> bool {
+    true
+}
+
+/// Inventory backed by a Docker daemon.
+///
+/// Every call is forwarded to the Docker API, no information is cached.
+pub struct DockerInventory<'a> {
+    docker: &'a Docker,
+    engine: Engine,
+}
+
+impl<'a> DockerInventory<'a> {
+    /// Create a new instance of `DockerInventory` querying the given Docker daemon.
+    pub fn new(docker: &'a Docker) -> DockerInventory<'a> {
+        DockerInventory {
+            docker: docker,
+            engine: Engine::Docker,
+        }
+    }
+
+    /// Set the engine serving the API, e.g. to query Podman instead of Docker, see
+    /// [`Engine::detect`](../docker/enum.Engine.html#method.detect).
+    pub fn with_engine(mut self, engine: Engine) -> DockerInventory<'a> {
+        self.engine = engine;
+        self
+    }
+}
+
+impl<'a> Inventory for DockerInventory<'a> {
+    fn containers(&self, filter: &ContainerFilter) -> Result<Vec<Container>> {
+        let container_list_options = match *filter {
+            ContainerFilter::All => Default::default(),
+            ContainerFilter::Running => ContainerListOptions::builder()
+                .filter(vec![ContainerFilterShiplift::Status("running".to_owned())])
+                .build(),
+        };
+
+        Ok(self
+            .docker
+            .containers()
+            .list(&container_list_options)?
+            .into_iter()
+            .map(|container| Container {
+                running: container.Status.starts_with("Up"),
+                id: container.Id,
+                names: container.Names,
+                labels: container.Labels,
+            })
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        Ok(self
+            .docker
+            .networks()
+            .list(&Default::default())?
+            .into_iter()
+            .map(|network| Network {
+                id: network.Id,
+                name: network.Name,
+                driver: network.Driver,
+                options: network.Options.unwrap_or_default(),
+                labels: network.Labels.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    fn endpoints(&self, network_id: &str) -> Result<Map<String, Endpoint>> {
+        // The network list doesn't include the attached containers, the network has to be
+        // inspected.
+        Ok(self
+            .docker
+            .networks()
+            .get(network_id)
+            .inspect()?
+            .Containers
+            .into_iter()
+            .map(|(container_id, details)| {
+                (
+                    container_id,
+                    Endpoint {
+                        ipv4_address: details.IPv4Address,
+                        ipv6_address: details.IPv6Address,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    fn engine(&self) -> Engine {
+        self.engine
+    }
+}
+
+/// Inventory holding all containers and networks in memory.
+///
+/// The inventory can be deserialized from JSON, for example:
+///
+/// ```
+/// # use dfw::inventory::*;
+/// # use dfw::ContainerFilter;
+/// let inventory = StaticInventory::from_json(r#"{
+///     "containers": [
+///         { "id": "3f4e...", "names": ["/web"] }
+///     ],
+///     "networks": [
+///         {
+///             "id": "0123456789abcdef",
+///             "name": "frontend",
+///             "driver": "bridge",
+///             "containers": {
+///                 "3f4e...": { "ipv4_address": "172.18.0.2/16" }
+///             }
+///         }
+///     ]
+/// }"#).unwrap();
+///
+/// assert_eq!(inventory.containers(&ContainerFilter::Running).unwrap().len(), 1);
+/// assert!(inventory.endpoint("0123456789abcdef", "3f4e...").unwrap().is_some());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StaticInventory {
+    /// The containers of the inventory.
+    #[serde(default)]
+    pub containers: Vec<Container>,
+    /// The networks of the inventory, including the endpoints of the attached containers.
+    #[serde(default)]
+    pub networks: Vec<StaticNetwork>,
+    /// The engine the inventory was captured from, `docker` by default.
+    #[serde(default)]
+    pub engine: Engine,
+}
+
+/// A network of a [`StaticInventory`](struct.StaticInventory.html).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StaticNetwork {
+    /// The network itself.
+    #[serde(flatten)]
+    pub network: Network,
+    /// The endpoints of the containers attached to the network, keyed by container ID.
+    #[serde(default)]
+    pub containers: Map<String, Endpoint>,
+}
+
+impl StaticInventory {
+    /// Deserialize a `StaticInventory` from a JSON string.
+    pub fn from_json(json: &str) -> Result<StaticInventory> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Serialize the `StaticInventory` into a pretty-printed JSON string.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Create a `StaticInventory` holding a snapshot of all containers and networks of another
+    /// inventory, e.g. of a [`DockerInventory`](struct.DockerInventory.html).
+    pub fn capture(inventory: &Inventory) -> Result<StaticInventory> {
+        let containers = inventory.containers(&ContainerFilter::All)?;
+        let networks = inventory
+            .networks()?
+            .into_iter()
+            .map(|network| {
+                Ok(StaticNetwork {
+                    containers: inventory.endpoints(&network.id)?,
+                    network: network,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(StaticInventory {
+            containers: containers,
+            networks: networks,
+            engine: inventory.engine(),
+        })
+    }
+}
+
+impl Inventory for StaticInventory {
+    fn containers(&self, filter: &ContainerFilter) -> Result<Vec<Container>> {
+        Ok(self
+            .containers
+            .iter()
+            .filter(|container| match *filter {
+                ContainerFilter::All => true,
+                ContainerFilter::Running => container.running,
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        Ok(self
+            .networks
+            .iter()
+            .map(|network| network.network.clone())
+            .collect())
+    }
+
+    fn endpoints(&self, network_id: &str) -> Result<Map<String, Endpoint>> {
+        match self
+            .networks
+            .iter()
+            .find(|network| network.network.id == network_id)
+        {
+            Some(network) => Ok(network.containers.clone()),
+            None => bail!("network not found: {}", network_id),
+        }
+    }
+
+    fn engine(&self) -> Engine {
+        self.engine
+    }
+}
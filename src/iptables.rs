@@ -11,16 +11,21 @@
 //! crate.
 //!
 //! [rust-iptables]: https://crates.io/crates/iptables
+//!
+//! The rules installed on the host can be read through [`save`](fn.save.html) and parsed into
+//! tables, chains and rules through [`IPTablesSave`](struct.IPTablesSave.html).
 
 use errors::*;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::convert::Into;
+use std::fmt;
 use std::io::BufWriter;
 use std::io::Write;
+use std::net::IpAddr;
 use std::os::unix::process::ExitStatusExt;
 use std::process::{Command, ExitStatus, Output, Stdio};
-use std::str;
+use std::str::{self, FromStr};
 
 macro_rules! proxy {
     ( $( #[$attr:meta] )* $name:ident ( $( $param:ident : $ty:ty ),* ) -> $ret:ty ) => {
@@ -155,6 +160,24 @@ macro_rules! loggers {
     };
 }
 
+macro_rules! recorder {
+    ( $( #[$attr:meta] )* $name:ident ( $( $param:ident : $ty:ty ),* ) -> $ret:ty ) => {
+        $( #[$attr] )*
+        fn $name(&self $(, $param: $ty )*) -> Result<$ret> {
+            let result = self.inner.$name($($param),*)?;
+            self.recorded.$name($($param),*)?;
+            Ok(result)
+        }
+    };
+}
+
+macro_rules! recorders {
+    ( $( $( #[$attr:meta] )*
+         $name:ident ( $( $param:ident : $ty:ty ),* ) -> $ret:ty );+ $(;)* ) => {
+        $( recorder!( $( #[$attr] )* $name ( $( $param : $ty ),* ) -> $ret ); )+
+    };
+}
+
 /// Enum identifying a IP protocol version. Can be used by `IPTables` implementations to discern
 /// between IPv4 rules and IPv6 rules.
 #[derive(Clone, Copy)]
@@ -166,6 +189,393 @@ pub enum IPVersion {
     IPv6,
 }
 
+/// Get the rules installed on the host, as printed by `iptables-save` (or `ip6tables-save` for
+/// IPv6).
+pub fn save(ip_version: IPVersion) -> Result<String> {
+    let cmd = match ip_version {
+        IPVersion::IPv4 => "iptables-save",
+        IPVersion::IPv6 => "ip6tables-save",
+    };
+
+    let output = Command::new(cmd)
+        .output()
+        .map_err(|e| format_err!("failed to execute {}: {}", cmd, e))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: '{}'",
+            cmd,
+            str::from_utf8(&output.stderr).unwrap_or("").trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Rules installed on the host, parsed from the output of `iptables-save` (or `ip6tables-save`).
+///
+/// The arguments of the rules are normalized, see [`normalize_rule`](fn.normalize_rule.html), so
+/// that rules can be compared independently of how they were written when they were added.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::iptables::IPTablesSave;
+/// let save: IPTablesSave = "*filter
+/// :INPUT ACCEPT [10:840]
+/// :DFWRS_INPUT - [0:0]
+/// -A INPUT -j DFWRS_INPUT
+/// -A DFWRS_INPUT -s 172.18.0.2 -p tcp -m tcp --dport 80 -j ACCEPT
+/// COMMIT"
+///     .parse()
+///     .unwrap();
+///
+/// let filter = save.table("filter").unwrap();
+/// assert_eq!(filter.chain("INPUT").unwrap().policy, Some("ACCEPT".to_owned()));
+/// assert_eq!(filter.chain("INPUT").unwrap().counters.packets, 10);
+/// assert_eq!(
+///     filter.rules_of("DFWRS_INPUT")[0].to_string(),
+///     "-A DFWRS_INPUT -s 172.18.0.2/32 -p tcp --dport 80 -j ACCEPT"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IPTablesSave {
+    /// The tables, in the order they were printed in.
+    pub tables: Vec<SavedTable>,
+}
+
+/// A table of [`IPTablesSave`](struct.IPTablesSave.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedTable {
+    /// Name of the table, e.g. `filter`.
+    pub name: String,
+    /// The chains of the table, in the order they were printed in.
+    pub chains: Vec<SavedChain>,
+    /// The rules of all chains of the table, in the order they were printed in.
+    pub rules: Vec<SavedRule>,
+}
+
+/// A chain of a [`SavedTable`](struct.SavedTable.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedChain {
+    /// Name of the chain.
+    pub name: String,
+    /// Policy of a builtin chain, e.g. `ACCEPT`, or `None` for user-defined chains.
+    pub policy: Option<String>,
+    /// Counters of the policy of the chain.
+    pub counters: Counters,
+}
+
+/// A rule of a [`SavedTable`](struct.SavedTable.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedRule {
+    /// Chain the rule is appended to.
+    pub chain: String,
+    /// Counters of the rule, if `iptables-save` was called with `-c`.
+    pub counters: Option<Counters>,
+    /// The normalized arguments of the rule following the chain, unquoted.
+    pub args: Vec<String>,
+}
+
+/// Packet and byte counters of a chain or rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Number of packets.
+    pub packets: u64,
+    /// Number of bytes.
+    pub bytes: u64,
+}
+
+impl IPTablesSave {
+    /// Parse the output of `iptables-save` or `ip6tables-save`.
+    pub fn parse(save: &str) -> Result<IPTablesSave> {
+        let mut tables: Vec<SavedTable> = Vec::new();
+        let mut in_table = false;
+        for (number, line) in save.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: &str| format_err!("line {} {}: '{}'", number + 1, message, line);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if let Some(name) = line.strip_prefix('*') {
+                if in_table {
+                    return Err(error("starts a table before the previous one is committed"));
+                }
+                in_table = true;
+                tables.push(SavedTable {
+                    name: name.to_owned(),
+                    ..SavedTable::default()
+                });
+                continue;
+            }
+
+            let table = match tables.last_mut() {
+                Some(table) if in_table => table,
+                _ => return Err(error("is outside of a table")),
+            };
+            if line == "COMMIT" {
+                in_table = false;
+            } else if let Some(chain) = line.strip_prefix(':') {
+                let mut parts = chain.split_whitespace();
+                let (name, policy, counters) = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(name), Some(policy), counters) => (name, policy, counters),
+                    _ => return Err(error("is not a valid chain")),
+                };
+                table.chains.push(SavedChain {
+                    name: name.to_owned(),
+                    policy: if policy == "-" {
+                        None
+                    } else {
+                        Some(policy.to_owned())
+                    },
+                    counters: match counters {
+                        Some(counters) => counters
+                            .parse()
+                            .map_err(|_| error("has invalid counters"))?,
+                        None => Counters::default(),
+                    },
+                });
+            } else {
+                // Rules are prefixed with their counters if `iptables-save` was called with `-c`.
+                let (counters, rule) = if line.starts_with('[') {
+                    let end = line
+                        .find(']')
+                        .ok_or_else(|| error("has invalid counters"))?;
+                    let counters = line[..=end]
+                        .parse()
+                        .map_err(|_| error("has invalid counters"))?;
+                    (Some(counters), line[end + 1..].trim_start())
+                } else {
+                    (None, line)
+                };
+                let rule = rule
+                    .strip_prefix("-A ")
+                    .ok_or_else(|| error("is not a valid rule"))?;
+                let mut parts = rule.trim_start().splitn(2, ' ');
+                table.rules.push(SavedRule {
+                    chain: parts.next().unwrap_or("").to_owned(),
+                    counters: counters,
+                    args: normalize_rule(parts.next().unwrap_or("")),
+                });
+            }
+        }
+        if in_table {
+            bail!("table `{}` is not committed", tables.last().unwrap().name);
+        }
+
+        Ok(IPTablesSave { tables: tables })
+    }
+
+    /// Get the table with the given name.
+    pub fn table(&self, name: &str) -> Option<&SavedTable> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+}
+
+impl FromStr for IPTablesSave {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        IPTablesSave::parse(s)
+    }
+}
+
+impl SavedTable {
+    /// Get the chain with the given name.
+    pub fn chain(&self, name: &str) -> Option<&SavedChain> {
+        self.chains.iter().find(|chain| chain.name == name)
+    }
+
+    /// Get the rules of the chain with the given name, in order.
+    pub fn rules_of(&self, chain: &str) -> Vec<&SavedRule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.chain == chain)
+            .collect()
+    }
+}
+
+impl SavedRule {
+    /// Create a rule from the arguments following the chain, normalizing them.
+    pub fn new(chain: &str, rule: &str) -> SavedRule {
+        SavedRule {
+            chain: chain.to_owned(),
+            counters: None,
+            args: normalize_rule(rule),
+        }
+    }
+
+    /// Get the target the rule jumps to, e.g. `ACCEPT` or a chain.
+    pub fn target(&self) -> Option<&str> {
+        let mut args = self.args.iter();
+        args.position(|arg| arg == "-j")
+            .and_then(|_| args.next())
+            .map(String::as_str)
+    }
+}
+
+/// Formats the rule as printed by `iptables-save`, without the counters, e.g.
+/// `-A DFWRS_INPUT -i docker0 -j ACCEPT`.
+impl fmt::Display for SavedRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "-A {}", self.chain)?;
+        for arg in &self.args {
+            write!(f, " {}", quote_arg(arg))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses counters formatted as `[<packets>:<bytes>]`.
+impl FromStr for Counters {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let counters = s
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .and_then(|s| {
+                let mut parts = s.splitn(2, ':');
+                match (parts.next()?.parse(), parts.next()?.parse()) {
+                    (Ok(packets), Ok(bytes)) => Some(Counters {
+                        packets: packets,
+                        bytes: bytes,
+                    }),
+                    _ => None,
+                }
+            });
+        counters.ok_or_else(|| format_err!("invalid counters '{}'", s))
+    }
+}
+
+/// Normalize the arguments of a rule to the form printed by `iptables-save`, returning them
+/// unquoted.
+///
+/// * Long options of the basic matches and the target are replaced by the short ones, e.g.
+///   `--source` by `-s`.
+/// * Addresses are given with their prefix length, e.g. `172.18.0.2/32`.
+/// * Protocols are lowercase, and their implicit matches (e.g. `-m tcp` for `-p tcp`) omitted.
+/// * Times of the `time` match include the seconds, e.g. `08:00:00`, and the defaults
+///   `iptables-save` may print (all weekdays, `--datestop 2038-01-19T03:14:07`) are omitted.
+/// * The target and its arguments follow the matches.
+pub fn normalize_rule(rule: &str) -> Vec<String> {
+    let mut matches: Vec<String> = Vec::new();
+    let mut target: Vec<String> = Vec::new();
+    let mut protocol = None;
+
+    let mut args = split_args(rule).into_iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-j" | "--jump" => {
+                target.push("-j".to_owned());
+                // The arguments of the target reach up to the next match.
+                while args
+                    .peek()
+                    .is_some_and(|arg| arg != "-m" && arg != "--match")
+                {
+                    target.push(args.next().unwrap());
+                }
+            }
+            "-s" | "--source" | "-d" | "--destination" => {
+                let short = if arg == "-s" || arg == "--source" {
+                    "-s"
+                } else {
+                    "-d"
+                };
+                matches.push(short.to_owned());
+                if let Some(address) = args.next() {
+                    matches.push(with_prefix_length(address));
+                }
+            }
+            "-p" | "--protocol" => {
+                matches.push("-p".to_owned());
+                if let Some(name) = args.next() {
+                    protocol = Some(name.to_lowercase());
+                    matches.push(name.to_lowercase());
+                }
+            }
+            "-m" | "--match" => {
+                if let Some(module) = args.next() {
+                    if protocol.as_ref() != Some(&module) {
+                        matches.push("-m".to_owned());
+                        matches.push(module);
+                    }
+                }
+            }
+            "--timestart" | "--timestop" => {
+                matches.push(arg);
+                if let Some(time) = args.next() {
+                    matches.push(if time.matches(':').count() == 1 {
+                        format!("{}:00", time)
+                    } else {
+                        time
+                    });
+                }
+            }
+            "--weekdays" if args.peek().is_some_and(|days| days.split(',').count() == 7) => {
+                args.next();
+            }
+            "--datestop" if args.peek().is_some_and(|date| date == DEFAULT_DATESTOP) => {
+                args.next();
+            }
+            "-i" | "--in-interface" => matches.push("-i".to_owned()),
+            "-o" | "--out-interface" => matches.push("-o".to_owned()),
+            _ => matches.push(arg),
+        }
+    }
+
+    matches.extend(target);
+    matches
+}
+
+/// The end date `iptables-save` prints for a `time` match without `--datestop`.
+const DEFAULT_DATESTOP: &'static str = "2038-01-19T03:14:07";
+
+fn with_prefix_length(address: String) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => format!("{}/32", address),
+        Ok(IpAddr::V6(_)) => format!("{}/128", address),
+        // Addresses with a prefix length or host names are kept as they are.
+        Err(_) => address,
+    }
+}
+
+/// Split the arguments like a shell would, honoring double quotes and backslash escapes.
+fn split_args(rule: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = rule.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+
+    args
+}
+
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return arg.to_owned();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 /// Compatibility trait to generalize the API used by [`rust-iptables`][rust-iptables].
 ///
 /// [rust-iptables]: https://crates.io/crates/iptables
@@ -445,6 +855,28 @@ impl IPTables for IPTablesRestore {
         Ok(true)
     }
 
+    fn insert_unique(&self, table: &str, chain: &str, rule: &str, position: i32) -> Result<bool> {
+        let appended_rule = format!("-A {} {}", chain, rule);
+        let inserted_rule = format!("-I {} {} {}", chain, position, rule);
+        let mut rules = self.rules.borrow_mut();
+        let (ref mut policies, ref mut rule_vec) = &mut rules
+            .entry(table.to_owned())
+            .or_insert_with(|| (BTreeMap::new(), Vec::new()));
+        let rule_exists = rule_vec.iter().any(|(chain_opt, value)| {
+            chain_opt.as_ref().map(String::as_str) == Some(chain)
+                && (value == &appended_rule || value == &inserted_rule)
+        });
+        if rule_exists {
+            return Ok(false);
+        }
+
+        // Set the default policy, if unset
+        set_default_policy(policies, chain);
+        rule_vec.push((Some(chain.to_owned()), inserted_rule));
+
+        Ok(true)
+    }
+
     fn list(&self, table: &str, chain: &str) -> Result<Vec<String>> {
         Ok(self
             .rules
@@ -531,15 +963,11 @@ impl IPTables for IPTablesRestore {
         ///
         /// Inserting at a specific position -- while technically supported by iptables-restore
         /// because the order of the rules read is honored -- is not required in the context of dfw.
-        /// None of the calls in `ProcessDFW` care about order, since no conflicting rules are
-        /// created.
+        /// The only call in `ProcessDFW` that cares about order, hooking the ingress chain of
+        /// Docker swarm into the `FORWARD` chain, uses
+        /// [`IPTablesRestore::insert_unique`](#method.insert_unique).
         insert(table: &str, chain: &str, rule: &str, position: i32) -> bool;
 
-        /// **METHOD UNSUPPORTED IN `IPTablesRestore`!**
-        ///
-        /// See [`IPTablesRestore::insert`](#method.insert).
-        insert_unique(table: &str, chain: &str, rule: &str, position: i32) -> bool;
-
         /// **METHOD UNSUPPORTED IN `IPTablesRestore`!**
         ///
         /// DFW does not require `append_unique`. Therefore no effort was made to replicate this
@@ -591,6 +1019,147 @@ impl IPTables for IPTablesRestore {
     }
 }
 
+/// [`IPTables`](trait.IPTables.html) implementation wrapping another implementation, keeping a
+/// copy of every change applied through it.
+///
+/// The copy is kept in the iptables-restore format, see
+/// [`IPTablesRecorder::take_rules`](#method.take_rules). Changes are only recorded if the wrapped
+/// implementation applied them successfully, and only kept once they were committed.
+pub struct IPTablesRecorder<'a> {
+    inner: &'a IPTables,
+    recorded: IPTablesRestore,
+    committed: RefCell<Vec<String>>,
+}
+
+impl<'a> IPTablesRecorder<'a> {
+    /// Create a new instance of `IPTablesRecorder`, wrapping the implementation for the given IP
+    /// version.
+    pub fn new(inner: &'a IPTables, ip_version: IPVersion) -> Result<IPTablesRecorder<'a>> {
+        Ok(IPTablesRecorder {
+            inner: inner,
+            recorded: IPTablesRestore::new(ip_version)?,
+            committed: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Retrieve the changes committed since the last call as a vector of lines in the
+    /// iptables-restore format, and start recording anew.
+    ///
+    /// Changes that have not been committed yet are discarded.
+    pub fn take_rules(&self) -> Vec<String> {
+        self.take_uncommitted();
+        self.committed.replace(Vec::new())
+    }
+
+    fn take_uncommitted(&self) -> Vec<String> {
+        if self.recorded.rules.borrow().is_empty() {
+            return Vec::new();
+        }
+        let rules = self.recorded.get_rules();
+        self.recorded.rules.replace(BTreeMap::new());
+
+        rules
+    }
+}
+
+impl<'a> IPTables for IPTablesRecorder<'a> {
+    recorders! {
+        set_policy(table: &str, chain: &str, policy: &str) -> bool;
+        execute(table: &str, command: &str) -> Output;
+        append(table: &str, chain: &str, rule: &str) -> bool;
+        append_replace(table: &str, chain: &str, rule: &str) -> bool;
+        delete(table: &str, chain: &str, rule: &str) -> bool;
+        new_chain(table: &str, chain: &str) -> bool;
+        flush_chain(table: &str, chain: &str) -> bool;
+        flush_table(table: &str) -> bool;
+    }
+
+    fn get_policy(&self, table: &str, chain: &str) -> Result<String> {
+        self.inner.get_policy(table, chain)
+    }
+
+    fn exists(&self, table: &str, chain: &str, rule: &str) -> Result<bool> {
+        self.inner.exists(table, chain, rule)
+    }
+
+    fn chain_exists(&self, table: &str, chain: &str) -> Result<bool> {
+        self.inner.chain_exists(table, chain)
+    }
+
+    // The following changes are not supported by `IPTablesRestore`, they are recorded as the
+    // iptables command that would apply them.
+
+    fn insert(&self, table: &str, chain: &str, rule: &str, position: i32) -> Result<bool> {
+        let result = self.inner.insert(table, chain, rule, position)?;
+        self.recorded
+            .execute(table, &format!("-I {} {} {}", chain, position, rule))?;
+        Ok(result)
+    }
+
+    fn insert_unique(&self, table: &str, chain: &str, rule: &str, position: i32) -> Result<bool> {
+        let result = self.inner.insert_unique(table, chain, rule, position)?;
+        if result {
+            self.recorded
+                .execute(table, &format!("-I {} {} {}", chain, position, rule))?;
+        }
+        Ok(result)
+    }
+
+    fn replace(&self, table: &str, chain: &str, rule: &str, position: i32) -> Result<bool> {
+        let result = self.inner.replace(table, chain, rule, position)?;
+        self.recorded
+            .execute(table, &format!("-R {} {} {}", chain, position, rule))?;
+        Ok(result)
+    }
+
+    fn append_unique(&self, table: &str, chain: &str, rule: &str) -> Result<bool> {
+        let result = self.inner.append_unique(table, chain, rule)?;
+        if result {
+            self.recorded.append(table, chain, rule)?;
+        }
+        Ok(result)
+    }
+
+    fn delete_all(&self, table: &str, chain: &str, rule: &str) -> Result<bool> {
+        let result = self.inner.delete_all(table, chain, rule)?;
+        self.recorded.delete(table, chain, rule)?;
+        Ok(result)
+    }
+
+    fn rename_chain(&self, table: &str, old_chain: &str, new_chain: &str) -> Result<bool> {
+        let result = self.inner.rename_chain(table, old_chain, new_chain)?;
+        self.recorded
+            .execute(table, &format!("-E {} {}", old_chain, new_chain))?;
+        Ok(result)
+    }
+
+    fn delete_chain(&self, table: &str, chain: &str) -> Result<bool> {
+        let result = self.inner.delete_chain(table, chain)?;
+        self.recorded.execute(table, &format!("-X {}", chain))?;
+        Ok(result)
+    }
+
+    fn list(&self, table: &str, chain: &str) -> Result<Vec<String>> {
+        self.inner.list(table, chain)
+    }
+
+    fn list_table(&self, table: &str) -> Result<Vec<String>> {
+        self.inner.list_table(table)
+    }
+
+    fn list_chains(&self, table: &str) -> Result<Vec<String>> {
+        self.inner.list_chains(table)
+    }
+
+    fn commit(&self) -> Result<bool> {
+        // The changes of a failed commit are discarded as well.
+        let rules = self.take_uncommitted();
+        let result = self.inner.commit()?;
+        self.committed.borrow_mut().extend(rules);
+        Ok(result)
+    }
+}
+
 fn set_default_policy(policies: &mut BTreeMap<Chain, Policy>, chain: &str) {
     policies
         .entry(chain.to_owned())
@@ -662,6 +1231,17 @@ mod tests_iptablesrestore {
             ":TEST_CHAIN - [0:0]",
             "-A TEST_CHAIN -s 10.0.0.1 -j ACCEPT",
         ]
+
+        double_insert_unique(ipt) {
+            ipt.append("filter", "TEST_CHAIN", "-s 10.0.0.2 -j DROP").unwrap();
+            ipt.insert_unique("filter", "TEST_CHAIN", "-s 10.0.0.1 -j ACCEPT", 1).unwrap();
+            ipt.insert_unique("filter", "TEST_CHAIN", "-s 10.0.0.1 -j ACCEPT", 1).unwrap();
+        } -> [
+            "*filter",
+            ":TEST_CHAIN - [0:0]",
+            "-A TEST_CHAIN -s 10.0.0.2 -j DROP",
+            "-I TEST_CHAIN 1 -s 10.0.0.1 -j ACCEPT",
+        ]
     }
 }
 
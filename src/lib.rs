@@ -54,6 +54,31 @@
 //! One category which DFWFW covers that is not (yet) implemented in DFW is
 //! `container_internals`, that is configuring iptables rules within containers.
 //!
+//! Values that are repeated across rules, like port lists, address ranges, interface names or
+//! network and container names, can be declared once in a `definitions` section and then be
+//! referenced from the rules. See the [definitions module][definitions.rs] for details.
+//!
+//! String values can reference environment variables as `${VAR}` or `${VAR:-default}`, the
+//! default being used if the variable is unset or empty. Referencing an unset variable without a
+//! default is an error. This allows configuring the Docker image of DFW through `-e`, for
+//! example `external_network_interfaces = ["${DFW_UPLINK:-eth0}"]`. Use `$${` for a literal `${`.
+//!
+//! Rules are generated section by section, in the order listed above, and in file order within a
+//! section. To move a rule ahead of rules of other sections, e.g. a specific `DROP` before a
+//! broader `ACCEPT`, give it a `priority`: within each chain, rules with a lower priority are
+//! generated first, rules without a priority have priority `0`. The default policies always follow
+//! the rules.
+//!
+//! Every rule can be restricted to a maintenance window with a `schedule`, e.g.
+//! `schedule = { days = "Mon-Fri", time = "22:00-02:00", timezone = "local" }`. Scheduled rules are
+//! rendered with the iptables `time` match; connections established while the rule was active are
+//! not cut off once the schedule ends. See [`Schedule`][schedule] for details.
+//!
+//! If a rule cannot be processed, e.g. because its schedule is invalid or the container has no IPv4
+//! address on the network, DFW logs the error, skips the rule and applies the remaining rules. A
+//! summary of the skipped rules is logged once processing finished. With `--strict` processing
+//! instead aborts at the first rule that fails, without committing any rules.
+//!
 //! See the [examples][examples] and [configuration types][types.rs] for a detailed description of
 //! every configuration section.
 //!
@@ -97,6 +122,170 @@
 //! This will download a lightweight image, coming in at under 6 MB, and subsequently run it using
 //! your configuration.
 //!
+//! ## Connecting to Docker
+//!
+//! DFW connects to the Docker instance the same way the Docker client does: the environment
+//! variables `DOCKER_HOST`, `DOCKER_TLS_VERIFY` and `DOCKER_CERT_PATH` are honored, and the
+//! instance is reached through `unix:///var/run/docker.sock` if `DOCKER_HOST` is not set. A remote
+//! instance protected by TLS can be configured through the environment or through the command line,
+//! which takes precedence:
+//!
+//! ```console
+//! $ dfw --config-path /config \
+//!       --docker-url tcp://docker.example.com:2376 \
+//!       --docker-tls-ca /etc/dfw/ca.pem \
+//!       --docker-tls-cert /etc/dfw/cert.pem \
+//!       --docker-tls-key /etc/dfw/key.pem
+//! ```
+//!
+//! The processing of the rules and the monitoring of the Docker events use the same connection.
+//!
+//! DFW also works with Podman through its Docker-compatible API, e.g. by pointing `--docker-url` to
+//! the Podman socket. The engine is detected on startup from the components it reports as its
+//! version, and can be set explicitly using `--engine docker` or `--engine podman`. With Podman,
+//! the bridge interfaces are named as reported by Podman (e.g. `podman1`) instead of after the
+//! network IDs, and the Podman event statuses (like `remove` and `died`) trigger the processing.
+//!
+//! ## Docker Swarm
+//!
+//! On swarm nodes, rules can reference the containers of swarm services on overlay networks. A
+//! service name given as `src_container` (or as `dst_container` of a `wider_world_to_container`
+//! rule) resolves to the tasks of the service running on the node. Overlay networks reach the host
+//! through the `docker_gwbridge` network, which is why the rules match the bridge and the addresses
+//! of the tasks within `docker_gwbridge`. Container-to-container rules on overlay networks are not
+//! supported, as this traffic does not pass the firewall of the host.
+//!
+//! Ports published through the routing mesh are exposed using `wider_world_to_container` rules on
+//! the `ingress` network, e.g.:
+//!
+//! ```toml
+//! [[wider_world_to_container.rules]]
+//! network = "ingress"
+//! dst_container = "web"
+//! expose_port = [80, 443]
+//! ```
+//!
+//! Any other traffic to the ingress sandbox, i.e. to published ports not exposed this way, is
+//! dropped.
+//!
+//! ## Rendering rules offline
+//!
+//! The rules DFW would apply can be rendered without access to the Docker instance, e.g. to review
+//! configuration changes in CI. Capture a snapshot of the containers and networks on the host, then
+//! render the rules for that snapshot in the `iptables-restore` format:
+//!
+//! ```console
+//! $ dfw inventory dump --output snapshot.json
+//! $ dfw render --config-path /config --inventory snapshot.json --format iptables-restore
+//! ```
+//!
+//! With `--format json` the rules are rendered as a [`Ruleset`][ruleset], the representation DFW
+//! generates before applying the rules. Every rule is described by its table, chain, matches and
+//! target, together with the configuration section (and index of the rule) it originates from.
+//!
+//! ## Comparing with the installed rules
+//!
+//! `dfw diff` generates the rules for the running containers and compares them with the rules
+//! installed on the host, as printed by `iptables-save` and `ip6tables-save`. Only the chains owned
+//! by DFW and the rules jumping into them are compared. Added and removed lines are printed per
+//! table, e.g.:
+//!
+//! ```console
+//! $ dfw diff --config-path /config
+//! # ipv4 filter
+//! - -A DFWRS_FORWARD -s 172.18.0.3/32 -i br-ba7fa850cf55 -o eth0 -j ACCEPT
+//! + -A DFWRS_FORWARD -s 172.18.0.2/32 -i br-ba7fa850cf55 -o eth0 -j ACCEPT
+//! ```
+//!
+//! The exit code is `0` if the rules are up to date and `2` if they differ.
+//!
+//! With the `iptables-restore` backend only the IPv4 rules are compared, as the backend doesn't
+//! commit the IPv6 rules yet.
+//!
+//! ## Validating configurations
+//!
+//! `dfw schema` prints a [JSON Schema][json-schema] of the configuration, which editors and CI can
+//! use to validate configurations before they are deployed:
+//!
+//! ```console
+//! $ dfw schema --output dfw.schema.json
+//! ```
+//!
+//! ## Reloading the configuration
+//!
+//! With `--watch-config`, DFW watches the configuration file or directory for changes. After a
+//! change (debounced by `--watch-debounce` milliseconds), the new configuration is loaded and its
+//! rules are generated without applying them. Only if this succeeds the configuration is applied,
+//! otherwise the error is logged and the last good configuration stays in effect.
+//!
+//! ## Retrying failed processing runs
+//!
+//! If applying the rules fails while DFW runs as a daemon, e.g. because the Docker API timed out
+//! or the xtables lock was held, DFW keeps running and retries the processing after
+//! `--retry-initial-delay` seconds (1 by default). The delay doubles with every retry up to
+//! `--retry-max-delay` seconds (300 by default). After `--retry-max-attempts` retries (10 by
+//! default) DFW logs an error and waits for the next event, tick or signal. Only errors within the
+//! configuration stop DFW.
+//!
+//! ## Logging
+//!
+//! By default DFW logs human readable records to stderr. With `--log-format json` every record is
+//! written as a single JSON object, with the key-value pairs DFW attaches to the record (e.g.
+//! `part`, `rule` or `network_name`) as separate fields. With `--log-destination syslog` the
+//! records are sent to the local syslog daemon, with `--log-destination file --log-file <FILE>`
+//! they are appended to the given file.
+//!
+//! ## Audit log
+//!
+//! With `--audit-log <FILE>` DFW records every ruleset it applied in an append-only audit log,
+//! one JSON object per line. Every record contains the time the rules were applied, what triggered
+//! the processing (`startup`, `docker_event`, `interval`, `sighup`, `config_change` or `retry`),
+//! the SHA-256 hash of the configuration in use and a unified diff of the generated rules against
+//! the previous record. The first record after DFW started contains the complete ruleset. Only
+//! committed rules are recorded, nothing is recorded with `--dry-run`. Failing to write the audit
+//! log is logged, but doesn't fail the processing.
+//!
+//! The audit log is rotated once it would exceed `--audit-log-max-size` bytes (10 MiB by default),
+//! keeping `--audit-log-keep` rotated logs (5 by default) named `<FILE>.1` to `<FILE>.<COUNT>`.
+//!
+//! ## Running as a systemd service
+//!
+//! DFW supports `Type=notify` services: it notifies systemd once the rules have been applied for
+//! the first time, and reports the outcome of every processing run as the status of the service. If
+//! the watchdog is enabled with `WatchdogSec=`, DFW pings it from its main loop, as long as the
+//! threads monitoring Docker events and the configuration are running and not stuck, e.g. on a
+//! stalled connection to Docker.
+//!
+//! ```ini
+//! [Unit]
+//! Description=Docker firewall framework
+//! After=docker.service
+//! Requires=docker.service
+//!
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/local/bin/dfw --config-file /etc/dfw/dfw.toml
+//! WatchdogSec=30
+//!
+//! [Install]
+//! WantedBy=multi-user.target
+//! ```
+//!
+//! ## Migrating from DFWFW
+//!
+//! Existing [DFWFW][dfwfw-github] configurations can be converted with `dfw import-dfwfw`:
+//!
+//! ```console
+//! $ dfw import-dfwfw dfwfw.conf --output dfw.toml
+//! ```
+//!
+//! The sections `container_to_container`, `container_to_wider_world`, `container_to_host`,
+//! `wider_world_to_container` and `container_dnat` are converted, as are the external network
+//! interface and the initialization rules. DFWFW selects containers and networks by expressions
+//! like `Name =~ ^web$`; only expressions matching an exact name can be converted. Rules and
+//! sections that cannot be converted are skipped, with a warning logged and listed at the top of
+//! the generated file. Review the result before using it.
+//!
 //! ## Motivation for this reimplementation
 //!
 //! I have reimplemented DFWFW in Rust for two reasons:
@@ -149,7 +338,11 @@
 //!
 //! [moby-issue-32686]: https://github.com/moby/moby/issues/32686
 //!
+//! [definitions.rs]: definitions/index.html
+//! [json-schema]: https://json-schema.org/
 //! [examples]: https://github.com/pitkley/dfw/tree/master/examples
+//! [ruleset]: ruleset/struct.Ruleset.html
+//! [schedule]: types/struct.Schedule.html
 //! [types.rs]: types/index.html
 
 // Increase the compiler's recursion limit for the `error_chain` crate.
@@ -160,23 +353,44 @@
 
 #[macro_use]
 extern crate derive_builder;
+extern crate diff;
 #[macro_use]
 extern crate failure;
 extern crate glob;
 extern crate iptables as ipt;
+extern crate libc;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate serde_path_to_error;
+extern crate serde_yaml;
+extern crate sha2;
 extern crate shiplift;
 #[macro_use]
 extern crate slog;
+extern crate slog_json;
+extern crate slog_term;
+extern crate sloggers;
 extern crate time;
 extern crate toml;
+extern crate url;
 
 // declare modules
+pub mod audit;
+pub mod definitions;
+pub mod dfwfw;
+pub mod docker;
 pub mod errors;
+pub mod inventory;
 pub mod iptables;
+pub mod logging;
 pub mod process;
+pub mod ruleset;
+pub mod schema;
+#[cfg(unix)]
+pub mod systemd;
 pub mod types;
 pub mod util;
 
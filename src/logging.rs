@@ -0,0 +1,246 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module builds the logger DFW logs to, according to `--log-format` and
+//! `--log-destination`.
+//!
+//! With the JSON format every record is written as a single JSON object, containing the timestamp
+//! (`ts`), the level (`level`) and the message (`msg`), as well as all key-value pairs attached to
+//! the record (e.g. `part`, `rule` or `network_name`) as separate fields.
+
+use errors::*;
+use slog::{Drain, Level, LevelFilter, Logger, OwnedKVList, Record};
+use slog_json::Json;
+use slog_term::{FullFormat, PlainSyncDecorator};
+use sloggers::file::FileLoggerBuilder;
+use sloggers::terminal::{Destination, TerminalLoggerBuilder};
+use sloggers::types::{Format, Severity};
+use sloggers::Build;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Socket of the local syslog daemon.
+pub const SYSLOG_SOCKET: &'static str = "/dev/log";
+
+/// Format of the log records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable, the key-value pairs follow the message.
+    Terminal,
+    /// One JSON object per record.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ::failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "terminal" => Ok(LogFormat::Terminal),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unsupported log format '{}'", s),
+        }
+    }
+}
+
+/// Destination of the log records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogDestination {
+    /// Standard error.
+    Stderr,
+    /// The local syslog daemon, see [`SYSLOG_SOCKET`](constant.SYSLOG_SOCKET.html).
+    Syslog,
+    /// The given file, records are appended.
+    File(PathBuf),
+}
+
+/// Build a logger writing records of at least the given severity to the destination.
+pub fn build_logger(
+    format: LogFormat,
+    destination: &LogDestination,
+    severity: Severity,
+) -> Result<Logger> {
+    Ok(match (format, destination) {
+        (LogFormat::Terminal, LogDestination::Stderr) => TerminalLoggerBuilder::new()
+            .format(Format::Full)
+            .level(severity)
+            .destination(Destination::Stderr)
+            .build()
+            .map_err(|e| format_err!("{}", e))?,
+        (LogFormat::Terminal, LogDestination::File(path)) => FileLoggerBuilder::new(path)
+            .format(Format::Full)
+            .level(severity)
+            .build()
+            .map_err(|e| format_err!("{}", e))?,
+        (LogFormat::Json, LogDestination::Stderr) => json_logger(io::stderr(), severity),
+        (LogFormat::Json, LogDestination::File(path)) => json_logger(
+            OpenOptions::new().create(true).append(true).open(path)?,
+            severity,
+        ),
+        (format, LogDestination::Syslog) => syslog_logger(SYSLOG_SOCKET, format, severity)?,
+    })
+}
+
+/// Build a logger sending records of at least the given severity to the syslog socket at `path`.
+///
+/// Records that cannot be sent, e.g. while the syslog daemon is restarting, are dropped.
+pub fn syslog_logger<P: AsRef<Path>>(
+    path: P,
+    format: LogFormat,
+    severity: Severity,
+) -> Result<Logger> {
+    let drain = Syslog::connect(path, format)?;
+    Ok(Logger::root(
+        LevelFilter::new(drain, severity.as_level()).ignore_res(),
+        o!(),
+    ))
+}
+
+fn json_logger<W>(writer: W, severity: Severity) -> Logger
+where
+    W: Write + Send + 'static,
+{
+    let drain = Mutex::new(Json::new(writer).add_default_keys().build());
+    // A record that cannot be written (e.g. on a full disk) is dropped instead of stopping DFW.
+    Logger::root(
+        LevelFilter::new(drain, severity.as_level()).ignore_res(),
+        o!(),
+    )
+}
+
+/// Drain sending every record as a datagram to a syslog socket, in the format understood by local
+/// syslog daemons (`<PRI>TAG: MESSAGE`, see RFC 3164). Records are logged with the `daemon`
+/// facility.
+///
+/// If sending a record fails, e.g. because the syslog daemon was restarted, the drain reconnects
+/// to the socket and retries once before returning the error.
+pub struct Syslog {
+    path: PathBuf,
+    socket: Mutex<UnixDatagram>,
+    formatter: Mutex<Box<Drain<Ok = (), Err = io::Error> + Send>>,
+    buffer: Buffer,
+    tag: String,
+}
+
+impl Syslog {
+    /// Connect to the syslog socket at `path`, formatting the messages according to `format`.
+    pub fn connect<P: AsRef<Path>>(path: P, format: LogFormat) -> Result<Syslog> {
+        let path = path.as_ref();
+        let socket = connect_socket(path).map_err(|e| {
+            format_err!(
+                "failed to connect to syslog socket {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+
+        let buffer = Buffer::default();
+        let formatter: Box<Drain<Ok = (), Err = io::Error> + Send> = match format {
+            // The syslog daemon adds its own timestamp.
+            LogFormat::Terminal => Box::new(
+                FullFormat::new(PlainSyncDecorator::new(buffer.clone()))
+                    .use_custom_timestamp(|_: &mut Write| Ok(()))
+                    .build(),
+            ),
+            LogFormat::Json => Box::new(Json::new(buffer.clone()).add_default_keys().build()),
+        };
+
+        Ok(Syslog {
+            path: path.to_owned(),
+            socket: Mutex::new(socket),
+            formatter: Mutex::new(formatter),
+            buffer: buffer,
+            tag: format!("dfw[{}]", process::id()),
+        })
+    }
+}
+
+impl Drain for Syslog {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        // The lock also makes sure the buffer only holds the message of this record.
+        let formatter = self
+            .formatter
+            .lock()
+            .map_err(|_| io::Error::other("syslog formatter is poisoned"))?;
+        formatter.log(record, values)?;
+
+        let message = self.buffer.take();
+        let datagram = format!(
+            "<{}>{}: {}",
+            syslog_priority(record.level()),
+            self.tag,
+            String::from_utf8_lossy(&message).trim()
+        );
+        let mut socket = self
+            .socket
+            .lock()
+            .map_err(|_| io::Error::other("syslog socket is poisoned"))?;
+        if socket.send(datagram.as_bytes()).is_err() {
+            *socket = connect_socket(&self.path)?;
+            socket.send(datagram.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn connect_socket(path: &Path) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+
+    Ok(socket)
+}
+
+/// Priority of a record with the `daemon` facility, according to RFC 3164.
+fn syslog_priority(level: Level) -> u8 {
+    const FACILITY_DAEMON: u8 = 3;
+    let severity = match level {
+        Level::Critical => 2,
+        Level::Error => 3,
+        Level::Warning => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+
+    FACILITY_DAEMON * 8 + severity
+}
+
+/// Buffer the formatters of the syslog drain write to, shared with the drain.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    fn take(&self) -> Vec<u8> {
+        match self.0.lock() {
+            Ok(mut buffer) => mem::take(&mut *buffer),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::other("log buffer is poisoned"))?
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -8,39 +8,70 @@
 
 //! This module holds the types related to configuration processing and rule creation.
 
+use docker::Engine;
 use errors::*;
+use inventory::{Container, Endpoint, Inventory, Network};
 use iptables::*;
-use shiplift::builder::{ContainerFilter as ContainerFilterShiplift, ContainerListOptions};
-use shiplift::rep::Container;
-use shiplift::rep::{NetworkContainerDetails, NetworkDetails};
-use shiplift::Docker;
+use ruleset::{
+    self, Chain, Command, Entry, Family, Interface, Matches, Placement, Provenance, Ruleset, Target,
+};
 use slog::Logger;
+use std::cell::RefCell;
 use std::collections::HashMap as Map;
+use std::collections::HashSet;
 use time;
 use types::*;
 
 const DFWRS_FORWARD_CHAIN: &'static str = "DFWRS_FORWARD";
+const DFWRS_INGRESS_CHAIN: &'static str = "DFWRS_INGRESS";
 const DFWRS_INPUT_CHAIN: &'static str = "DFWRS_INPUT";
 const DFWRS_POSTROUTING_CHAIN: &'static str = "DFWRS_POSTROUTING";
 const DFWRS_PREROUTING_CHAIN: &'static str = "DFWRS_PREROUTING";
 
+const BRIDGE_NAME_OPTION: &'static str = "com.docker.network.bridge.name";
+const OVERLAY_DRIVER: &'static str = "overlay";
+const SERVICE_NAME_LABEL: &'static str = "com.docker.swarm.service.name";
+
+/// Name of the swarm network implementing the routing mesh.
+const INGRESS_NETWORK: &'static str = "ingress";
+/// Name of the bridge network connecting the containers on overlay networks to the host.
+const GWBRIDGE_NETWORK: &'static str = "docker_gwbridge";
+/// Key of the endpoint of the ingress sandbox within the `docker_gwbridge` network.
+const INGRESS_SANDBOX: &'static str = "ingress-sbox";
+
 /// Enclosing struct to manage rule processing.
 pub struct ProcessDFW<'a> {
-    docker: &'a Docker,
+    inventory: &'a Inventory,
     dfw: &'a DFW,
     ipt4: &'a IPTables,
     ipt6: &'a IPTables,
     container_map: Map<String, Container>,
-    network_map: Map<String, NetworkDetails>,
+    network_map: Map<String, Network>,
+    /// Endpoints of the networks looked up so far, keyed by network ID and container ID.
+    ///
+    /// ## Note
+    ///
+    /// `RefCell` is required because the endpoints are looked up while the rules are generated.
+    endpoint_map: RefCell<Map<String, Map<String, Endpoint>>>,
+    engine: Engine,
+    ingress_sandbox: Option<String>,
     external_network_interfaces: Option<Vec<String>>,
     primary_external_network_interface: Option<String>,
+    cache: Option<&'a ProcessingCache>,
+    reusable_rules: Option<Map<RuleKey, Vec<CachedRule>>>,
+    affected_container_names: HashSet<String>,
+    generated_rules: RefCell<Map<RuleKey, Vec<CachedRule>>>,
+    pending_rules: RefCell<Vec<PendingRule>>,
+    entries: RefCell<Vec<Entry>>,
+    strict: bool,
+    rule_errors: RefCell<Vec<RuleError>>,
     logger: Logger,
 }
 
 impl<'a> ProcessDFW<'a> {
     /// Create a new instance of `ProcessDFW` for rule processing.
     pub fn new(
-        docker: &'a Docker,
+        inventory: &'a Inventory,
         dfw: &'a DFW,
         ipt4: &'a IPTables,
         ipt6: &'a IPTables,
@@ -49,13 +80,7 @@ impl<'a> ProcessDFW<'a> {
     ) -> Result<ProcessDFW<'a>> {
         let logger = logger.new(o!());
 
-        let container_list_options = match processing_options.container_filter {
-            ContainerFilter::All => Default::default(),
-            ContainerFilter::Running => ContainerListOptions::builder()
-                .filter(vec![ContainerFilterShiplift::Status("running".to_owned())])
-                .build(),
-        };
-        let containers = docker.containers().list(&container_list_options)?;
+        let containers = inventory.containers(&processing_options.container_filter)?;
         debug!(logger, "Got list of containers";
                o!("containers" => format!("{:#?}", containers)));
 
@@ -64,7 +89,7 @@ impl<'a> ProcessDFW<'a> {
         trace!(logger, "Got map of containers";
                o!("container_map" => format!("{:#?}", container_map)));
 
-        let networks = docker.networks().list(&Default::default())?;
+        let networks = inventory.networks()?;
         debug!(logger, "Got list of networks";
                o!("networks" => format!("{:#?}", networks)));
 
@@ -83,57 +108,181 @@ impl<'a> ProcessDFW<'a> {
             .and_then(|v| v.get(0))
             .map(|s| s.to_owned());
 
-        Ok(ProcessDFW {
-            docker: docker,
+        let mut process = ProcessDFW {
+            inventory: inventory,
             dfw: dfw,
             ipt4: ipt4,
             ipt6: ipt6,
             container_map: container_map,
             network_map: network_map,
+            endpoint_map: RefCell::new(Map::new()),
+            engine: inventory.engine(),
+            ingress_sandbox: None,
             external_network_interfaces: external_network_interfaces,
             primary_external_network_interface: primary_external_network_interface,
+            cache: None,
+            reusable_rules: None,
+            affected_container_names: HashSet::new(),
+            generated_rules: RefCell::new(Map::new()),
+            pending_rules: RefCell::new(Vec::new()),
+            entries: RefCell::new(Vec::new()),
+            strict: processing_options.strict,
+            rule_errors: RefCell::new(Vec::new()),
             logger: logger,
-        })
+        };
+
+        process.ingress_sandbox = process.get_ingress_sandbox()?;
+        trace!(process.logger, "Got ingress sandbox";
+               o!("ingress_sandbox" => format!("{:?}", process.ingress_sandbox)));
+
+        Ok(process)
+    }
+
+    /// Use the given cache to store the rules generated during processing, and to reuse the rules
+    /// of a previous run where possible.
+    ///
+    /// If `affected_containers` contains the IDs of the containers a Docker event was received
+    /// for, only the configuration rules referencing one of these containers are regenerated. All
+    /// other rules are taken from the cache, which avoids resolving them again. (Rules that only
+    /// reference networks do not have to be regenerated, because they don't depend on the
+    /// containers attached to the network.)
+    ///
+    /// Processing falls back to regenerating all rules if `affected_containers` is `None`, if the
+    /// cache is empty, if the configuration or the list of networks changed since the cached run,
+    /// or if any of the affected containers is unknown.
+    pub fn with_cache(
+        mut self,
+        cache: &'a ProcessingCache,
+        affected_containers: Option<Vec<String>>,
+    ) -> ProcessDFW<'a> {
+        self.cache = Some(cache);
+
+        let affected_containers = match affected_containers {
+            Some(affected_containers) => affected_containers,
+            None => {
+                debug!(
+                    self.logger,
+                    "No affected containers given, processing all rules"
+                );
+                return self;
+            }
+        };
+        let state = cache.state.borrow();
+        let state = match *state {
+            Some(ref state) => state,
+            None => {
+                debug!(self.logger, "Cache is empty, processing all rules");
+                return self;
+            }
+        };
+        if state.dfw != *self.dfw || state.network_ids != get_network_ids(&self.network_map) {
+            debug!(
+                self.logger,
+                "Configuration or networks changed, processing all rules"
+            );
+            return self;
+        }
+
+        let container_names = get_container_names(&self.container_map);
+        let mut affected_container_names = HashSet::new();
+        for container_id in &affected_containers {
+            let mut names = container_names
+                .get(container_id)
+                .cloned()
+                .unwrap_or_default();
+            if let Some(cached_names) = state.container_names.get(container_id) {
+                names.extend(cached_names.iter().cloned());
+            }
+
+            if names.is_empty() {
+                debug!(self.logger, "Affected container is unknown, processing all rules";
+                       o!("container_id" => container_id));
+                return self;
+            }
+            affected_container_names.extend(names);
+        }
+
+        debug!(self.logger, "Only processing rules referencing affected containers";
+               o!("affected_containers" => format!("{:?}", affected_container_names)));
+        self.reusable_rules = Some(state.rules.clone());
+        self.affected_container_names = affected_container_names;
+
+        self
     }
 
     /// Start the processing using the configuration given at creation.
     pub fn process(&self) -> Result<()> {
+        self.generate()?;
+
+        info!(self.logger, "Committing changes");
+        self.ipt4.commit()?;
+        // TODO: self.ipt6.commit()?;
+        info!(self.logger, "Successfully committed changes");
+
+        if let Some(cache) = self.cache {
+            trace!(self.logger, "Update processing cache");
+            cache.state.replace(Some(CacheState {
+                dfw: self.dfw.clone(),
+                network_ids: get_network_ids(&self.network_map),
+                container_names: get_container_names(&self.container_map),
+                rules: self.generated_rules.replace(Map::new()),
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Generate the rules using the configuration given at creation and apply them to the
+    /// backends, without committing them.
+    ///
+    /// This is useful in combination with the
+    /// [`IPTablesRestore`](../iptables/struct.IPTablesRestore.html) backend, which allows
+    /// retrieving the generated rules through
+    /// [`get_rules`](../iptables/struct.IPTablesRestore.html#method.get_rules).
+    pub fn generate(&self) -> Result<()> {
+        let ruleset = self.ruleset()?;
+
+        debug!(self.logger, "Apply ruleset";
+               o!("entries" => ruleset.entries.len()));
+        ruleset.apply(self.ipt4, self.ipt6, &self.logger)
+    }
+
+    /// Generate the rules using the configuration given at creation, without applying them to
+    /// the backends.
+    pub fn ruleset(&self) -> Result<Ruleset> {
         info!(self.logger, "Starting processing";
               o!("started_processing_at" => format!("{}", time::now().rfc3339())));
 
-        create_and_flush_chain("filter", DFWRS_FORWARD_CHAIN, self.ipt4, self.ipt6)?;
-        create_and_flush_chain("filter", DFWRS_INPUT_CHAIN, self.ipt4, self.ipt6)?;
-        create_and_flush_chain("nat", DFWRS_PREROUTING_CHAIN, self.ipt4, self.ipt6)?;
-        create_and_flush_chain("nat", DFWRS_POSTROUTING_CHAIN, self.ipt4, self.ipt6)?;
+        self.create_and_flush_chain("filter", DFWRS_FORWARD_CHAIN);
+        self.create_and_flush_chain("filter", DFWRS_INPUT_CHAIN);
+        self.create_and_flush_chain("nat", DFWRS_PREROUTING_CHAIN);
+        self.create_and_flush_chain("nat", DFWRS_POSTROUTING_CHAIN);
+        if self.ingress_sandbox.is_some() {
+            self.create_and_flush_chain("filter", DFWRS_INGRESS_CHAIN);
+        }
         debug!(self.logger, "Created and flushed chains");
 
         if let Some(ref init) = self.dfw.initialization {
             debug!(self.logger, "Starting sub-processing";
                    o!("part" => "initialization"));
-            self.process_initialization(init)?;
+            self.process_initialization(init);
         }
 
         // Setup input and forward chain
-        initialize_chain("filter", DFWRS_INPUT_CHAIN, self.ipt4, self.ipt6)?;
-        self.ipt4
-            .append_replace("filter", "INPUT", &format!("-j {}", DFWRS_INPUT_CHAIN))?;
-        initialize_chain("filter", DFWRS_FORWARD_CHAIN, self.ipt4, self.ipt6)?;
-        self.ipt4
-            .append_replace("filter", "FORWARD", &format!("-j {}", DFWRS_FORWARD_CHAIN))?;
+        self.initialize_chain("filter", DFWRS_INPUT_CHAIN);
+        self.add_rule(jump_rule("filter", "INPUT", DFWRS_INPUT_CHAIN));
+        self.initialize_chain("filter", DFWRS_FORWARD_CHAIN);
+        self.add_rule(jump_rule("filter", "FORWARD", DFWRS_FORWARD_CHAIN));
         // TODO: verify what is needed for ipt6
         debug!(self.logger, "Setup input and forward chains");
 
+        if let Some(ref ingress_sandbox) = self.ingress_sandbox {
+            self.setup_ingress_chain(ingress_sandbox)?;
+        }
+
         // Setup pre- and postrouting
-        self.ipt4.append_replace(
-            "nat",
-            "PREROUTING",
-            &format!("-j {}", DFWRS_PREROUTING_CHAIN),
-        )?;
-        self.ipt4.append_replace(
-            "nat",
-            "POSTROUTING",
-            &format!("-j {}", DFWRS_POSTROUTING_CHAIN),
-        )?;
+        self.add_rule(jump_rule("nat", "PREROUTING", DFWRS_PREROUTING_CHAIN));
+        self.add_rule(jump_rule("nat", "POSTROUTING", DFWRS_POSTROUTING_CHAIN));
         // TODO: verify what is needed for ipt6
         debug!(self.logger, "Setup pre- and postrouting");
 
@@ -163,41 +312,59 @@ impl<'a> ProcessDFW<'a> {
             self.process_container_dnat(cd)?;
         }
 
+        self.append_pending_rules();
+
+        // The default policies of the sections have to follow the rules of all sections
+        if let Some(ref ctww) = self.dfw.container_to_wider_world {
+            self.process_ctww_default_policy(ctww)?;
+        }
+        if let Some(ref cth) = self.dfw.container_to_host {
+            self.process_cth_default_policy(cth)?;
+        }
+        if self.ingress_sandbox.is_some() {
+            // Published ports of swarm services that are not exposed explicitly are dropped.
+            self.add_rule(ruleset::Rule {
+                family: Family::IPv4,
+                table: "filter".to_owned(),
+                chain: DFWRS_INGRESS_CHAIN.to_owned(),
+                placement: Placement::Append,
+                matches: Matches::default(),
+                target: Target::Drop,
+                provenance: Provenance::Setup,
+            });
+            // TODO: verify what is needed for ipt6
+        }
+
         if let Some(ref external_network_interfaces) = self.external_network_interfaces {
             for external_network_interface in external_network_interfaces {
                 // Add accept rules for Docker bridge
                 if let Some(bridge_network) = self.network_map.get("bridge") {
-                    if let Some(bridge_name) = bridge_network
-                        .Options
-                        .as_ref()
-                        .ok_or_else(|| format_err!("couldn't get container options"))?
-                        .get("com.docker.network.bridge.name")
-                    {
+                    if let Some(bridge_name) = bridge_network.options.get(BRIDGE_NAME_OPTION) {
                         debug!(self.logger, "Add ACCEPT rules for Docker bridge";
                                o!("docker_bridge" => bridge_name,
                                   "external_network_interface" => external_network_interface));
 
-                        let rule_str = Rule::default()
+                        let rule = Rule::default()
                             .in_interface(bridge_name)
                             .out_interface(external_network_interface)
                             .jump("ACCEPT")
-                            .build()?;
+                            .build("filter", DFWRS_FORWARD_CHAIN, Provenance::Setup)?;
 
                         trace!(self.logger, "Add forward rule for external network interface";
                                o!("external_network_interface" => external_network_interface,
-                                  "rule" => rule_str.to_owned()));
-                        self.ipt4.append("filter", DFWRS_FORWARD_CHAIN, &rule_str)?;
+                                  "rule" => rule.to_iptables()?));
+                        self.add_rule(rule);
                         // TODO: verify what is needed for ipt6
 
-                        let rule_str = Rule::default()
+                        let rule = Rule::default()
                             .in_interface(bridge_name)
                             .jump("ACCEPT")
-                            .build()?;
+                            .build("filter", DFWRS_INPUT_CHAIN, Provenance::Setup)?;
 
                         trace!(self.logger, "Add input rule for external network interface";
                                o!("external_network_interface" => external_network_interface,
-                                  "rule" => &rule_str));
-                        self.ipt4.append("filter", DFWRS_INPUT_CHAIN, &rule_str)?;
+                                  "rule" => rule.to_iptables()?));
+                        self.add_rule(rule);
                         // TODO: verify what is needed for ipt6
                     }
                 }
@@ -206,16 +373,15 @@ impl<'a> ProcessDFW<'a> {
                 debug!(self.logger, "Configure postrouting for external network interface";
                        o!("external_network_interface" => external_network_interface));
 
-                let rule_str = Rule::default()
+                let rule = Rule::default()
                     .out_interface(external_network_interface)
                     .jump("MASQUERADE")
-                    .build()?;
+                    .build("nat", DFWRS_POSTROUTING_CHAIN, Provenance::Setup)?;
 
                 trace!(self.logger, "Add post-routing rule for external network interface";
                        o!("external_network_interface" => external_network_interface,
-                          "rule" => &rule_str));
-                self.ipt4
-                    .append("nat", DFWRS_POSTROUTING_CHAIN, &rule_str)?;
+                          "rule" => rule.to_iptables()?));
+                self.add_rule(rule);
                 // TODO: verify what is needed for ipt6
             }
         }
@@ -226,24 +392,372 @@ impl<'a> ProcessDFW<'a> {
                    o!("part" => "container_to_container",
                       "default_policy" => &ctc.default_policy));
 
-            self.ipt4.append(
-                "filter",
-                DFWRS_FORWARD_CHAIN,
-                &format!("-j {}", ctc.default_policy),
-            )?;
+            self.add_rule(ruleset::Rule {
+                family: Family::IPv4,
+                table: "filter".to_owned(),
+                chain: DFWRS_FORWARD_CHAIN.to_owned(),
+                placement: Placement::Append,
+                matches: Matches::default(),
+                target: Target::from_action(&ctc.default_policy),
+                provenance: Provenance::DefaultPolicy {
+                    section: "container_to_container".to_owned(),
+                },
+            });
             // TODO: verify what is needed for ipt6
         }
 
+        let rule_errors = self.rule_errors.borrow();
+        if !rule_errors.is_empty() {
+            warn!(self.logger, "Skipped rules that failed to process";
+                  o!("failed_rules" => rule_errors.len(),
+                     "errors" => rule_errors
+                         .iter()
+                         .map(|e| e.to_string())
+                         .collect::<Vec<_>>()
+                         .join("; ")));
+        }
+
         info!(self.logger, "Finished processing";
               o!("finished_processing_at" => format!("{}", time::now().rfc3339())));
-        info!(self.logger, "Committing changes");
-        self.ipt4.commit()?;
-        // TODO: self.ipt6.commit()?;
-        info!(self.logger, "Successfully committed changes");
+
+        Ok(Ruleset {
+            entries: self.entries.replace(Vec::new()),
+        })
+    }
+
+    /// Get the errors of the configuration rules that failed to process and were skipped.
+    ///
+    /// This is always empty if [`ProcessingOptions::strict`](struct.ProcessingOptions.html) is
+    /// set, since processing stops at the first failing rule.
+    pub fn rule_errors(&self) -> Vec<RuleError> {
+        self.rule_errors.borrow().clone()
+    }
+
+    /// Process the configuration rule identified by `key` using `process_rule`.
+    ///
+    /// If processing the rule fails, the error is returned in strict mode. Otherwise the error is
+    /// collected and the rule is skipped, discarding the rules it generated up to the failure so
+    /// it is not applied partially.
+    fn isolate_rule<F>(&self, key: RuleKey, process_rule: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let pending_rules = self.pending_rules.borrow().len();
+        let error = match process_rule() {
+            Ok(()) => return Ok(()),
+            Err(e) => RuleError {
+                section: key.0.to_owned(),
+                rule: key.1,
+                message: e.to_string(),
+            },
+        };
+        if self.strict {
+            return Err(error.into());
+        }
+
+        warn!(self.logger, "Failed to process rule, skipping it";
+              o!("part" => key.0,
+                 "rule_index" => key.1,
+                 "error" => &error.message));
+        self.pending_rules.borrow_mut().truncate(pending_rules);
+        // Not caching the rule makes sure it is processed again during the next run.
+        self.generated_rules.borrow_mut().remove(&key);
+        self.rule_errors.borrow_mut().push(error);
+
+        Ok(())
+    }
+
+    /// Reuse the rules the previous run generated for the configuration rule identified by `key`,
+    /// provided none of the referenced `containers` was affected.
+    ///
+    /// Returns `true` if the cached rules were applied and the configuration rule does not have to
+    /// be processed again.
+    fn reuse_cached_rules(&self, key: RuleKey, containers: &[Option<&String>]) -> Result<bool> {
+        // Make sure configuration rules that don't generate any rule are cached, too.
+        self.generated_rules.borrow_mut().entry(key).or_default();
+
+        let cached_rules = match self.reusable_rules.as_ref().and_then(|r| r.get(&key)) {
+            Some(cached_rules) => cached_rules,
+            None => return Ok(false),
+        };
+        if containers
+            .iter()
+            .filter_map(|c| *c)
+            .any(|c| self.affected_container_names.contains(c))
+        {
+            return Ok(false);
+        }
+
+        trace!(self.logger, "Reuse cached rules";
+               o!("part" => key.0,
+                  "rule_index" => key.1));
+        for rule in cached_rules {
+            self.append_rule(key, rule.clone());
+        }
+
+        Ok(true)
+    }
+
+    /// Append the rule generated for the configuration rule identified by `key`, keeping track of
+    /// it for the processing cache.
+    ///
+    /// The rule is only applied by `append_pending_rules`, which orders the rules by priority.
+    fn append_rule(&self, key: RuleKey, rule: ruleset::Rule) {
+        self.generated_rules
+            .borrow_mut()
+            .entry(key)
+            .or_default()
+            .push(rule.clone());
+        self.pending_rules
+            .borrow_mut()
+            .push((self.rule_priority(key), rule));
+    }
+
+    /// Add the rules generated for the configuration rules to the ruleset, ordered by their
+    /// priority.
+    ///
+    /// The sort is stable, rules of the same priority keep the order they were generated in.
+    fn append_pending_rules(&self) {
+        let mut pending_rules = self.pending_rules.replace(Vec::new());
+        pending_rules.sort_by_key(|&(priority, _)| priority);
+
+        for (priority, rule) in pending_rules {
+            trace!(self.logger, "Add rule";
+                   o!("priority" => priority,
+                      "table" => &rule.table,
+                      "chain" => &rule.chain,
+                      "rule" => format!("{:?}", rule)));
+            self.add_rule(rule);
+        }
+    }
+
+    /// Add a rule to the ruleset.
+    fn add_rule(&self, rule: ruleset::Rule) {
+        self.entries.borrow_mut().push(Entry::Rule(Box::new(rule)));
+    }
+
+    /// Create and flush the chain for both IP families.
+    fn create_and_flush_chain(&self, table: &str, chain: &str) {
+        for &family in &[Family::IPv4, Family::IPv6] {
+            self.entries.borrow_mut().push(Entry::Chain(Chain {
+                family: family,
+                table: table.to_owned(),
+                name: chain.to_owned(),
+            }));
+        }
+    }
+
+    /// Drop invalid packets and accept the packets of established connections in the chain, for
+    /// both IP families.
+    fn initialize_chain(&self, table: &str, chain: &str) {
+        let states: [(&[&str], Target); 2] = [
+            (&["INVALID"], Target::Drop),
+            (&["RELATED", "ESTABLISHED"], Target::Accept),
+        ];
+        for (state, target) in &states {
+            for &family in &[Family::IPv4, Family::IPv6] {
+                self.add_rule(ruleset::Rule {
+                    family: family,
+                    table: table.to_owned(),
+                    chain: chain.to_owned(),
+                    placement: Placement::Append,
+                    matches: Matches {
+                        state: state.iter().map(|s| s.to_string()).collect(),
+                        ..Matches::default()
+                    },
+                    target: target.clone(),
+                    provenance: Provenance::Setup,
+                });
+            }
+        }
+    }
+
+    /// Get the priority of the configuration rule identified by `key`.
+    fn rule_priority(&self, key: RuleKey) -> i32 {
+        // The rules of all sections have a `priority`, but don't share a common type.
+        macro_rules! priority {
+            ( $dfw:expr, $key:expr, $( $section:ident ),+ ) => {
+                match $key.0 {
+                    $( stringify!($section) => $dfw
+                        .$section
+                        .as_ref()
+                        .and_then(|s| s.rules.as_ref())
+                        .and_then(|r| r.get($key.1))
+                        .and_then(|r| r.priority), )+
+                    _ => None,
+                }
+            };
+        }
+
+        priority!(
+            self.dfw,
+            key,
+            container_to_container,
+            container_to_wider_world,
+            container_to_host,
+            wider_world_to_container,
+            container_dnat
+        )
+        .unwrap_or(0)
+    }
+
+    /// Route the traffic of the external network interfaces to the swarm routing mesh through the
+    /// ingress chain.
+    ///
+    /// Docker accepts the traffic to the published ports of swarm services before it reaches the
+    /// forward chain of DFW, which is why the ingress chain has to be inserted in front of the
+    /// rules created by Docker.
+    fn setup_ingress_chain(&self, ingress_sandbox: &str) -> Result<()> {
+        self.initialize_chain("filter", DFWRS_INGRESS_CHAIN);
+
+        let gwbridge = self.bridge_name(&self.network_map[INGRESS_NETWORK])?;
+        for external_network_interface in self.external_network_interfaces.iter().flatten() {
+            let mut rule = Rule::default()
+                .in_interface(external_network_interface)
+                .out_interface(&gwbridge)
+                .destination(ingress_sandbox)
+                .jump(DFWRS_INGRESS_CHAIN)
+                .build("filter", "FORWARD", Provenance::Setup)?;
+            rule.placement = Placement::InsertUnique { position: 1 };
+            debug!(self.logger, "Route ingress traffic through ingress chain";
+                   o!("external_network_interface" => external_network_interface,
+                      "rule" => rule.to_iptables()?));
+            self.add_rule(rule);
+            // TODO: verify what is needed for ipt6
+        }
+
         Ok(())
     }
 
-    fn process_initialization(&self, init: &Initialization) -> Result<()> {
+    /// Get the name of the bridge connecting the containers of the network to the host.
+    ///
+    /// Containers on overlay networks are connected to the host through the `docker_gwbridge`
+    /// network, whose bridge is named after the network rather than its ID.
+    fn bridge_name(&self, network: &Network) -> Result<String> {
+        let network = if network.driver == OVERLAY_DRIVER {
+            self.network_map.get(GWBRIDGE_NETWORK).ok_or_else(|| {
+                format_err!(
+                    "overlay network `{}` requires the `{}` network",
+                    network.name,
+                    GWBRIDGE_NETWORK
+                )
+            })?
+        } else {
+            network
+        };
+        if network.name == GWBRIDGE_NETWORK {
+            return Ok(network
+                .options
+                .get(BRIDGE_NAME_OPTION)
+                .map(String::as_str)
+                .unwrap_or(GWBRIDGE_NETWORK)
+                .to_owned());
+        }
+
+        get_bridge_name(network, self.engine)
+    }
+
+    /// Get the endpoint through which the container is reachable from the host in the network, if
+    /// the container is attached to the network.
+    ///
+    /// The endpoint of a container attached to an overlay network is its endpoint in the
+    /// `docker_gwbridge` network.
+    fn endpoint(&self, network: &Network, container_name: &str) -> Result<Option<Endpoint>> {
+        let container_id = match self.container_map.get(container_name) {
+            Some(container) => &container.id,
+            None => return Ok(None),
+        };
+        let endpoint = self.network_endpoint(&network.id, container_id)?;
+        if network.driver != OVERLAY_DRIVER || endpoint.is_none() {
+            return Ok(endpoint);
+        }
+
+        match self.network_map.get(GWBRIDGE_NETWORK) {
+            Some(gwbridge) => self.network_endpoint(&gwbridge.id, container_id),
+            None => bail!(
+                "overlay network `{}` requires the `{}` network",
+                network.name,
+                GWBRIDGE_NETWORK
+            ),
+        }
+    }
+
+    /// Get the endpoint with the given key (usually the container ID) within the network.
+    ///
+    /// The endpoints of a network are looked up in the inventory once, when the first rule
+    /// referencing the network is generated. Networks no (regenerated) rule references are never
+    /// looked up, and a network that can't be looked up only fails the rules referencing it.
+    fn network_endpoint(&self, network_id: &str, key: &str) -> Result<Option<Endpoint>> {
+        if !self.endpoint_map.borrow().contains_key(network_id) {
+            let endpoints = self.inventory.endpoints(network_id)?;
+            trace!(self.logger, "Got endpoints of network";
+                   o!("network_id" => network_id,
+                      "endpoints" => format!("{:#?}", endpoints)));
+            self.endpoint_map
+                .borrow_mut()
+                .insert(network_id.to_owned(), endpoints);
+        }
+
+        Ok(self.endpoint_map.borrow()[network_id].get(key).cloned())
+    }
+
+    /// Get the IPv4 address of the ingress sandbox, through which the routing mesh of Docker swarm
+    /// receives the traffic to the published ports of services, if this node is part of a swarm.
+    fn get_ingress_sandbox(&self) -> Result<Option<String>> {
+        let ingress = self
+            .network_map
+            .get(INGRESS_NETWORK)
+            .filter(|network| network.driver == OVERLAY_DRIVER);
+        let gwbridge = match (ingress, self.network_map.get(GWBRIDGE_NETWORK)) {
+            (Some(_), Some(gwbridge)) => gwbridge,
+            _ => return Ok(None),
+        };
+
+        // The sandbox is not a container, Docker lists it under its name instead of an ID.
+        match self.network_endpoint(&gwbridge.id, INGRESS_SANDBOX)? {
+            Some(endpoint) => Ok(Some(
+                endpoint
+                    .ipv4_address
+                    .split('/')
+                    .next()
+                    .ok_or_else(|| format_err!("IPv4 address is empty"))?
+                    .to_owned(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Resolve a container referenced by a rule to the names of the containers to create rules
+    /// for.
+    ///
+    /// If no container with the given name exists, but the name is the name of a swarm service,
+    /// the reference resolves to the tasks of the service running on this node.
+    fn resolve_containers(&self, reference: &str) -> Vec<String> {
+        if self.container_map.contains_key(reference) {
+            return vec![reference.to_owned()];
+        }
+
+        let mut tasks = self
+            .container_map
+            .iter()
+            .filter(|(_, container)| {
+                container.labels.get(SERVICE_NAME_LABEL).map(String::as_str) == Some(reference)
+            })
+            // Containers are listed under each of their names, only use one name per task.
+            .map(|(name, container)| (&container.id, name))
+            .collect::<Map<_, _>>()
+            .into_values()
+            .cloned()
+            .collect::<Vec<_>>();
+        if tasks.is_empty() {
+            return vec![reference.to_owned()];
+        }
+        tasks.sort();
+
+        tasks
+    }
+
+    fn process_initialization(&self, init: &Initialization) {
         if let Some(ref v4) = init.v4 {
             debug!(self.logger, "Process initialization rules";
                    o!("ip" => "v4"));
@@ -256,14 +770,11 @@ impl<'a> ProcessDFW<'a> {
                            o!("ip" => "v4",
                               "table" => table,
                               "rule" => rule));
-                    let out = self.ipt4.execute(table, rule)?;
-                    trace!(self.logger, "Rule executed";
-                           o!("ip" => "v4",
-                              "table" => table,
-                              "rule" => rule,
-                              "status" => out.status.code(),
-                              "stdout" => String::from_utf8_lossy(&out.stdout).into_owned(),
-                              "stderr" => String::from_utf8_lossy(&out.stderr).into_owned()))
+                    self.entries.borrow_mut().push(Entry::Command(Command {
+                        family: Family::IPv4,
+                        table: table.to_owned(),
+                        command: rule.to_owned(),
+                    }));
                 }
             }
         }
@@ -280,19 +791,14 @@ impl<'a> ProcessDFW<'a> {
                            o!("ip" => "v4",
                               "table" => table,
                               "rule" => rule));
-                    let out = self.ipt6.execute(table, rule)?;
-                    trace!(self.logger, "Rule executed";
-                           o!("ip" => "v4",
-                              "table" => table,
-                              "rule" => rule,
-                              "status" => out.status.code(),
-                              "stdout" => String::from_utf8_lossy(&out.stdout).into_owned(),
-                              "stderr" => String::from_utf8_lossy(&out.stderr).into_owned()))
+                    self.entries.borrow_mut().push(Entry::Command(Command {
+                        family: Family::IPv6,
+                        table: table.to_owned(),
+                        command: rule.to_owned(),
+                    }));
                 }
             }
         }
-
-        Ok(())
     }
 
     fn process_container_to_container(&self, ctc: &ContainerToContainer) -> Result<()> {
@@ -306,105 +812,120 @@ impl<'a> ProcessDFW<'a> {
     }
 
     fn process_ctc_rules(&self, rules: &[ContainerToContainerRule]) -> Result<()> {
-        for rule in rules {
-            debug!(self.logger, "Process rule";
-                   o!("part" => "container_to_container",
-                      "rule" => format!("{:?}", rule)));
-            let mut ipt_rule = Rule::default();
+        for (index, rule) in rules.iter().enumerate() {
+            let key = ("container_to_container", index);
+            self.isolate_rule(key, || self.process_ctc_rule(key, rule))?;
+        }
 
-            let network = match self.network_map.get(&rule.network) {
-                Some(network) => network,
-                None => continue,
-            };
-            trace!(self.logger, "Got network";
-                   o!("network_name" => &rule.network,
-                      "network" => format!("{:?}", network)));
+        Ok(())
+    }
 
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(self.logger, "Got bridge name";
-                   o!("network_name" => &network.Name,
-                      "bridge_name" => &bridge_name));
+    fn process_ctc_rule(&self, key: RuleKey, rule: &ContainerToContainerRule) -> Result<()> {
+        if self.reuse_cached_rules(
+            key,
+            &[rule.src_container.as_ref(), rule.dst_container.as_ref()],
+        )? {
+            return Ok(());
+        }
 
-            ipt_rule
-                .in_interface(&bridge_name)
-                .out_interface(&bridge_name);
-
-            if let Some(ref src_container) = rule.src_container {
-                let src_network = match get_network_for_container(
-                    self.docker,
-                    &self.container_map,
-                    src_container,
-                    &network.Id,
-                )? {
-                    Some(src_network) => src_network,
-                    None => continue,
-                };
-                trace!(self.logger, "Got source network";
-                       o!("network_name" => &network.Name,
-                          "src_network" => format!("{:?}", src_network)));
+        debug!(self.logger, "Process rule";
+               o!("part" => "container_to_container",
+                  "rule" => format!("{:?}", rule)));
+        let mut ipt_rule = Rule::default();
 
-                let bridge_name = get_bridge_name(&network.Id)?;
-                trace!(self.logger, "Got bridge name";
-                       o!("network_name" => &network.Name,
-                          "bridge_name" => &bridge_name));
+        let network = match self.network_map.get(&rule.network) {
+            Some(network) => network,
+            None => return Ok(()),
+        };
+        trace!(self.logger, "Got network";
+               o!("network_name" => &rule.network,
+                  "network" => format!("{:?}", network)));
+        if network.driver == OVERLAY_DRIVER {
+            bail!(
+                "container-to-container rules are not supported on overlay network `{}`, its \
+                 traffic does not pass the firewall of the host",
+                network.name
+            );
+        }
 
-                ipt_rule
-                    .in_interface(&bridge_name)
-                    .out_interface(&bridge_name)
-                    .source(
-                        src_network
-                            .IPv4Address
-                            .split('/')
-                            .next()
-                            .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    );
-            }
+        let bridge_name = self.bridge_name(network)?;
+        trace!(self.logger, "Got bridge name";
+               o!("network_name" => &network.name,
+                  "bridge_name" => &bridge_name));
 
-            if let Some(ref dst_container) = rule.dst_container {
-                let dst_network = match get_network_for_container(
-                    self.docker,
-                    &self.container_map,
-                    dst_container,
-                    &network.Id,
-                )? {
-                    Some(dst_network) => dst_network,
-                    None => continue,
-                };
-                trace!(self.logger, "Got destination network";
-                       o!("network_name" => &network.Name,
-                          "dst_network" => format!("{:?}", dst_network)));
+        ipt_rule
+            .in_interface(&bridge_name)
+            .out_interface(&bridge_name);
 
-                let bridge_name = get_bridge_name(&network.Id)?;
-                trace!(self.logger, "Got bridge name";
-                       o!("network_name" => &network.Name,
-                          "bridge_name" => &bridge_name));
+        if let Some(ref src_container) = rule.src_container {
+            let src_network = match self.endpoint(network, src_container)? {
+                Some(src_network) => src_network,
+                None => return Ok(()),
+            };
+            trace!(self.logger, "Got source network";
+                   o!("network_name" => &network.name,
+                      "src_network" => format!("{:?}", src_network)));
 
-                ipt_rule.out_interface(&bridge_name).destination(
-                    dst_network
-                        .IPv4Address
+            let bridge_name = self.bridge_name(network)?;
+            trace!(self.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
+
+            ipt_rule
+                .in_interface(&bridge_name)
+                .out_interface(&bridge_name)
+                .source(
+                    src_network
+                        .ipv4_address
                         .split('/')
                         .next()
                         .ok_or_else(|| format_err!("IPv4 address is empty"))?,
                 );
-            }
+        }
 
-            if let Some(ref filter) = rule.filter {
-                ipt_rule.filter(filter);
-            }
+        if let Some(ref dst_container) = rule.dst_container {
+            let dst_network = match self.endpoint(network, dst_container)? {
+                Some(dst_network) => dst_network,
+                None => return Ok(()),
+            };
+            trace!(self.logger, "Got destination network";
+                   o!("network_name" => &network.name,
+                      "dst_network" => format!("{:?}", dst_network)));
 
-            // Set jump
-            ipt_rule.jump(&rule.action);
+            let bridge_name = self.bridge_name(network)?;
+            trace!(self.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
 
-            let rule_str = ipt_rule.build()?;
-            debug!(self.logger, "Add forward rule";
-                   o!("part" => "container_to_container",
-                      "rule" => &rule_str));
+            ipt_rule.out_interface(&bridge_name).destination(
+                dst_network
+                    .ipv4_address
+                    .split('/')
+                    .next()
+                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+            );
+        }
 
-            // Apply the rule
-            self.ipt4.append("filter", DFWRS_FORWARD_CHAIN, &rule_str)?;
-            // TODO: verify what is needed for ipt6
+        if let Some(ref filter) = rule.filter {
+            ipt_rule.filter(filter);
         }
 
+        if let Some(ref schedule) = rule.schedule {
+            ipt_rule.schedule(schedule);
+        }
+
+        // Set jump
+        ipt_rule.jump(&rule.action);
+
+        let rule = ipt_rule.build("filter", DFWRS_FORWARD_CHAIN, rule_provenance(key))?;
+        debug!(self.logger, "Add forward rule";
+               o!("part" => "container_to_container",
+                  "rule" => rule.to_iptables()?));
+
+        // Apply the rule
+        self.append_rule(key, rule);
+        // TODO: verify what is needed for ipt6
+
         Ok(())
     }
 
@@ -416,7 +937,10 @@ impl<'a> ProcessDFW<'a> {
             self.process_ctww_rules(ctwwr)?;
         }
 
-        // Default policy
+        Ok(())
+    }
+
+    fn process_ctww_default_policy(&self, ctww: &ContainerToWiderWorld) -> Result<()> {
         if let Some(ref external_network_interfaces) = self.external_network_interfaces {
             debug!(self.logger, "Set default policy for external network interfaces";
                    o!("part" => "container_to_wider_world",
@@ -427,24 +951,35 @@ impl<'a> ProcessDFW<'a> {
                        o!("part" => "container_to_wider_world",
                           "external_network_interface" => external_network_interface,
                           "default_policy" => &ctww.default_policy));
-                for network in self.network_map.values() {
-                    let bridge_name = get_bridge_name(&network.Id)?;
+                // The overlay networks share the bridge of the `docker_gwbridge` network.
+                for network in self
+                    .network_map
+                    .values()
+                    .filter(|network| network.driver != OVERLAY_DRIVER)
+                {
+                    let bridge_name = self.bridge_name(network)?;
                     trace!(self.logger, "Got bridge name";
-                           o!("network_name" => &network.Name,
+                           o!("network_name" => &network.name,
                               "bridge_name" => &bridge_name));
 
                     let rule = Rule::default()
                         .in_interface(&bridge_name)
                         .out_interface(external_network_interface)
                         .jump(&ctww.default_policy)
-                        .build()?;
+                        .build(
+                            "filter",
+                            DFWRS_FORWARD_CHAIN,
+                            Provenance::DefaultPolicy {
+                                section: "container_to_wider_world".to_owned(),
+                            },
+                        )?;
 
                     debug!(self.logger, "Add forward rule for default policy";
                            o!("part" => "container_to_wider_world",
                               "external_network_interface" => external_network_interface,
                               "default_policy" => &ctww.default_policy,
-                              "rule" => &rule));
-                    self.ipt4.append("filter", DFWRS_FORWARD_CHAIN, &rule)?;
+                              "rule" => rule.to_iptables()?));
+                    self.add_rule(rule);
                     // TODO: verify what is needed for ipt6
                 }
             }
@@ -454,83 +989,110 @@ impl<'a> ProcessDFW<'a> {
     }
 
     fn process_ctww_rules(&self, rules: &[ContainerToWiderWorldRule]) -> Result<()> {
-        for rule in rules {
-            debug!(self.logger, "Process rule";
-                   o!("part" => "container_to_wider_world",
-                      "rule" => format!("{:?}", rule)));
-            let mut ipt_rule = Rule::default();
+        for (index, rule) in rules.iter().enumerate() {
+            let key = ("container_to_wider_world", index);
+            self.isolate_rule(key, || self.process_ctww_rule(key, rule))?;
+        }
 
-            if let Some(ref network) = rule.network {
-                if let Some(network) = self.network_map.get(network) {
-                    let bridge_name = get_bridge_name(&network.Id)?;
-                    trace!(self.logger, "Got bridge name";
-                           o!("network_name" => &network.Name,
-                              "bridge_name" => &bridge_name));
+        Ok(())
+    }
 
-                    ipt_rule.in_interface(&bridge_name);
+    fn process_ctww_rule(&self, key: RuleKey, rule: &ContainerToWiderWorldRule) -> Result<()> {
+        if self.reuse_cached_rules(key, &[rule.src_container.as_ref()])? {
+            return Ok(());
+        }
 
-                    if let Some(ref src_container) = rule.src_container {
-                        if let Some(src_network) = get_network_for_container(
-                            self.docker,
-                            &self.container_map,
-                            src_container,
-                            &network.Id,
-                        )? {
-                            trace!(self.logger, "Got source network";
-                                   o!("network_name" => &network.Name,
-                                      "src_network" => format!("{:?}", src_network)));
+        match rule.src_container {
+            Some(ref src_container) => {
+                for src_container in self.resolve_containers(src_container) {
+                    self.process_ctww_rule_for(key, rule, Some(&src_container))?;
+                }
+                Ok(())
+            }
+            None => self.process_ctww_rule_for(key, rule, None),
+        }
+    }
 
-                            let bridge_name = get_bridge_name(&network.Id)?;
-                            trace!(self.logger, "Got bridge name";
-                                   o!("network_name" => &network.Name,
-                                      "bridge_name" => &bridge_name));
+    fn process_ctww_rule_for(
+        &self,
+        key: RuleKey,
+        rule: &ContainerToWiderWorldRule,
+        src_container: Option<&str>,
+    ) -> Result<()> {
+        debug!(self.logger, "Process rule";
+               o!("part" => "container_to_wider_world",
+                  "rule" => format!("{:?}", rule)));
+        let mut ipt_rule = Rule::default();
+
+        if let Some(ref network) = rule.network {
+            if let Some(network) = self.network_map.get(network) {
+                let bridge_name = self.bridge_name(network)?;
+                trace!(self.logger, "Got bridge name";
+                       o!("network_name" => &network.name,
+                          "bridge_name" => &bridge_name));
 
-                            ipt_rule.in_interface(&bridge_name).source(
-                                src_network
-                                    .IPv4Address
-                                    .split('/')
-                                    .next()
-                                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                            );
-                        }
+                ipt_rule.in_interface(&bridge_name);
+
+                if let Some(src_container) = src_container {
+                    if let Some(src_network) = self.endpoint(network, src_container)? {
+                        trace!(self.logger, "Got source network";
+                               o!("network_name" => &network.name,
+                                  "src_network" => format!("{:?}", src_network)));
+
+                        let bridge_name = self.bridge_name(network)?;
+                        trace!(self.logger, "Got bridge name";
+                               o!("network_name" => &network.name,
+                                  "bridge_name" => &bridge_name));
+
+                        ipt_rule.in_interface(&bridge_name).source(
+                            src_network
+                                .ipv4_address
+                                .split('/')
+                                .next()
+                                .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                        );
                     }
                 }
             }
+        }
 
-            if let Some(ref filter) = rule.filter {
-                ipt_rule.filter(filter);
-            }
-
-            ipt_rule.jump(&rule.action);
+        if let Some(ref filter) = rule.filter {
+            ipt_rule.filter(filter);
+        }
 
-            // Try to build the rule without the out_interface defined to see if any of the other
-            // mandatory fields has been populated.
-            debug!(self.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", ipt_rule)));
-            ipt_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
+        if let Some(ref schedule) = rule.schedule {
+            ipt_rule.schedule(schedule);
+        }
 
-            if let Some(ref external_network_interface) = rule.external_network_interface {
-                trace!(self.logger, "Rule has specific external network interface";
-                       o!("external_network_interface" => external_network_interface));
-                ipt_rule.out_interface(external_network_interface);
-            } else if let Some(ref primary_external_network_interface) =
-                self.primary_external_network_interface
-            {
-                trace!(self.logger, "Rule uses primary external network interface";
-                       o!("external_network_interface" => primary_external_network_interface));
-                ipt_rule.out_interface(primary_external_network_interface);
-            }
+        ipt_rule.jump(&rule.action);
 
-            let rule_str = ipt_rule.build()?;
-            debug!(self.logger, "Add forward rule";
-                   o!("part" => "container_to_wider_world",
-                      "rule" => &rule_str));
+        // Try to build the rule without the out_interface defined to see if any of the other
+        // mandatory fields has been populated.
+        debug!(self.logger, "Build rule to verify contents";
+               o!("args" => format!("{:?}", ipt_rule)));
+        ipt_rule.verify()?;
 
-            // Apply the rule
-            self.ipt4.append("filter", DFWRS_FORWARD_CHAIN, &rule_str)?;
-            // TODO: verify what is needed for ipt6
+        if let Some(ref external_network_interface) = rule.external_network_interface {
+            trace!(self.logger, "Rule has specific external network interface";
+                   o!("external_network_interface" => external_network_interface));
+            ipt_rule.out_interface(external_network_interface);
+        } else if let Some(ref primary_external_network_interface) =
+            self.primary_external_network_interface
+        {
+            trace!(self.logger, "Rule uses primary external network interface";
+                   o!("external_network_interface" => primary_external_network_interface));
+            ipt_rule.out_interface(primary_external_network_interface);
         }
 
+        let rule = ipt_rule.build("filter", DFWRS_FORWARD_CHAIN, rule_provenance(key))?;
+        debug!(self.logger, "Add forward rule";
+               o!("part" => "container_to_wider_world",
+                  "rule" => rule.to_iptables()?));
+
+        // Apply the rule
+        self.append_rule(key, rule);
+        // TODO: verify what is needed for ipt6
+
         Ok(())
     }
 
@@ -542,23 +1104,37 @@ impl<'a> ProcessDFW<'a> {
             self.process_cth_rules(cthr)?;
         }
 
-        // Default policy
-        for network in self.network_map.values() {
-            let bridge_name = get_bridge_name(&network.Id)?;
+        Ok(())
+    }
+
+    fn process_cth_default_policy(&self, cth: &ContainerToHost) -> Result<()> {
+        // The overlay networks share the bridge of the `docker_gwbridge` network.
+        for network in self
+            .network_map
+            .values()
+            .filter(|network| network.driver != OVERLAY_DRIVER)
+        {
+            let bridge_name = self.bridge_name(network)?;
             trace!(self.logger, "Got bridge name";
-                   o!("network_name" => &network.Name,
+                   o!("network_name" => &network.name,
                       "bridge_name" => &bridge_name));
 
             let rule = Rule::default()
                 .in_interface(&bridge_name)
                 .jump(&cth.default_policy)
-                .build()?;
+                .build(
+                    "filter",
+                    DFWRS_INPUT_CHAIN,
+                    Provenance::DefaultPolicy {
+                        section: "container_to_host".to_owned(),
+                    },
+                )?;
 
             trace!(self.logger, "Add input rule for default policy";
                    o!("part" => "container_to_host",
                       "default_policy" => &cth.default_policy,
-                      "rule" => &rule));
-            self.ipt4.append("filter", DFWRS_INPUT_CHAIN, &rule)?;
+                      "rule" => rule.to_iptables()?));
+            self.add_rule(rule);
             // TODO: verify what is needed for ipt6
         }
 
@@ -566,69 +1142,96 @@ impl<'a> ProcessDFW<'a> {
     }
 
     fn process_cth_rules(&self, rules: &[ContainerToHostRule]) -> Result<()> {
-        for rule in rules {
-            debug!(self.logger, "Process rule";
-                   o!("part" => "container_to_host",
-                      "rule" => format!("{:?}", rule)));
-            let mut ipt_rule = Rule::default();
+        for (index, rule) in rules.iter().enumerate() {
+            let key = ("container_to_host", index);
+            self.isolate_rule(key, || self.process_cth_rule(key, rule))?;
+        }
 
-            let network = match self.network_map.get(&rule.network) {
-                Some(network) => network,
-                None => continue,
-            };
-            trace!(self.logger, "Got network";
-                   o!("network_name" => &network.Name,
-                      "network" => format!("{:?}", network)));
+        Ok(())
+    }
 
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(self.logger, "Got bridge name";
-                   o!("network_name" => &network.Name,
-                      "bridge_name" => &bridge_name));
+    fn process_cth_rule(&self, key: RuleKey, rule: &ContainerToHostRule) -> Result<()> {
+        if self.reuse_cached_rules(key, &[rule.src_container.as_ref()])? {
+            return Ok(());
+        }
 
-            ipt_rule.in_interface(&bridge_name);
-
-            if let Some(ref src_container) = rule.src_container {
-                if let Some(src_network) = get_network_for_container(
-                    self.docker,
-                    &self.container_map,
-                    src_container,
-                    &network.Id,
-                )? {
-                    trace!(self.logger, "Got source network";
-                           o!("network_name" => &network.Name,
-                              "src_network" => format!("{:?}", src_network)));
-                    ipt_rule.source(
-                        src_network
-                            .IPv4Address
-                            .split('/')
-                            .next()
-                            .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    );
+        match rule.src_container {
+            Some(ref src_container) => {
+                for src_container in self.resolve_containers(src_container) {
+                    self.process_cth_rule_for(key, rule, Some(&src_container))?;
                 }
+                Ok(())
             }
+            None => self.process_cth_rule_for(key, rule, None),
+        }
+    }
 
-            if let Some(ref filter) = rule.filter {
-                ipt_rule.filter(filter);
-            }
+    fn process_cth_rule_for(
+        &self,
+        key: RuleKey,
+        rule: &ContainerToHostRule,
+        src_container: Option<&str>,
+    ) -> Result<()> {
+        debug!(self.logger, "Process rule";
+               o!("part" => "container_to_host",
+                  "rule" => format!("{:?}", rule)));
+        let mut ipt_rule = Rule::default();
+
+        let network = match self.network_map.get(&rule.network) {
+            Some(network) => network,
+            None => return Ok(()),
+        };
+        trace!(self.logger, "Got network";
+               o!("network_name" => &network.name,
+                  "network" => format!("{:?}", network)));
 
-            ipt_rule.jump(&rule.action);
+        let bridge_name = self.bridge_name(network)?;
+        trace!(self.logger, "Got bridge name";
+               o!("network_name" => &network.name,
+                  "bridge_name" => &bridge_name));
 
-            // Try to build the rule without the out_interface defined to see if any of the other
-            // mandatory fields has been populated.
-            debug!(self.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", ipt_rule)));
-            ipt_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
+        ipt_rule.in_interface(&bridge_name);
 
-            let rule_str = ipt_rule.build()?;
-            debug!(self.logger, "Add input rule";
-                   o!("part" => "container_to_host",
-                      "rule" => &rule_str));
+        if let Some(src_container) = src_container {
+            if let Some(src_network) = self.endpoint(network, src_container)? {
+                trace!(self.logger, "Got source network";
+                       o!("network_name" => &network.name,
+                          "src_network" => format!("{:?}", src_network)));
+                ipt_rule.source(
+                    src_network
+                        .ipv4_address
+                        .split('/')
+                        .next()
+                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                );
+            }
+        }
 
-            // Apply the rule
-            self.ipt4.append("filter", DFWRS_INPUT_CHAIN, &rule_str)?;
-            // TODO: verify what is needed for ipt6
+        if let Some(ref filter) = rule.filter {
+            ipt_rule.filter(filter);
         }
 
+        if let Some(ref schedule) = rule.schedule {
+            ipt_rule.schedule(schedule);
+        }
+
+        ipt_rule.jump(&rule.action);
+
+        // Try to build the rule without the out_interface defined to see if any of the other
+        // mandatory fields has been populated.
+        debug!(self.logger, "Build rule to verify contents";
+               o!("args" => format!("{:?}", ipt_rule)));
+        ipt_rule.verify()?;
+
+        let rule = ipt_rule.build("filter", DFWRS_INPUT_CHAIN, rule_provenance(key))?;
+        debug!(self.logger, "Add input rule";
+               o!("part" => "container_to_host",
+                  "rule" => rule.to_iptables()?));
+
+        // Apply the rule
+        self.append_rule(key, rule);
+        // TODO: verify what is needed for ipt6
+
         Ok(())
     }
 
@@ -645,118 +1248,220 @@ impl<'a> ProcessDFW<'a> {
         debug!(self.logger, "Process rules";
                o!("part" => "wider_world_to_container"));
 
-        for rule in rules {
-            debug!(self.logger, "Process rule";
-                   o!("part" => "wider_world_to_container",
-                      "rule" => format!("{:?}", rule)));
-            for expose_port in &rule.expose_port {
-                let mut ipt_forward_rule = Rule::default();
-                let mut ipt_dnat_rule = Rule::default();
+        for (index, rule) in rules.iter().enumerate() {
+            let key = ("wider_world_to_container", index);
+            self.isolate_rule(key, || self.process_wwtc_rule(key, rule))?;
+        }
+        Ok(())
+    }
+
+    fn process_wwtc_rule(&self, key: RuleKey, rule: &WiderWorldToContainerRule) -> Result<()> {
+        if self.reuse_cached_rules(key, &[Some(&rule.dst_container)])? {
+            return Ok(());
+        }
+
+        debug!(self.logger, "Process rule";
+               o!("part" => "wider_world_to_container",
+                  "rule" => format!("{:?}", rule)));
+        if rule.network == INGRESS_NETWORK {
+            if let Some(ref ingress_sandbox) = self.ingress_sandbox {
+                return self.process_wwtc_ingress_rule(key, rule, ingress_sandbox);
+            }
+        }
+
+        let dst_containers = self.resolve_containers(&rule.dst_container);
+        if dst_containers.len() > 1 {
+            bail!(
+                "service `{}` has {} tasks on this node, expose its ports through the `{}` network",
+                rule.dst_container,
+                dst_containers.len(),
+                INGRESS_NETWORK
+            );
+        }
+        let dst_container = &dst_containers[0];
+
+        for expose_port in &rule.expose_port {
+            let mut ipt_forward_rule = Rule::default();
+            let mut ipt_dnat_rule = Rule::default();
+
+            let network = match self.network_map.get(&rule.network) {
+                Some(network) => network,
+                None => continue,
+            };
+            trace!(self.logger, "Got network";
+                   o!("network_name" => &network.name,
+                      "network" => format!("{:?}", network)));
+
+            let bridge_name = self.bridge_name(network)?;
+            trace!(self.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
+
+            ipt_forward_rule.out_interface(&bridge_name);
+
+            if let Some(dst_network) = self.endpoint(network, dst_container)? {
+                trace!(self.logger, "Got destination network";
+                       o!("network_name" => &network.name,
+                          "dst_network" => format!("{:?}", dst_network)));
 
-                let network = match self.network_map.get(&rule.network) {
-                    Some(network) => network,
-                    None => continue,
+                ipt_forward_rule.destination(
+                    dst_network
+                        .ipv4_address
+                        .split('/')
+                        .next()
+                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                );
+
+                let destination_port = match expose_port.container_port {
+                    Some(destination_port) => destination_port.to_string(),
+                    None => expose_port.host_port.to_string(),
                 };
-                trace!(self.logger, "Got network";
-                       o!("network_name" => &network.Name,
-                          "network" => format!("{:?}", network)));
+                ipt_forward_rule.destination_port(&destination_port);
+                ipt_dnat_rule.destination_port(&destination_port);
+                ipt_dnat_rule.dnat(&format!(
+                    "{}:{}",
+                    dst_network
+                        .ipv4_address
+                        .split('/')
+                        .next()
+                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                    destination_port
+                ));
+            } else {
+                // Network for container has to exist
+                continue;
+            }
 
-                let bridge_name = get_bridge_name(&network.Id)?;
-                trace!(self.logger, "Got bridge name";
-                       o!("network_name" => &network.Name,
-                          "bridge_name" => &bridge_name));
+            // Set correct protocol
+            ipt_forward_rule.protocol(&expose_port.family);
+            ipt_dnat_rule.protocol(&expose_port.family);
 
-                ipt_forward_rule.out_interface(&bridge_name);
-
-                if let Some(dst_network) = get_network_for_container(
-                    self.docker,
-                    &self.container_map,
-                    &rule.dst_container,
-                    &network.Id,
-                )? {
-                    trace!(self.logger, "Got destination network";
-                           o!("network_name" => &network.Name,
-                              "dst_network" => format!("{:?}", dst_network)));
-
-                    ipt_forward_rule.destination(
-                        dst_network
-                            .IPv4Address
-                            .split('/')
-                            .next()
-                            .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    );
-
-                    let destination_port = match expose_port.container_port {
-                        Some(destination_port) => destination_port.to_string(),
-                        None => expose_port.host_port.to_string(),
-                    };
-                    ipt_forward_rule.destination_port(&destination_port);
-                    ipt_dnat_rule.destination_port(&destination_port);
-                    ipt_dnat_rule.jump(&format!(
-                        "DNAT --to-destination {}:{}",
-                        dst_network
-                            .IPv4Address
-                            .split('/')
-                            .next()
-                            .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                        destination_port
-                    ));
-                } else {
-                    // Network for container has to exist
-                    continue;
-                }
+            if let Some(ref schedule) = rule.schedule {
+                ipt_forward_rule.schedule(schedule);
+                ipt_dnat_rule.schedule(schedule);
+            }
+
+            ipt_forward_rule.jump("ACCEPT");
+
+            // Try to build the rule without the out_interface defined to see if any of the
+            // other mandatory fields has been populated.
+            debug!(self.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", ipt_forward_rule)));
+            ipt_forward_rule.verify()?;
+            debug!(self.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", ipt_dnat_rule)));
+            ipt_dnat_rule.verify()?;
 
-                // Set correct protocol
-                ipt_forward_rule.protocol(&expose_port.family);
-                ipt_dnat_rule.protocol(&expose_port.family);
+            if let Some(ref external_network_interface) = rule.external_network_interface {
+                trace!(self.logger, "Rule has specific external network interface";
+                       o!("external_network_interface" => external_network_interface));
 
-                ipt_forward_rule.jump("ACCEPT");
+                ipt_forward_rule.in_interface(external_network_interface);
+                ipt_dnat_rule.in_interface(external_network_interface);
+            } else if let Some(ref primary_external_network_interface) =
+                self.primary_external_network_interface
+            {
+                trace!(self.logger, "Rule uses primary external network interface";
+                       o!("external_network_interface" => primary_external_network_interface));
 
-                // Try to build the rule without the out_interface defined to see if any of the
-                // other mandatory fields has been populated.
-                debug!(self.logger, "Build rule to verify contents";
-                       o!("args" => format!("{:?}", ipt_forward_rule)));
-                ipt_forward_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
-                debug!(self.logger, "Build rule to verify contents";
-                       o!("args" => format!("{:?}", ipt_dnat_rule)));
-                ipt_dnat_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
+                ipt_forward_rule.in_interface(primary_external_network_interface);
+                ipt_dnat_rule.in_interface(primary_external_network_interface);
+            } else {
+                // The DNAT rule requires the external interface
+                continue;
+            }
 
-                if let Some(ref external_network_interface) = rule.external_network_interface {
-                    trace!(self.logger, "Rule has specific external network interface";
-                           o!("external_network_interface" => external_network_interface));
+            let forward_rule =
+                ipt_forward_rule.build("filter", DFWRS_FORWARD_CHAIN, rule_provenance(key))?;
+            debug!(self.logger, "Add forward rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => forward_rule.to_iptables()?));
+            let dnat_rule =
+                ipt_dnat_rule.build("nat", DFWRS_PREROUTING_CHAIN, rule_provenance(key))?;
+            debug!(self.logger, "Add DNAT rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => dnat_rule.to_iptables()?));
 
-                    ipt_forward_rule.in_interface(external_network_interface);
-                    ipt_dnat_rule.in_interface(external_network_interface);
-                } else if let Some(ref primary_external_network_interface) =
-                    self.primary_external_network_interface
-                {
-                    trace!(self.logger, "Rule uses primary external network interface";
-                           o!("external_network_interface" => primary_external_network_interface));
+            // Apply the rule
+            self.append_rule(key, forward_rule);
+            self.append_rule(key, dnat_rule);
+            // TODO: verify what is needed for ipt6
+        }
 
-                    ipt_forward_rule.in_interface(primary_external_network_interface);
-                    ipt_dnat_rule.in_interface(primary_external_network_interface);
-                } else {
-                    // The DNAT rule requires the external interface
-                    continue;
-                }
+        Ok(())
+    }
 
-                let forward_rule_str = ipt_forward_rule.build()?;
-                debug!(self.logger, "Add forward rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &forward_rule_str));
-                let dnat_rule_str = ipt_dnat_rule.build()?;
-                debug!(self.logger, "Add DNAT rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &dnat_rule_str));
-
-                // Apply the rule
-                self.ipt4
-                    .append("filter", DFWRS_FORWARD_CHAIN, &forward_rule_str)?;
-                self.ipt4
-                    .append("nat", DFWRS_PREROUTING_CHAIN, &dnat_rule_str)?;
-                // TODO: verify what is needed for ipt6
+    /// Expose the published ports of a swarm service, which the routing mesh forwards to the
+    /// ingress sandbox.
+    ///
+    /// The routing mesh balances the connections across the tasks of the service on all nodes,
+    /// which is why the rule does not depend on the tasks running on this node.
+    fn process_wwtc_ingress_rule(
+        &self,
+        key: RuleKey,
+        rule: &WiderWorldToContainerRule,
+        ingress_sandbox: &str,
+    ) -> Result<()> {
+        let gwbridge = self.bridge_name(&self.network_map[INGRESS_NETWORK])?;
+        trace!(self.logger, "Got bridge name";
+               o!("network_name" => INGRESS_NETWORK,
+                  "bridge_name" => &gwbridge));
+
+        let external_network_interface = match rule.external_network_interface {
+            Some(ref external_network_interface) => external_network_interface,
+            None => match self.primary_external_network_interface {
+                Some(ref primary_external_network_interface) => primary_external_network_interface,
+                // The DNAT rule requires the external interface
+                None => return Ok(()),
+            },
+        };
+
+        for expose_port in &rule.expose_port {
+            if expose_port.container_port.is_some() {
+                bail!(
+                    "the container port of service `{}` is defined by the service, remove \
+                     `container_port` from the rule",
+                    rule.dst_container
+                );
             }
+            let host_port = expose_port.host_port.to_string();
+
+            let mut ipt_forward_rule = Rule::default();
+            ipt_forward_rule
+                .in_interface(external_network_interface)
+                .out_interface(&gwbridge)
+                .destination(ingress_sandbox)
+                .protocol(&expose_port.family)
+                .destination_port(&host_port);
+            let mut ipt_dnat_rule = Rule::default();
+            ipt_dnat_rule
+                .in_interface(external_network_interface)
+                .protocol(&expose_port.family)
+                .destination_port(&host_port);
+            if let Some(ref schedule) = rule.schedule {
+                ipt_forward_rule.schedule(schedule);
+                ipt_dnat_rule.schedule(schedule);
+            }
+            ipt_forward_rule.jump("ACCEPT");
+            ipt_dnat_rule.dnat(&format!("{}:{}", ingress_sandbox, host_port));
+
+            let forward_rule =
+                ipt_forward_rule.build("filter", DFWRS_INGRESS_CHAIN, rule_provenance(key))?;
+            debug!(self.logger, "Add ingress rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => forward_rule.to_iptables()?));
+            let dnat_rule =
+                ipt_dnat_rule.build("nat", DFWRS_PREROUTING_CHAIN, rule_provenance(key))?;
+            debug!(self.logger, "Add DNAT rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => dnat_rule.to_iptables()?));
+
+            // Apply the rule
+            self.append_rule(key, forward_rule);
+            self.append_rule(key, dnat_rule);
+            // TODO: verify what is needed for ipt6
         }
+
         Ok(())
     }
 
@@ -773,126 +1478,133 @@ impl<'a> ProcessDFW<'a> {
         debug!(self.logger, "Process rules";
                o!("part" => "container_dnat"));
 
-        for rule in rules {
-            debug!(self.logger, "Process rule";
-                   o!("part" => "container_dnat",
-                      "rule" => format!("{:?}", rule)));
-            for expose_port in &rule.expose_port {
-                let mut ipt_rule = Rule::default();
+        for (index, rule) in rules.iter().enumerate() {
+            let key = ("container_dnat", index);
+            self.isolate_rule(key, || self.process_cd_rule(key, rule))?;
+        }
+
+        Ok(())
+    }
 
-                if let Some(ref network) = rule.src_network {
-                    if let Some(network) = self.network_map.get(network) {
-                        trace!(self.logger, "Got network";
-                               o!("network_name" => &network.Name,
-                                  "network" => format!("{:?}", network)));
+    fn process_cd_rule(&self, key: RuleKey, rule: &ContainerDNATRule) -> Result<()> {
+        if self.reuse_cached_rules(
+            key,
+            &[rule.src_container.as_ref(), Some(&rule.dst_container)],
+        )? {
+            return Ok(());
+        }
 
-                        let bridge_name = get_bridge_name(&network.Id)?;
-                        trace!(self.logger, "Got bridge name";
-                               o!("network_name" => &network.Name,
-                                  "bridge_name" => &bridge_name));
+        debug!(self.logger, "Process rule";
+               o!("part" => "container_dnat",
+                  "rule" => format!("{:?}", rule)));
+        for expose_port in &rule.expose_port {
+            let mut ipt_rule = Rule::default();
+
+            if let Some(ref network) = rule.src_network {
+                if let Some(network) = self.network_map.get(network) {
+                    trace!(self.logger, "Got network";
+                           o!("network_name" => &network.name,
+                              "network" => format!("{:?}", network)));
 
-                        ipt_rule.in_interface(&bridge_name);
-
-                        if let Some(ref src_container) = rule.src_container {
-                            if let Some(src_network) = get_network_for_container(
-                                self.docker,
-                                &self.container_map,
-                                src_container,
-                                &network.Id,
-                            )? {
-                                trace!(self.logger, "Got source network";
-                                       o!("network_name" => &network.Name,
-                                          "src_network" => format!("{:?}", src_network)));
-
-                                let bridge_name = get_bridge_name(&network.Id)?;
-                                trace!(self.logger, "Got bridge name";
-                                       o!("network_name" => &network.Name,
-                                          "bridge_name" => &bridge_name));
-
-                                ipt_rule.in_interface(&bridge_name).source(
-                                    src_network
-                                        .IPv4Address
-                                        .split('/')
-                                        .next()
-                                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                                );
-                            }
+                    let bridge_name = self.bridge_name(network)?;
+                    trace!(self.logger, "Got bridge name";
+                           o!("network_name" => &network.name,
+                              "bridge_name" => &bridge_name));
+
+                    ipt_rule.in_interface(&bridge_name);
+
+                    if let Some(ref src_container) = rule.src_container {
+                        if let Some(src_network) = self.endpoint(network, src_container)? {
+                            trace!(self.logger, "Got source network";
+                                   o!("network_name" => &network.name,
+                                      "src_network" => format!("{:?}", src_network)));
+
+                            let bridge_name = self.bridge_name(network)?;
+                            trace!(self.logger, "Got bridge name";
+                                   o!("network_name" => &network.name,
+                                      "bridge_name" => &bridge_name));
+
+                            ipt_rule.in_interface(&bridge_name).source(
+                                src_network
+                                    .ipv4_address
+                                    .split('/')
+                                    .next()
+                                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                            );
                         }
                     }
                 }
+            }
 
-                let network = match self.network_map.get(&rule.dst_network) {
-                    Some(network) => network,
-                    None => continue,
-                };
-                let dst_network = match get_network_for_container(
-                    self.docker,
-                    &self.container_map,
-                    &rule.dst_container,
-                    &network.Id,
-                )? {
-                    Some(dst_network) => dst_network,
-                    None => continue,
-                };
-                trace!(self.logger, "Got destination network";
-                       o!("network_name" => &network.Name,
-                          "dst_network" => format!("{:?}", dst_network)));
+            let network = match self.network_map.get(&rule.dst_network) {
+                Some(network) => network,
+                None => continue,
+            };
+            let dst_network = match self.endpoint(network, &rule.dst_container)? {
+                Some(dst_network) => dst_network,
+                None => continue,
+            };
+            trace!(self.logger, "Got destination network";
+                   o!("network_name" => &network.name,
+                      "dst_network" => format!("{:?}", dst_network)));
 
-                let bridge_name = get_bridge_name(&network.Id)?;
-                trace!(self.logger, "Got bridge name";
-                       o!("network_name" => &network.Name,
-                          "bridge_name" => &bridge_name));
+            let bridge_name = self.bridge_name(network)?;
+            trace!(self.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
 
-                ipt_rule.out_interface(&bridge_name);
+            ipt_rule.out_interface(&bridge_name);
 
-                let destination_port = match expose_port.container_port {
-                    Some(destination_port) => destination_port.to_string(),
-                    None => expose_port.host_port.to_string(),
-                };
-                ipt_rule.destination_port(&destination_port);
-                ipt_rule.jump(&format!(
-                    "DNAT --to-destination {}:{}",
-                    dst_network
-                        .IPv4Address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    destination_port
-                ));
+            let destination_port = match expose_port.container_port {
+                Some(destination_port) => destination_port.to_string(),
+                None => expose_port.host_port.to_string(),
+            };
+            ipt_rule.destination_port(&destination_port);
+            if let Some(ref schedule) = rule.schedule {
+                ipt_rule.schedule(schedule);
+            }
+            ipt_rule.dnat(&format!(
+                "{}:{}",
+                dst_network
+                    .ipv4_address
+                    .split('/')
+                    .next()
+                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                destination_port
+            ));
+
+            // Try to build the rule without the out_interface defined to see if any of the
+            // other mandatory fields has been populated.
+            debug!(self.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", ipt_rule)));
+            ipt_rule.verify()?;
 
-                // Try to build the rule without the out_interface defined to see if any of the
-                // other mandatory fields has been populated.
-                debug!(self.logger, "Build rule to verify contents";
-                       o!("args" => format!("{:?}", ipt_rule)));
-                ipt_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
-
-                if ipt_rule.out_interface.is_none() {
-                    if let Some(ref primary_external_network_interface) =
-                        self.primary_external_network_interface
-                    {
-                        trace!(self.logger, "Set primary external network interface";
-                               o!("external_network_interface"
-                                  => primary_external_network_interface));
-
-                        ipt_rule
-                            .in_interface(primary_external_network_interface)
-                            .not_in_interface(true);
-                    } else {
-                        // We need to specify a external network interface.
-                        // If it is not defined, skip the rule.
-                        continue;
-                    }
+            if ipt_rule.out_interface.is_none() {
+                if let Some(ref primary_external_network_interface) =
+                    self.primary_external_network_interface
+                {
+                    trace!(self.logger, "Set primary external network interface";
+                           o!("external_network_interface"
+                              => primary_external_network_interface));
+
+                    ipt_rule
+                        .in_interface(primary_external_network_interface)
+                        .not_in_interface(true);
+                } else {
+                    // We need to specify a external network interface.
+                    // If it is not defined, skip the rule.
+                    continue;
                 }
+            }
 
-                let rule_str = ipt_rule.build()?;
-                debug!(self.logger, "Add prerouting rule";
-                       o!("part" => "container_dnat",
-                          "rule" => &rule_str));
+            let rule = ipt_rule.build("nat", DFWRS_PREROUTING_CHAIN, rule_provenance(key))?;
+            debug!(self.logger, "Add prerouting rule";
+                   o!("part" => "container_dnat",
+                      "rule" => rule.to_iptables()?));
 
-                // Apply the rule
-                self.ipt4.append("nat", DFWRS_PREROUTING_CHAIN, &rule_str)?;
-                // TODO: verify what is needed for ipt6
-            }
+            // Apply the rule
+            self.append_rule(key, rule);
+            // TODO: verify what is needed for ipt6
         }
 
         Ok(())
@@ -915,7 +1627,8 @@ struct Rule {
     pub destination_port: Option<String>,
 
     pub filter: Option<String>,
-    pub jump: Option<String>,
+    pub schedule: Option<Schedule>,
+    pub target: Option<Target>,
 
     pub comment: Option<String>,
 }
@@ -1006,12 +1719,29 @@ impl Rule {
         new
     }
 
+    pub fn schedule(&mut self, value: &Schedule) -> &mut Self {
+        let new = self;
+        new.schedule = Some(value.clone());
+        new
+    }
+
     pub fn jump<S: ?Sized>(&mut self, value: &S) -> &mut Self
     where
         S: AsRef<str>,
     {
         let new = self;
-        new.jump = Some(value.as_ref().into());
+        new.target = Some(Target::from_action(value.as_ref()));
+        new
+    }
+
+    pub fn dnat<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        let new = self;
+        new.target = Some(Target::Dnat {
+            to_destination: value.as_ref().into(),
+        });
         new
     }
 
@@ -1024,80 +1754,62 @@ impl Rule {
         new
     }
 
-    pub fn build(&self) -> Result<String> {
-        let mut args: Vec<String> = Vec::new();
-
-        if let Some(ref source) = self.source {
-            args.push("-s".to_owned());
-            args.push(source.to_owned());
-        }
-        if let Some(ref destination) = self.destination {
-            args.push("-d".to_owned());
-            args.push(destination.to_owned());
-        }
-        if let Some(ref in_interface) = self.in_interface {
-            if self.not_in_interface {
-                args.push("!".to_owned());
-            }
-            args.push("-i".to_owned());
-            args.push(in_interface.to_owned());
-        }
-        if let Some(ref out_interface) = self.out_interface {
-            if self.not_out_interface {
-                args.push("!".to_owned());
-            }
-            args.push("-o".to_owned());
-            args.push(out_interface.to_owned());
-        }
-
-        if let Some(ref protocol) = self.protocol {
-            args.push("-p".to_owned());
-            args.push(protocol.to_owned());
-        } else if self.source_port.is_some() || self.destination_port.is_some() {
-            // Source and destination ports require that the protocol is set.
-            // If it hasn't been specified explicitly, use "tcp" as default.
-            args.push("-p".to_owned());
-            args.push("tcp".to_owned());
-        }
-
-        if let Some(ref source_port) = self.source_port {
-            args.push("--sport".to_owned());
-            args.push(source_port.to_owned());
-        }
-
-        if let Some(ref destination_port) = self.destination_port {
-            args.push("--dport".to_owned());
-            args.push(destination_port.to_owned());
-        }
+    pub fn matches(&self) -> Matches {
+        let interface = |name: &Option<String>, negated: bool| {
+            name.as_ref().map(|name| Interface {
+                name: name.to_owned(),
+                negated: negated,
+            })
+        };
 
-        if let Some(ref filter) = self.filter {
-            args.push(filter.to_owned());
+        Matches {
+            source: self.source.clone(),
+            destination: self.destination.clone(),
+            in_interface: interface(&self.in_interface, self.not_in_interface),
+            out_interface: interface(&self.out_interface, self.not_out_interface),
+            protocol: self.protocol.clone(),
+            source_port: self.source_port.clone(),
+            destination_port: self.destination_port.clone(),
+            state: Vec::new(),
+            filter: self.filter.clone(),
+            schedule: self.schedule.clone(),
+            comment: self.comment.clone(),
         }
+    }
 
-        // Bail if none of the above was initialized
-        if args.is_empty() {
+    /// Verify that the rule can be built, i.e. that it matches on something and has a target.
+    pub fn verify(&self) -> Result<()> {
+        let mut matches = self.matches();
+        // Bail if none of the matches was initialized, the comment doesn't match anything
+        matches.comment = None;
+        if matches.is_empty() {
             bail!(
                 "one of `source`, `destination`, `in_interface`, `out_interface` \
                  `protocol`, `source_port`, `destination_port` or `filter` must  be \
                  initialized"
             );
         }
-
-        if let Some(ref jump) = self.jump {
-            args.push("-j".to_owned());
-            args.push(jump.to_owned());
-        } else {
+        if self.target.is_none() {
             bail!("`jump` must be initialized");
         }
+        // Make sure the matches can be rendered, e.g. that the schedule is valid.
+        matches.to_iptables()?;
 
-        if let Some(ref comment) = self.comment {
-            args.push("-m".to_owned());
-            args.push("comment".to_owned());
-            args.push("--comment".to_owned());
-            args.push(format!("\"{}\"", comment));
-        }
+        Ok(())
+    }
 
-        Ok(args.join(" "))
+    pub fn build(&self, table: &str, chain: &str, provenance: Provenance) -> Result<ruleset::Rule> {
+        self.verify()?;
+
+        Ok(ruleset::Rule {
+            family: Family::IPv4,
+            table: table.to_owned(),
+            chain: chain.to_owned(),
+            placement: Placement::Append,
+            matches: self.matches(),
+            target: self.target.clone().unwrap(),
+            provenance: provenance,
+        })
     }
 }
 
@@ -1116,81 +1828,101 @@ pub struct ProcessingOptions {
     /// Option to filter the containers to be processed, see
     /// [`ContainerFilter`](enum.ContainerFilter.html).
     pub container_filter: ContainerFilter,
+    /// Abort processing if a configuration rule fails to process, instead of skipping the rule
+    /// and applying the remaining rules.
+    pub strict: bool,
 }
 
 impl Default for ProcessingOptions {
     fn default() -> Self {
         ProcessingOptions {
             container_filter: ContainerFilter::All,
+            strict: false,
         }
     }
 }
 
-fn create_and_flush_chain(
-    table: &str,
-    chain: &str,
-    ipt4: &IPTables,
-    ipt6: &IPTables,
-) -> Result<()> {
-    // Create and flush CTC chain
-    ipt4.new_chain(table, chain)?;
-    ipt6.new_chain(table, chain)?;
-    ipt4.flush_chain(table, chain)?;
-    ipt6.flush_chain(table, chain)?;
-
-    Ok(())
+/// Identifies a configuration rule by its section and its index within the section.
+type RuleKey = (&'static str, usize);
+
+/// A rule generated for a configuration rule.
+type CachedRule = ruleset::Rule;
+
+/// A generated rule that has yet to be added to the ruleset, together with the priority of its
+/// configuration rule.
+type PendingRule = (i32, ruleset::Rule);
+
+/// Cache holding the rules generated during the last successful processing run, see
+/// [`ProcessDFW::with_cache`](struct.ProcessDFW.html#method.with_cache).
+#[derive(Debug, Default)]
+pub struct ProcessingCache {
+    /// ## Note
+    ///
+    /// `RefCell` is required because the cache is shared with `ProcessDFW` immutably.
+    state: RefCell<Option<CacheState>>,
+}
+
+impl ProcessingCache {
+    /// Create a new, empty instance of `ProcessingCache`.
+    pub fn new() -> ProcessingCache {
+        Default::default()
+    }
 }
 
-fn initialize_chain(table: &str, chain: &str, ipt4: &IPTables, ipt6: &IPTables) -> Result<()> {
-    // Drop INVALID, accept RELATED/ESTABLISHED
-    ipt4.append(table, chain, "-m state --state INVALID -j DROP")?;
-    ipt6.append(table, chain, "-m state --state INVALID -j DROP")?;
-    ipt4.append(
-        table,
-        chain,
-        "-m state --state RELATED,ESTABLISHED -j ACCEPT",
-    )?;
-    ipt6.append(
-        table,
-        chain,
-        "-m state --state RELATED,ESTABLISHED -j ACCEPT",
-    )?;
-
-    Ok(())
+#[derive(Debug)]
+struct CacheState {
+    dfw: DFW,
+    network_ids: Vec<String>,
+    /// Maps container IDs to the names of the container.
+    container_names: Map<String, Vec<String>>,
+    rules: Map<RuleKey, Vec<CachedRule>>,
 }
 
-fn get_bridge_name(network_id: &str) -> Result<String> {
-    if network_id.len() < 12 {
-        bail!("network has to be longer than 12 characters");
+/// Get the provenance of the rules generated for the configuration rule identified by `key`.
+fn rule_provenance(key: RuleKey) -> Provenance {
+    Provenance::Rule {
+        section: key.0.to_owned(),
+        index: key.1,
     }
-    Ok(format!("br-{}", &network_id[..12]))
 }
 
-fn get_network_for_container(
-    docker: &Docker,
-    container_map: &Map<String, Container>,
-    container_name: &str,
-    network_id: &str,
-) -> Result<Option<NetworkContainerDetails>> {
-    Ok(match container_map.get(container_name) {
-        Some(container) => match docker
-            .networks()
-            .get(network_id)
-            .inspect()?
-            .Containers
-            .get(&container.Id)
-        {
-            Some(network) => Some(network.clone()),
-            None => None,
+/// Get the rule jumping from the builtin chain to the chain owned by DFW.
+fn jump_rule(table: &str, builtin_chain: &str, chain: &str) -> ruleset::Rule {
+    ruleset::Rule {
+        family: Family::IPv4,
+        table: table.to_owned(),
+        chain: builtin_chain.to_owned(),
+        placement: Placement::AppendUnique,
+        matches: Matches::default(),
+        target: Target::Jump {
+            chain: chain.to_owned(),
         },
-        None => None,
-    })
+        provenance: Provenance::Setup,
+    }
+}
+
+fn get_bridge_name(network: &Network, engine: Engine) -> Result<String> {
+    match engine {
+        Engine::Docker => {
+            if network.id.len() < 12 {
+                bail!("network has to be longer than 12 characters");
+            }
+            Ok(format!("br-{}", &network.id[..12]))
+        }
+        // Podman names the bridges itself (`podman0`, `podman1`, ...) and reports the name in the
+        // options of the network.
+        Engine::Podman => network
+            .options
+            .get(BRIDGE_NAME_OPTION)
+            .cloned()
+            .ok_or_else(|| format_err!("bridge of network `{}` is unknown", network.name)),
+    }
 }
 
 fn get_container_map(containers: &[Container]) -> Result<Option<Map<String, Container>>> {
     let mut container_map: Map<String, Container> = Map::new();
     for container in containers {
-        for name in &container.Names {
+        for name in &container.names {
             container_map.insert(
                 name.clone().trim_left_matches('/').to_owned(),
                 container.clone(),
@@ -1205,10 +1937,36 @@ fn get_container_map(containers: &[Container]) -> Result<Option<Map<String, Cont
     }
 }
 
-fn get_network_map(networks: &[NetworkDetails]) -> Result<Option<Map<String, NetworkDetails>>> {
-    let mut network_map: Map<String, NetworkDetails> = Map::new();
+fn get_network_ids(network_map: &Map<String, Network>) -> Vec<String> {
+    let mut network_ids = network_map
+        .values()
+        .map(|network| network.id.to_owned())
+        .collect::<Vec<_>>();
+    network_ids.sort();
+
+    network_ids
+}
+
+fn get_container_names(container_map: &Map<String, Container>) -> Map<String, Vec<String>> {
+    let mut container_names: Map<String, Vec<String>> = Map::new();
+    for (name, container) in container_map {
+        let names = container_names.entry(container.id.to_owned()).or_default();
+        names.push(name.to_owned());
+        // Rules reference the tasks of a swarm service through the name of the service.
+        if let Some(service_name) = container.labels.get(SERVICE_NAME_LABEL) {
+            if !names.contains(service_name) {
+                names.push(service_name.to_owned());
+            }
+        }
+    }
+
+    container_names
+}
+
+fn get_network_map(networks: &[Network]) -> Result<Option<Map<String, Network>>> {
+    let mut network_map: Map<String, Network> = Map::new();
     for network in networks {
-        network_map.insert(network.Name.clone(), network.clone());
+        network_map.insert(network.name.clone(), network.clone());
     }
 
     if network_map.is_empty() {
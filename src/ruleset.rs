@@ -0,0 +1,670 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module holds the intermediate representation of the rules generated by DFW.
+//!
+//! [`ProcessDFW::ruleset`](../process/struct.ProcessDFW.html#method.ruleset) generates a
+//! [`Ruleset`](struct.Ruleset.html) from the configuration, independently of the backend the
+//! rules are applied with. The ruleset can be inspected, serialized (e.g. to JSON through
+//! [`Ruleset::to_json`](struct.Ruleset.html#method.to_json)) or applied to
+//! [`IPTables`](../iptables/trait.IPTables.html) backends through
+//! [`Ruleset::apply`](struct.Ruleset.html#method.apply). It can also be compared with the rules
+//! installed on the host through [`Ruleset::diff`](struct.Ruleset.html#method.diff).
+//!
+//! # Example
+//!
+//! ```
+//! # use dfw::ruleset::*;
+//! let rule = Rule {
+//!     family: Family::IPv4,
+//!     table: "filter".to_owned(),
+//!     chain: "DFWRS_FORWARD".to_owned(),
+//!     placement: Placement::Append,
+//!     matches: Matches {
+//!         in_interface: Some(Interface::new("docker0")),
+//!         protocol: Some("tcp".to_owned()),
+//!         destination_port: Some("80".to_owned()),
+//!         ..Matches::default()
+//!     },
+//!     target: Target::Accept,
+//!     provenance: Provenance::Rule {
+//!         section: "container_to_wider_world".to_owned(),
+//!         index: 0,
+//!     },
+//! };
+//! assert_eq!(rule.to_iptables().unwrap(), "-i docker0 -p tcp --dport 80 -j ACCEPT");
+//! ```
+
+use diff;
+use errors::*;
+use iptables::{IPTables, IPTablesSave, SavedRule, SavedTable};
+use serde_json;
+use slog::Logger;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use types::Schedule;
+
+/// Prefix of the names of the chains owned by DFW.
+pub const CHAIN_PREFIX: &'static str = "DFWRS_";
+
+/// IP family a chain, rule or command applies to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Family {
+    /// IPv4, applied through `iptables`.
+    #[serde(rename = "ipv4")]
+    IPv4,
+    /// IPv6, applied through `ip6tables`.
+    #[serde(rename = "ipv6")]
+    IPv6,
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Family::IPv4 => write!(f, "ipv4"),
+            Family::IPv6 => write!(f, "ipv6"),
+        }
+    }
+}
+
+/// The rules generated by DFW, in the order they have to be applied in.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ruleset {
+    /// The entries of the ruleset.
+    pub entries: Vec<Entry>,
+}
+
+/// An entry of a [`Ruleset`](struct.Ruleset.html).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entry {
+    /// Create a chain, or flush it if it already exists.
+    Chain(Chain),
+    /// Add a rule to a chain.
+    Rule(Box<Rule>),
+    /// Execute a command of the `initialization` section verbatim.
+    Command(Command),
+}
+
+/// A chain owned by DFW.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    /// IP family of the chain.
+    pub family: Family,
+    /// Table of the chain, e.g. `filter`.
+    pub table: String,
+    /// Name of the chain.
+    pub name: String,
+}
+
+/// A command of the [`initialization`](../types/struct.Initialization.html) section.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// IP family to execute the command for.
+    pub family: Family,
+    /// Table to execute the command in.
+    pub table: String,
+    /// The command, i.e. the arguments passed to `iptables`.
+    pub command: String,
+}
+
+/// A rule generated by DFW.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rule {
+    /// IP family of the rule.
+    pub family: Family,
+    /// Table of the rule, e.g. `filter` or `nat`.
+    pub table: String,
+    /// Chain of the rule.
+    pub chain: String,
+    /// How the rule is added to the chain.
+    #[serde(default)]
+    pub placement: Placement,
+    /// The conditions the packets have to match for the target to apply.
+    #[serde(default)]
+    pub matches: Matches,
+    /// What to do with the matching packets.
+    pub target: Target,
+    /// What caused DFW to generate the rule.
+    pub provenance: Provenance,
+}
+
+/// How a rule is added to its chain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Placement {
+    /// Append the rule to the chain.
+    #[default]
+    Append,
+    /// Append the rule to the chain, unless the chain already contains it.
+    AppendUnique,
+    /// Insert the rule at the position (starting at 1) of the chain, unless the chain already
+    /// contains it.
+    InsertUnique {
+        /// Position to insert the rule at.
+        position: i32,
+    },
+}
+
+/// The matches of a rule. A packet has to match all of the matches that are set.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct Matches {
+    /// Source address of the packet, e.g. `172.18.0.2`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Destination address of the packet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    /// Interface the packet is received through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_interface: Option<Interface>,
+    /// Interface the packet is sent through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_interface: Option<Interface>,
+    /// Protocol of the packet, e.g. `tcp`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    /// Source port of the packet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_port: Option<String>,
+    /// Destination port of the packet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_port: Option<String>,
+    /// Connection tracking states of the packet, e.g. `RELATED` and `ESTABLISHED`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub state: Vec<String>,
+    /// Additional `iptables` arguments given by the `filter` of a configuration rule.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    /// Times the rule is active in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    /// Comment attached to the rule.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A network interface matched by a rule.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Interface {
+    /// Name of the interface.
+    pub name: String,
+    /// Match all interfaces except this one.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub negated: bool,
+}
+
+impl Interface {
+    /// Create a match for the interface with the given name.
+    pub fn new(name: &str) -> Interface {
+        Interface {
+            name: name.to_owned(),
+            negated: false,
+        }
+    }
+}
+
+/// The target of a rule.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Target {
+    /// Accept the packet.
+    Accept,
+    /// Drop the packet.
+    Drop,
+    /// Reject the packet.
+    Reject,
+    /// Stop traversing the chain.
+    Return,
+    /// Masquerade the source address of the packet.
+    Masquerade,
+    /// Rewrite the destination of the packet.
+    Dnat {
+        /// New destination, e.g. `172.18.0.2:80`.
+        to_destination: String,
+    },
+    /// Jump to a chain, or any other target given verbatim, e.g. `LOG --log-prefix dfw`.
+    Jump {
+        /// The chain or target, including its arguments.
+        chain: String,
+    },
+}
+
+impl Target {
+    /// Get the target for an `action` of the configuration, e.g. `ACCEPT`.
+    ///
+    /// Actions that aren't one of the builtin targets are kept verbatim as
+    /// [`Target::Jump`](#variant.Jump).
+    pub fn from_action(action: &str) -> Target {
+        match action {
+            "ACCEPT" => Target::Accept,
+            "DROP" => Target::Drop,
+            "REJECT" => Target::Reject,
+            "RETURN" => Target::Return,
+            "MASQUERADE" => Target::Masquerade,
+            _ => Target::Jump {
+                chain: action.to_owned(),
+            },
+        }
+    }
+
+    /// Render the target as the arguments of `-j`.
+    pub fn to_iptables(&self) -> String {
+        match *self {
+            Target::Accept => "ACCEPT".to_owned(),
+            Target::Drop => "DROP".to_owned(),
+            Target::Reject => "REJECT".to_owned(),
+            Target::Return => "RETURN".to_owned(),
+            Target::Masquerade => "MASQUERADE".to_owned(),
+            Target::Dnat { ref to_destination } => {
+                format!("DNAT --to-destination {}", to_destination)
+            }
+            Target::Jump { ref chain } => chain.to_owned(),
+        }
+    }
+}
+
+/// What caused DFW to generate a rule.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "origin", rename_all = "snake_case")]
+pub enum Provenance {
+    /// DFW sets up the rule itself, e.g. the jumps into the chains owned by DFW.
+    Setup,
+    /// The `default_policy` of a section of the configuration.
+    DefaultPolicy {
+        /// Name of the section, e.g. `container_to_wider_world`.
+        section: String,
+    },
+    /// A rule of the configuration.
+    Rule {
+        /// Name of the section, e.g. `container_to_wider_world`.
+        section: String,
+        /// Index of the rule within the section.
+        index: usize,
+    },
+}
+
+impl Matches {
+    /// Render the matches as `iptables` arguments.
+    pub fn to_iptables(&self) -> Result<String> {
+        let mut args: Vec<String> = Vec::new();
+
+        if let Some(ref source) = self.source {
+            args.push("-s".to_owned());
+            args.push(source.to_owned());
+        }
+        if let Some(ref destination) = self.destination {
+            args.push("-d".to_owned());
+            args.push(destination.to_owned());
+        }
+        if let Some(ref in_interface) = self.in_interface {
+            if in_interface.negated {
+                args.push("!".to_owned());
+            }
+            args.push("-i".to_owned());
+            args.push(in_interface.name.to_owned());
+        }
+        if let Some(ref out_interface) = self.out_interface {
+            if out_interface.negated {
+                args.push("!".to_owned());
+            }
+            args.push("-o".to_owned());
+            args.push(out_interface.name.to_owned());
+        }
+
+        if let Some(ref protocol) = self.protocol {
+            args.push("-p".to_owned());
+            args.push(protocol.to_owned());
+        } else if self.source_port.is_some() || self.destination_port.is_some() {
+            // Source and destination ports require that the protocol is set.
+            // If it hasn't been specified explicitly, use "tcp" as default.
+            args.push("-p".to_owned());
+            args.push("tcp".to_owned());
+        }
+
+        if let Some(ref source_port) = self.source_port {
+            args.push("--sport".to_owned());
+            args.push(source_port.to_owned());
+        }
+
+        if let Some(ref destination_port) = self.destination_port {
+            args.push("--dport".to_owned());
+            args.push(destination_port.to_owned());
+        }
+
+        if !self.state.is_empty() {
+            args.push("-m state --state".to_owned());
+            args.push(self.state.join(","));
+        }
+
+        if let Some(ref filter) = self.filter {
+            args.push(filter.to_owned());
+        }
+
+        if let Some(ref schedule) = self.schedule {
+            args.push(
+                schedule
+                    .to_iptables()
+                    .map_err(|e| format_err!("invalid schedule: {}", e))?,
+            );
+        }
+
+        Ok(args.join(" "))
+    }
+
+    /// Whether none of the matches is set, i.e. every packet matches.
+    pub fn is_empty(&self) -> bool {
+        *self == Matches::default()
+    }
+}
+
+impl Rule {
+    /// Render the rule as the `iptables` arguments following the chain, e.g.
+    /// `-i docker0 -j ACCEPT`.
+    pub fn to_iptables(&self) -> Result<String> {
+        let mut args = vec![];
+        let matches = self.matches.to_iptables()?;
+        if !matches.is_empty() {
+            args.push(matches);
+        }
+        args.push("-j".to_owned());
+        args.push(self.target.to_iptables());
+        if let Some(ref comment) = self.matches.comment {
+            args.push("-m comment --comment".to_owned());
+            args.push(format!("\"{}\"", comment));
+        }
+
+        Ok(args.join(" "))
+    }
+}
+
+impl Ruleset {
+    /// Create a new, empty ruleset.
+    pub fn new() -> Ruleset {
+        Default::default()
+    }
+
+    /// Get the rules of the ruleset.
+    pub fn rules(&self) -> Vec<&Rule> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match *entry {
+                Entry::Rule(ref rule) => Some(&**rule),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Deserialize a `Ruleset` from a JSON string.
+    pub fn from_json(json: &str) -> Result<Ruleset> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Serialize the `Ruleset` into a pretty-printed JSON string.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Apply the entries of the ruleset to the backends, in order.
+    ///
+    /// This does not commit the rules, see
+    /// [`IPTables::commit`](../iptables/trait.IPTables.html#tymethod.commit).
+    pub fn apply(&self, ipt4: &IPTables, ipt6: &IPTables, logger: &Logger) -> Result<()> {
+        let backend = |family: Family| match family {
+            Family::IPv4 => ipt4,
+            Family::IPv6 => ipt6,
+        };
+
+        for entry in &self.entries {
+            match *entry {
+                Entry::Chain(ref chain) => {
+                    let ipt = backend(chain.family);
+                    ipt.new_chain(&chain.table, &chain.name)?;
+                    ipt.flush_chain(&chain.table, &chain.name)?;
+                }
+                Entry::Rule(ref rule) => {
+                    let ipt = backend(rule.family);
+                    let rule_str = rule.to_iptables()?;
+                    match rule.placement {
+                        Placement::Append => ipt.append(&rule.table, &rule.chain, &rule_str)?,
+                        Placement::AppendUnique => {
+                            ipt.append_replace(&rule.table, &rule.chain, &rule_str)?
+                        }
+                        Placement::InsertUnique { position } => {
+                            ipt.insert_unique(&rule.table, &rule.chain, &rule_str, position)?
+                        }
+                    };
+                }
+                Entry::Command(ref command) => {
+                    let out = backend(command.family).execute(&command.table, &command.command)?;
+                    trace!(logger, "Command executed";
+                           o!("family" => format!("{:?}", command.family),
+                              "table" => &command.table,
+                              "command" => &command.command,
+                              "status" => out.status.code(),
+                              "stdout" => String::from_utf8_lossy(&out.stdout).into_owned(),
+                              "stderr" => String::from_utf8_lossy(&out.stderr).into_owned()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a line is missing from or superfluous in the installed rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The line is part of the ruleset, but not installed.
+    Added,
+    /// The line is installed, but not part of the ruleset.
+    Removed,
+}
+
+/// A difference between the installed rules and a ruleset, see
+/// [`Ruleset::diff`](struct.Ruleset.html#method.diff).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// IP family of the changed line.
+    pub family: Family,
+    /// Table of the changed line.
+    pub table: String,
+    /// Whether the line is added or removed.
+    pub kind: ChangeKind,
+    /// The changed line in the `iptables-save` format, e.g. `-A DFWRS_INPUT -i docker0 -j ACCEPT`
+    /// for a rule or `:DFWRS_INPUT` for a chain.
+    pub line: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = match self.kind {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+        };
+        write!(f, "{} {}", sign, self.line)
+    }
+}
+
+/// Chains and rules of a table, in the form of `iptables-save`.
+#[derive(Debug, Default)]
+struct TableState {
+    chains: BTreeSet<String>,
+    rules: BTreeMap<String, Vec<SavedRule>>,
+}
+
+impl TableState {
+    /// Get the state of a table installed on the host, without the counters of the rules.
+    fn installed(table: &SavedTable) -> TableState {
+        let mut state = TableState::default();
+        state
+            .chains
+            .extend(table.chains.iter().map(|chain| chain.name.to_owned()));
+        for rule in &table.rules {
+            state
+                .rules
+                .entry(rule.chain.to_owned())
+                .or_default()
+                .push(SavedRule {
+                    counters: None,
+                    ..rule.clone()
+                });
+        }
+
+        state
+    }
+
+    /// Get the rules within the owned chains and the rules jumping into them, by chain.
+    fn owned_rules(&self, owned: &BTreeSet<String>) -> BTreeMap<String, Vec<SavedRule>> {
+        self.rules
+            .iter()
+            .map(|(chain, rules)| {
+                let rules = rules
+                    .iter()
+                    .filter(|rule| {
+                        owned.contains(chain)
+                            || rule.target().is_some_and(|target| owned.contains(target))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (chain.to_owned(), rules)
+            })
+            .filter(|(_, rules)| !rules.is_empty())
+            .collect()
+    }
+}
+
+impl Ruleset {
+    /// Compare the rules of the family with the rules installed on the host, as parsed from the
+    /// output of `iptables-save` (or `ip6tables-save` for IPv6).
+    ///
+    /// Only the chains owned by DFW, i.e. the chains of the ruleset and the installed chains
+    /// prefixed with `DFWRS_`, and the rules jumping into these chains are compared. The commands
+    /// of the `initialization` section are not part of the comparison.
+    ///
+    /// The rules are normalized before they are compared (see
+    /// [`normalize_rule`](../iptables/fn.normalize_rule.html)), e.g. `-s 172.18.0.2` is compared
+    /// as `-s 172.18.0.2/32`, as printed by `iptables-save`.
+    pub fn diff(&self, family: Family, installed: &IPTablesSave) -> Result<Vec<Change>> {
+        let desired = self.table_states(family)?;
+        let installed = installed
+            .tables
+            .iter()
+            .map(|table| (table.name.to_owned(), TableState::installed(table)))
+            .collect::<BTreeMap<_, _>>();
+
+        let tables = desired
+            .keys()
+            .chain(installed.keys())
+            .collect::<BTreeSet<_>>();
+        let empty = TableState::default();
+        let mut changes = Vec::new();
+        for table in tables {
+            let desired = desired.get(table).unwrap_or(&empty);
+            let installed = installed.get(table).unwrap_or(&empty);
+            let mut change = |kind, line: String| {
+                changes.push(Change {
+                    family: family,
+                    table: table.to_owned(),
+                    kind: kind,
+                    line: line,
+                })
+            };
+
+            let owned = desired
+                .chains
+                .iter()
+                .chain(
+                    installed
+                        .chains
+                        .iter()
+                        .filter(|chain| chain.starts_with(CHAIN_PREFIX)),
+                )
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            for chain in &owned {
+                match (
+                    desired.chains.contains(chain),
+                    installed.chains.contains(chain),
+                ) {
+                    (true, false) => change(ChangeKind::Added, format!(":{}", chain)),
+                    (false, true) => change(ChangeKind::Removed, format!(":{}", chain)),
+                    _ => {}
+                }
+            }
+
+            let desired_rules = desired.owned_rules(&owned);
+            let installed_rules = installed.owned_rules(&owned);
+            let chains = desired_rules
+                .keys()
+                .chain(installed_rules.keys())
+                .collect::<BTreeSet<_>>();
+            let no_rules = Vec::new();
+            for chain in chains {
+                let desired_rules = desired_rules.get(chain).unwrap_or(&no_rules);
+                let installed_rules = installed_rules.get(chain).unwrap_or(&no_rules);
+                for result in diff::slice(installed_rules, desired_rules) {
+                    match result {
+                        diff::Result::Left(rule) => change(ChangeKind::Removed, rule.to_string()),
+                        diff::Result::Right(rule) => change(ChangeKind::Added, rule.to_string()),
+                        diff::Result::Both(..) => {}
+                    }
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Get the chains and rules of the family as `iptables-save` would print them once the
+    /// ruleset is applied.
+    fn table_states(&self, family: Family) -> Result<BTreeMap<String, TableState>> {
+        let mut tables: BTreeMap<String, TableState> = BTreeMap::new();
+        for entry in &self.entries {
+            match *entry {
+                Entry::Chain(ref chain) if chain.family == family => {
+                    let table = tables.entry(chain.table.to_owned()).or_default();
+                    table.chains.insert(chain.name.to_owned());
+                    // Applying the chain flushes it.
+                    table.rules.remove(&chain.name);
+                }
+                Entry::Rule(ref rule) if rule.family == family => {
+                    let saved_rule = SavedRule::new(&rule.chain, &rule.to_iptables()?);
+                    let rules = tables
+                        .entry(rule.table.to_owned())
+                        .or_default()
+                        .rules
+                        .entry(rule.chain.to_owned())
+                        .or_default();
+                    match rule.placement {
+                        Placement::Append => rules.push(saved_rule),
+                        Placement::AppendUnique => {
+                            if !rules.contains(&saved_rule) {
+                                rules.push(saved_rule);
+                            }
+                        }
+                        Placement::InsertUnique { position } => {
+                            if !rules.contains(&saved_rule) {
+                                let index = (position.max(1) as usize - 1).min(rules.len());
+                                rules.insert(index, saved_rule);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(tables)
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
@@ -0,0 +1,313 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module provides a [JSON Schema][json-schema] of the configuration.
+//!
+//! The schema describes [`types::DFW`](../types/struct.DFW.html) and all nested types, as well as
+//! the `definitions` section (see the [`definitions`](../definitions/index.html) module). It can be
+//! used by editors and CI to validate configurations written in TOML, YAML or JSON before they are
+//! handed to DFW. The schema is printed by `dfw schema`.
+//!
+//! The schema describes a complete configuration. The files of a configuration directory (see
+//! [`util::load_path`](../util/fn.load_path.html)) are only valid once merged, e.g. a file might
+//! add rules to a section whose `default_policy` is set by another file.
+//!
+//! [json-schema]: https://json-schema.org/
+
+use serde_json::Value;
+
+/// Create the JSON Schema (draft-07) for the DFW configuration.
+pub fn dfw_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "DFW configuration",
+        "description": "Configuration of DFW, the Docker firewall framework. Every section is \
+                        optional.",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "definitions": { "$ref": "#/definitions/Definitions" },
+            "defaults": { "$ref": "#/definitions/Defaults" },
+            "initialization": { "$ref": "#/definitions/Initialization" },
+            "container_to_container": { "$ref": "#/definitions/ContainerToContainer" },
+            "container_to_wider_world": { "$ref": "#/definitions/ContainerToWiderWorld" },
+            "container_to_host": { "$ref": "#/definitions/ContainerToHost" },
+            "wider_world_to_container": { "$ref": "#/definitions/WiderWorldToContainer" },
+            "container_dnat": { "$ref": "#/definitions/ContainerDNAT" }
+        },
+        "definitions": {
+            "Definitions": {
+                "description": "Reusable values, referenced as `@<name>` or `@{<kind>.<name>}`.",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "ports": { "$ref": "#/definitions/DefinitionValues" },
+                    "addresses": { "$ref": "#/definitions/DefinitionValues" },
+                    "interfaces": { "$ref": "#/definitions/DefinitionValues" },
+                    "networks": { "$ref": "#/definitions/DefinitionValues" },
+                    "containers": { "$ref": "#/definitions/DefinitionValues" }
+                }
+            },
+            "DefinitionValues": {
+                "type": "object",
+                "additionalProperties": {
+                    "anyOf": [
+                        { "$ref": "#/definitions/DefinitionValue" },
+                        { "type": "array", "items": { "$ref": "#/definitions/DefinitionValue" } }
+                    ]
+                }
+            },
+            "DefinitionValue": {
+                "description": "A single value, or a port in any form accepted by `expose_port`.",
+                "anyOf": [
+                    { "type": ["string", "integer"] },
+                    { "$ref": "#/definitions/ExposePort" }
+                ]
+            },
+            "Defaults": {
+                "description": "The default configuration section, used by DFW for rule \
+                                processing.",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "external_network_interfaces": {
+                        "description": "External network interfaces of the host to consider \
+                                        during building the rules.",
+                        "anyOf": [
+                            { "type": "null" },
+                            { "type": "string" },
+                            { "type": "array", "items": { "type": "string" } }
+                        ]
+                    }
+                }
+            },
+            "Initialization": {
+                "description": "Custom rules to add to any table in iptables and ip6tables.",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "v4": { "$ref": "#/definitions/InitializationRules" },
+                    "v6": { "$ref": "#/definitions/InitializationRules" }
+                }
+            },
+            "InitializationRules": {
+                "description": "Rules to add, keyed by the table, e.g. `filter` or `nat`.",
+                "type": "object",
+                "additionalProperties": { "type": "array", "items": { "type": "string" } }
+            },
+            "ContainerToContainer": {
+                "description": "Defines how containers can communicate amongst each other.",
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["default_policy"],
+                "properties": {
+                    "default_policy": { "$ref": "#/definitions/Policy" },
+                    "rules": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/ContainerToContainerRule" }
+                    }
+                }
+            },
+            "ContainerToContainerRule": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["network", "action"],
+                "properties": {
+                    "network": {
+                        "description": "Common network between the source container and the \
+                                        destination container to apply the rule to.",
+                        "type": "string"
+                    },
+                    "src_container": { "type": "string" },
+                    "dst_container": { "type": "string" },
+                    "filter": { "$ref": "#/definitions/Filter" },
+                    "action": { "$ref": "#/definitions/Action" },
+                    "schedule": { "$ref": "#/definitions/Schedule" },
+                    "priority": { "$ref": "#/definitions/Priority" }
+                }
+            },
+            "ContainerToWiderWorld": {
+                "description": "Defines how containers can communicate with the wider world.",
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["default_policy"],
+                "properties": {
+                    "default_policy": { "$ref": "#/definitions/Policy" },
+                    "rules": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/ContainerToWiderWorldRule" }
+                    }
+                }
+            },
+            "ContainerToWiderWorldRule": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["action"],
+                "properties": {
+                    "network": { "type": "string" },
+                    "src_container": { "type": "string" },
+                    "filter": { "$ref": "#/definitions/Filter" },
+                    "action": { "$ref": "#/definitions/Action" },
+                    "external_network_interface": { "type": "string" },
+                    "schedule": { "$ref": "#/definitions/Schedule" },
+                    "priority": { "$ref": "#/definitions/Priority" }
+                }
+            },
+            "ContainerToHost": {
+                "description": "Defines how containers can communicate with the host.",
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["default_policy"],
+                "properties": {
+                    "default_policy": { "$ref": "#/definitions/Policy" },
+                    "rules": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/ContainerToHostRule" }
+                    }
+                }
+            },
+            "ContainerToHostRule": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["network", "action"],
+                "properties": {
+                    "network": { "type": "string" },
+                    "src_container": { "type": "string" },
+                    "filter": { "$ref": "#/definitions/Filter" },
+                    "action": { "$ref": "#/definitions/Action" },
+                    "schedule": { "$ref": "#/definitions/Schedule" },
+                    "priority": { "$ref": "#/definitions/Priority" }
+                }
+            },
+            "WiderWorldToContainer": {
+                "description": "Defines how containers can be reached from the wider world.",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "rules": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/WiderWorldToContainerRule" }
+                    }
+                }
+            },
+            "WiderWorldToContainerRule": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["network", "dst_container", "expose_port"],
+                "properties": {
+                    "network": { "type": "string" },
+                    "dst_container": { "type": "string" },
+                    "expose_port": { "$ref": "#/definitions/ExposePorts" },
+                    "external_network_interface": { "type": "string" },
+                    "schedule": { "$ref": "#/definitions/Schedule" },
+                    "priority": { "$ref": "#/definitions/Priority" }
+                }
+            },
+            "ContainerDNAT": {
+                "description": "Defines how containers can communicate with each other over \
+                                non-common networks.",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "rules": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/ContainerDNATRule" }
+                    }
+                }
+            },
+            "ContainerDNATRule": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["dst_network", "dst_container", "expose_port"],
+                "properties": {
+                    "src_network": { "type": "string" },
+                    "src_container": { "type": "string" },
+                    "dst_network": { "type": "string" },
+                    "dst_container": { "type": "string" },
+                    "expose_port": { "$ref": "#/definitions/ExposePorts" },
+                    "schedule": { "$ref": "#/definitions/Schedule" },
+                    "priority": { "$ref": "#/definitions/Priority" }
+                }
+            },
+            "ExposePorts": {
+                "description": "Ports to apply the rule to: a single port or a list of ports.",
+                "anyOf": [
+                    { "$ref": "#/definitions/ExposePort" },
+                    { "type": "array", "items": { "$ref": "#/definitions/ExposePort" } }
+                ]
+            },
+            "ExposePort": {
+                "description": "A port given as integer (`80`), as string (`\"53/udp\"`) or as \
+                                struct.",
+                "anyOf": [
+                    { "$ref": "#/definitions/Port" },
+                    { "type": "string" },
+                    {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["host_port"],
+                        "properties": {
+                            "host_port": { "$ref": "#/definitions/Port" },
+                            "container_port": { "$ref": "#/definitions/Port" },
+                            "family": {
+                                "description": "Family of the exposed port, `tcp` by default.",
+                                "type": "string"
+                            }
+                        }
+                    }
+                ]
+            },
+            "Schedule": {
+                "description": "Restricts a rule to certain days of the week and/or a time of \
+                                day.",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "days": {
+                        "description": "Days of the week, e.g. `Mon`, `Monday` or `Mon-Fri`.",
+                        "anyOf": [
+                            { "type": "string" },
+                            { "type": "array", "items": { "type": "string" } }
+                        ]
+                    },
+                    "time": {
+                        "description": "Time of day, formatted as `HH:MM-HH:MM`.",
+                        "type": "string"
+                    },
+                    "timezone": {
+                        "description": "Timezone to interpret the schedule in, `utc` (default) \
+                                        or `local`.",
+                        "type": "string"
+                    }
+                }
+            },
+            "Port": { "type": "integer", "minimum": 0, "maximum": 65535 },
+            "Priority": {
+                "description": "Priority of the rule within its chain, lower priorities are \
+                                generated first. Defaults to `0`.",
+                "type": "integer",
+                "minimum": -2147483648,
+                "maximum": 2147483647
+            },
+            "Policy": {
+                "description": "The default for when there is not a specific rule, e.g. \
+                                `ACCEPT` or `DROP`.",
+                "type": "string"
+            },
+            "Action": {
+                "description": "Action to take, e.g. `ACCEPT`, `DROP` or `REJECT`.",
+                "type": "string"
+            },
+            "Filter": {
+                "description": "Additional filter, which will be added to the iptables \
+                                command.",
+                "type": "string"
+            }
+        }
+    })
+}
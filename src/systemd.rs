@@ -0,0 +1,78 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module implements the parts of the systemd [notification protocol][sd_notify] DFW uses
+//! when running as a service with `Type=notify`.
+//!
+//! Notifications are sent to the socket systemd passes in the `NOTIFY_SOCKET` environment
+//! variable. If the variable is not set, i.e. DFW is not started by systemd, sending a
+//! notification does nothing.
+//!
+//! [sd_notify]: https://www.freedesktop.org/software/systemd/man/sd_notify.html
+
+use errors::*;
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+/// Tells systemd that the start-up is finished.
+pub const READY: &'static str = "READY=1";
+/// Tells systemd that the service is shutting down.
+pub const STOPPING: &'static str = "STOPPING=1";
+/// Keep-alive ping for the systemd watchdog.
+pub const WATCHDOG: &'static str = "WATCHDOG=1";
+
+/// Send the newline-separated `state` assignments (e.g. `READY=1`) to systemd.
+///
+/// Returns `false` if `NOTIFY_SOCKET` is not set and no notification was sent.
+///
+/// Sockets in the abstract namespace (`NOTIFY_SOCKET` starting with `@`) are not supported.
+pub fn notify(state: &str) -> Result<bool> {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return Ok(false),
+    };
+    if socket.to_string_lossy().starts_with('@') {
+        bail!(
+            "notification socket `{}` is in the abstract namespace, which is not supported",
+            socket.to_string_lossy()
+        );
+    }
+
+    let datagram = UnixDatagram::unbound()?;
+    datagram.send_to(state.as_bytes(), &socket)?;
+
+    Ok(true)
+}
+
+/// Create a `STATUS=` assignment for [`notify`](fn.notify.html), describing the state of the
+/// service in a single line.
+pub fn status(status: &str) -> String {
+    format!("STATUS={}", status.replace('\n', " "))
+}
+
+/// Get the interval in which the watchdog has to be pinged with
+/// [`WATCHDOG`](constant.WATCHDOG.html), or `None` if the watchdog is not enabled for this
+/// process.
+///
+/// The interval is half of the timeout systemd passes in `WATCHDOG_USEC`, as recommended by
+/// `sd_watchdog_enabled(3)`.
+pub fn watchdog_interval() -> Option<Duration> {
+    // `WATCHDOG_PID` is optional, but if it is set the watchdog is meant for that process only.
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    match env::var("WATCHDOG_USEC").ok()?.parse::<u64>() {
+        Ok(usec) if usec > 0 => Some(Duration::from_micros(usec / 2)),
+        _ => None,
+    }
+}
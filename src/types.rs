@@ -55,6 +55,15 @@
 //! dst_container = "container_c"
 //! expose_port = { host_port = 8080, container_port = 80, family = "tcp" }
 //! ```
+//!
+//! # Priorities and schedules
+//!
+//! Every rule can have a `priority`. Within each chain, rules with a lower priority are generated
+//! before rules with a higher priority, regardless of their section. Rules with the same priority
+//! keep their order, i.e. the order of the sections and the order within the file.
+//!
+//! Every rule can also have a `schedule`, restricting it to certain days of the week and/or a
+//! time of day, see [`Schedule`](struct.Schedule.html).
 
 use serde::de::{self, Deserialize, DeserializeSeed, Deserializer};
 use std::collections::HashMap as Map;
@@ -68,7 +77,7 @@ const DEFAULT_PROTOCOL: &'static str = "tcp";
 /// firewall rules.
 ///
 /// Every section is optional.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct DFW {
     /// The `defaults` configuration section
@@ -88,7 +97,7 @@ pub struct DFW {
 }
 
 /// The default configuration section, used by DFW for rule processing.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct Defaults {
     /// This defines the external network interfaces of the host to consider during building the
@@ -123,7 +132,7 @@ pub struct Defaults {
 ///     # ...
 /// ]
 /// ```
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct Initialization {
     /// Initialization rules for iptables (IPv4). Expects a map where the key is a specific table
@@ -157,7 +166,7 @@ pub struct Initialization {
 }
 
 /// The container-to-container section, defining how containers can communicate amongst each other.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToContainer {
     /// The `default_policy` defines the default for when there is not a specific rule.
@@ -182,11 +191,14 @@ pub struct ContainerToContainer {
 }
 
 /// Definition for a rule to be used in the container-to-container section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToContainerRule {
     /// Common network between the source container and the destination container to apply the rule
     /// to.
+    ///
+    /// Overlay networks are not supported, the traffic between their containers does not pass the
+    /// firewall of the host.
     pub network: String,
     /// Source container to apply the rule to.
     pub src_container: Option<String>,
@@ -196,11 +208,15 @@ pub struct ContainerToContainerRule {
     pub filter: Option<String>,
     /// Action to take (i.e. `ACCEPT`, `DROP`, `REFUSE`).
     pub action: String,
+    /// Schedule restricting when the rule is active.
+    pub schedule: Option<Schedule>,
+    /// Priority of the rule, defaults to `0`.
+    pub priority: Option<i32>,
 }
 
 /// The container-to-wider-world section, defining how containers can communicate with the wider
 /// world.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToWiderWorld {
     /// The `default_policy` defines the default for when there is not a specific rule.
@@ -225,12 +241,15 @@ pub struct ContainerToWiderWorld {
 }
 
 /// Definition for a rule to be used in the container-to-wider-world section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToWiderWorldRule {
     /// Network of the source container to apply the rule to.
     pub network: Option<String>,
     /// Source container to apply the rule to.
+    ///
+    /// This can also be the name of a Docker swarm service, applying the rule to every task of the
+    /// service running on this node.
     pub src_container: Option<String>,
     /// Additional filter, which will be added to the iptables command.
     pub filter: Option<String>,
@@ -238,10 +257,14 @@ pub struct ContainerToWiderWorldRule {
     pub action: String,
     /// Specific external network interface to target.
     pub external_network_interface: Option<String>,
+    /// Schedule restricting when the rule is active.
+    pub schedule: Option<Schedule>,
+    /// Priority of the rule, defaults to `0`.
+    pub priority: Option<i32>,
 }
 
 /// The container-to-host section, defining how containers can communicate with the host.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToHost {
     /// The `default_policy` defines the default for when there is not a specific rule.
@@ -266,21 +289,28 @@ pub struct ContainerToHost {
 }
 
 /// Definition for a rule to be used in the container-to-host section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToHostRule {
     /// Network of the source container to apply the rule to.
     pub network: String,
     /// Source container to apply the rule to.
+    ///
+    /// This can also be the name of a Docker swarm service, applying the rule to every task of the
+    /// service running on this node.
     pub src_container: Option<String>,
     /// Additional filter, which will be added to the iptables command.
     pub filter: Option<String>,
     /// Action to take (i.e. `ACCEPT`, `DROP`, `REFUSE`).
     pub action: String,
+    /// Schedule restricting when the rule is active.
+    pub schedule: Option<Schedule>,
+    /// Priority of the rule, defaults to `0`.
+    pub priority: Option<i32>,
 }
 
 /// The wider-world-to-container section, defining how containers can reached from the wider world.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct WiderWorldToContainer {
     /// An optional list of rules, see
@@ -303,13 +333,20 @@ pub struct WiderWorldToContainer {
 }
 
 /// Definition for a rule to be used in the wider-world-to-container section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct WiderWorldToContainerRule {
     /// Network of the destination container to apply the rule to.
+    ///
+    /// With the `ingress` network of Docker swarm, the rule exposes the published ports of the
+    /// swarm service given in `dst_container` through the routing mesh. Published ports of
+    /// services that aren't exposed by a rule are dropped.
     pub network: String,
 
     /// Destination container to apply the rule to.
+    ///
+    /// This can also be the name of a Docker swarm service that has a single task running on this
+    /// node.
     pub dst_container: String,
 
     /// Ports to apply the rule to.
@@ -355,10 +392,16 @@ pub struct WiderWorldToContainerRule {
 
     /// Specific external network interface to target.
     pub external_network_interface: Option<String>,
+
+    /// Schedule restricting when the rule is active.
+    pub schedule: Option<Schedule>,
+
+    /// Priority of the rule, defaults to `0`.
+    pub priority: Option<i32>,
 }
 
 /// Struct to hold a port definition to expose on the host/between containers.
-#[derive(Deserialize, Debug, Clone, Default, Builder, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Builder, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ExposePort {
     /// Port the `container_port` should be exposed to on the host.
@@ -429,7 +472,7 @@ impl FromStr for ExposePort {
 
 /// The container-DNAT section, defining how containers can communicate with each other over
 /// non-common networks.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerDNAT {
     /// An optional list of rules, see
@@ -452,7 +495,7 @@ pub struct ContainerDNAT {
 }
 
 /// Definition for a rule to be used in the container-DNAT section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerDNATRule {
     /// Network of the source container to apply the rule to.
@@ -507,6 +550,279 @@ pub struct ContainerDNATRule {
     /// ```
     #[serde(deserialize_with = "single_or_seq_string_or_struct")]
     pub expose_port: Vec<ExposePort>,
+
+    /// Schedule restricting when the rule is active.
+    pub schedule: Option<Schedule>,
+
+    /// Priority of the rule, defaults to `0`.
+    pub priority: Option<i32>,
+}
+
+/// Restricts a rule to certain days of the week and/or a time of day, e.g. a maintenance window.
+///
+/// A rule with a schedule only matches while the schedule is active. It is rendered as the
+/// iptables `time` match, see [`to_iptables`](#method.to_iptables), or as nftables `meta day` and
+/// `meta hour` expressions, see [`to_nft`](#method.to_nft).
+///
+/// # Example
+///
+/// ```toml
+/// schedule = { days = "Mon-Fri", time = "08:00-17:00" }
+/// schedule = { days = ["Sat", "Sun"], time = "22:00-02:00", timezone = "local" }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// Days of the week the rule is active on, either a single string or a list of strings.
+    ///
+    /// Days are given by name, abbreviated (`Mon`) or in full (`Monday`), or as a range of days
+    /// (`Mon-Fri`). If omitted, the rule is active on every day.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    pub days: Option<Vec<String>>,
+
+    /// Time of day the rule is active in, formatted as `HH:MM-HH:MM` (seconds can be added as
+    /// `HH:MM:SS`). If omitted, the rule is active the whole day.
+    ///
+    /// If the start is after the end, the range spans midnight, e.g. `22:00-02:00`. Combined with
+    /// `days`, such a range starts on the given days and ends on the following day.
+    pub time: Option<String>,
+
+    /// Timezone the `time` and `days` are interpreted in, either `utc` or `local` (the timezone of
+    /// the kernel). Defaults to `utc`.
+    pub timezone: Option<String>,
+}
+
+const WEEKDAYS: [(&'static str, &'static str); 7] = [
+    ("Mon", "Monday"),
+    ("Tue", "Tuesday"),
+    ("Wed", "Wednesday"),
+    ("Thu", "Thursday"),
+    ("Fri", "Friday"),
+    ("Sat", "Saturday"),
+    ("Sun", "Sunday"),
+];
+
+impl Schedule {
+    /// Render the schedule as arguments for the iptables `time` match.
+    ///
+    /// The arguments are rendered the way `iptables-save` prints them, with the times as
+    /// `HH:MM:SS`, so that the installed rules can be compared with the rendered ones.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::Schedule;
+    /// let schedule = Schedule {
+    ///     days: Some(vec!["Mon-Fri".to_owned()]),
+    ///     time: Some("08:00-17:00".to_owned()),
+    ///     timezone: None,
+    /// };
+    /// assert_eq!(
+    ///     schedule.to_iptables().unwrap(),
+    ///     "-m time --timestart 08:00:00 --timestop 17:00:00 --weekdays Mon,Tue,Wed,Thu,Fri"
+    /// );
+    /// ```
+    pub fn to_iptables(&self) -> Result<String, String> {
+        let days = self.weekdays()?;
+        let time = self.time_range()?;
+        let local = self.local_timezone()?;
+
+        let mut args = vec!["-m".to_owned(), "time".to_owned()];
+        if let Some((ref start, ref stop)) = time {
+            args.push("--timestart".to_owned());
+            args.push(with_seconds(start));
+            args.push("--timestop".to_owned());
+            args.push(with_seconds(stop));
+        }
+        if let Some(ref days) = days {
+            args.push("--weekdays".to_owned());
+            args.push(
+                days.iter()
+                    .map(|&day| WEEKDAYS[day].0)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        if local {
+            args.push("--kerneltz".to_owned());
+        }
+        // Without `--contiguous` a range spanning midnight would also end on the given days.
+        if let (Some(_), Some((ref start, ref stop))) = (&days, &time) {
+            if with_seconds(start) > with_seconds(stop) {
+                args.push("--contiguous".to_owned());
+            }
+        }
+
+        Ok(args.join(" "))
+    }
+
+    /// Render the schedule as nftables expressions, to be used by nftables-style backends.
+    ///
+    /// `nft` interprets the times in the timezone it is run in, it has to be run with `TZ=UTC` for
+    /// a schedule in UTC. A range spanning midnight can't be combined with `days`, nftables has no
+    /// equivalent to `--contiguous`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::Schedule;
+    /// let schedule = Schedule {
+    ///     days: Some(vec!["Sat".to_owned(), "Sun".to_owned()]),
+    ///     time: Some("08:00-17:00".to_owned()),
+    ///     timezone: None,
+    /// };
+    /// assert_eq!(
+    ///     schedule.to_nft().unwrap(),
+    ///     "meta day { \"Saturday\", \"Sunday\" } meta hour \"08:00\"-\"17:00\""
+    /// );
+    /// ```
+    pub fn to_nft(&self) -> Result<String, String> {
+        let days = self.weekdays()?;
+        let time = self.time_range()?;
+        self.local_timezone()?;
+
+        let mut expressions = Vec::new();
+        if let Some(ref days) = days {
+            let days: Vec<String> = days
+                .iter()
+                .map(|&day| format!("\"{}\"", WEEKDAYS[day].1))
+                .collect();
+            expressions.push(match days.len() {
+                1 => format!("meta day {}", days[0]),
+                _ => format!("meta day {{ {} }}", days.join(", ")),
+            });
+        }
+        if let Some((start, stop)) = time {
+            if start > stop {
+                if days.is_some() {
+                    return Err(
+                        "a time range spanning midnight can't be combined with `days` in nftables"
+                            .to_owned(),
+                    );
+                }
+                // Match everything outside of the inverted range.
+                expressions.push(format!("meta hour != \"{}\"-\"{}\"", stop, start));
+            } else {
+                expressions.push(format!("meta hour \"{}\"-\"{}\"", start, stop));
+            }
+        }
+
+        Ok(expressions.join(" "))
+    }
+
+    /// Parse the days into indexes of `WEEKDAYS`, sorted and without duplicates.
+    fn weekdays(&self) -> Result<Option<Vec<usize>>, String> {
+        let days = match self.days {
+            Some(ref days) => days,
+            None => return self.check_not_empty().map(|_| None),
+        };
+        if days.is_empty() {
+            return Err("`days` must not be empty".to_owned());
+        }
+
+        let mut weekdays = [false; 7];
+        for day in days {
+            let mut split = day.splitn(2, '-');
+            let first = parse_weekday(split.next().unwrap_or_default())?;
+            match split.next() {
+                Some(last) => {
+                    // Ranges can wrap around the end of the week, e.g. `Fri-Mon`.
+                    let last = parse_weekday(last)?;
+                    let mut day = first;
+                    loop {
+                        weekdays[day] = true;
+                        if day == last {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                }
+                None => weekdays[first] = true,
+            }
+        }
+
+        Ok(Some((0..7).filter(|&day| weekdays[day]).collect()))
+    }
+
+    /// Parse the time into its normalized start and stop.
+    fn time_range(&self) -> Result<Option<(String, String)>, String> {
+        let time = match self.time {
+            Some(ref time) => time,
+            None => return Ok(None),
+        };
+        let mut split = time.splitn(2, '-');
+        match (split.next(), split.next()) {
+            (Some(start), Some(stop)) => {
+                let start = parse_time_of_day(start)?;
+                let stop = parse_time_of_day(stop)?;
+                if start == stop {
+                    return Err(format!("time range '{}' is empty", time));
+                }
+                Ok(Some((start, stop)))
+            }
+            _ => Err(format!(
+                "time range '{}' has invalid format, expected `HH:MM-HH:MM`",
+                time
+            )),
+        }
+    }
+
+    fn local_timezone(&self) -> Result<bool, String> {
+        match self.timezone {
+            None => Ok(false),
+            Some(ref timezone) if timezone.eq_ignore_ascii_case("utc") => Ok(false),
+            Some(ref timezone) if timezone.eq_ignore_ascii_case("local") => Ok(true),
+            Some(ref timezone) => Err(format!(
+                "unsupported timezone '{}', expected `utc` or `local`",
+                timezone
+            )),
+        }
+    }
+
+    fn check_not_empty(&self) -> Result<(), String> {
+        if self.time.is_none() {
+            return Err("schedule must define `days` or `time`".to_owned());
+        }
+        Ok(())
+    }
+}
+
+fn parse_weekday(day: &str) -> Result<usize, String> {
+    let day = day.trim();
+    WEEKDAYS
+        .iter()
+        .position(|&(short, long)| {
+            day.eq_ignore_ascii_case(short) || day.eq_ignore_ascii_case(long)
+        })
+        .ok_or_else(|| format!("unknown day of the week '{}'", day))
+}
+
+/// Parse `HH:MM` or `HH:MM:SS` and normalize it to two digits per component.
+fn parse_time_of_day(time: &str) -> Result<String, String> {
+    let time = time.trim();
+    let components = time
+        .split(':')
+        .map(|component| component.parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("time '{}' has invalid format, expected `HH:MM`", time))?;
+    match components[..] {
+        [hours, minutes] if hours < 24 && minutes < 60 => {
+            Ok(format!("{:02}:{:02}", hours, minutes))
+        }
+        [hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 => {
+            Ok(format!("{:02}:{:02}:{:02}", hours, minutes, seconds))
+        }
+        _ => Err(format!("time '{}' is not a valid time of day", time)),
+    }
+}
+
+/// Append the seconds to a time of day as `HH:MM`, iptables always prints them.
+fn with_seconds(time: &str) -> String {
+    if time.len() == 5 {
+        format!("{}:00", time)
+    } else {
+        time.to_owned()
+    }
 }
 
 fn default_expose_port_family() -> String {
@@ -532,6 +848,13 @@ where
         FromStr::from_str(&value.to_string()).map_err(de::Error::custom)
     }
 
+    fn visit_u64<E>(self, value: u64) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        FromStr::from_str(&value.to_string()).map_err(de::Error::custom)
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<T, E>
     where
         E: de::Error,
@@ -596,6 +919,15 @@ where
             .map_err(de::Error::custom)
     }
 
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        FromStr::from_str(&value.to_string())
+            .map(|e| vec![e])
+            .map_err(de::Error::custom)
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
@@ -667,5 +999,37 @@ fn option_string_or_seq_string<'de, D>(deserializer: D) -> Result<Option<Vec<Str
 where
     D: Deserializer<'de>,
 {
-    string_or_seq_string(deserializer).map(Some)
+    struct OptionStringOrSeqString(PhantomData<Option<Vec<String>>>);
+
+    impl<'de> de::Visitor<'de> for OptionStringOrSeqString {
+        type Value = Option<Vec<String>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("null, string or sequence of strings")
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_unit<E>(self) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            string_or_seq_string(deserializer).map(Some)
+        }
+    }
+
+    // YAML and JSON can explicitly set the value to null, which TOML can't.
+    deserializer.deserialize_option(OptionStringOrSeqString(PhantomData))
 }
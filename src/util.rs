@@ -8,42 +8,971 @@
 
 //! Utilities module
 
+use definitions;
 use errors::*;
 
 use glob::glob;
 use serde::de::DeserializeOwned;
+use serde_json::{self, Map as JsonMap, Value};
+use serde_path_to_error;
+use serde_yaml;
+use std::collections::HashMap as Map;
+use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use toml;
 
-/// Load single TOML-file from path and deserialize it into type `T`.
+/// Supported configuration file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// TOML, used for files ending in `.toml`.
+    Toml,
+    /// YAML, used for files ending in `.yaml` or `.yml`.
+    Yaml,
+    /// JSON, used for files ending in `.json`.
+    Json,
+}
+
+impl Format {
+    /// Determine the format of a configuration file by its extension.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::util::Format;
+    /// assert_eq!(Format::from_path("dfw.toml").unwrap(), Format::Toml);
+    /// assert_eq!(Format::from_path("dfw.yml").unwrap(), Format::Yaml);
+    /// assert_eq!(Format::from_path("dfw.json").unwrap(), Format::Json);
+    /// assert!(Format::from_path("dfw.conf").is_err());
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Format> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            Some("json") => Ok(Format::Json),
+            _ => bail!(
+                "unsupported configuration format, expected .toml, .yaml, .yml or .json: {}",
+                path.display()
+            ),
+        }
+    }
+
+    /// Deserialize the given string in this format into type `T`.
+    pub fn deserialize<T>(self, contents: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(match self {
+            Format::Toml => toml::from_str(contents)?,
+            Format::Yaml => serde_yaml::from_str(contents)?,
+            Format::Json => serde_json::from_str(contents)?,
+        })
+    }
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut contents = String::new();
+    let mut file = BufReader::new(File::open(path)?);
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Source of the configuration, either a single file or a directory of files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// A single configuration file, loaded with [`load_file`](fn.load_file.html).
+    File(String),
+    /// A directory of configuration files, loaded with [`load_path`](fn.load_path.html).
+    Path(String),
+}
+
+impl ConfigSource {
+    /// Load the configuration and deserialize it into type `T`.
+    pub fn load<T>(&self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match *self {
+            ConfigSource::File(ref file) => load_file(file),
+            ConfigSource::Path(ref path) => load_path(path),
+        }
+    }
+
+    /// Get the directory that has to be watched to notice changes of the configuration.
+    ///
+    /// For a single file this is the directory containing it, since editors commonly replace the
+    /// file instead of writing to it.
+    pub fn watch_directory(&self) -> PathBuf {
+        match *self {
+            ConfigSource::File(ref file) => match Path::new(file).parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+                _ => PathBuf::from("."),
+            },
+            ConfigSource::Path(ref path) => PathBuf::from(path),
+        }
+    }
+
+    /// Check if a change of the given path, within the [watched
+    /// directory](#method.watch_directory), affects the configuration.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::util::ConfigSource;
+    /// let source = ConfigSource::File("/etc/dfw/dfw.toml".to_owned());
+    /// assert!(source.is_affected_by("/etc/dfw/dfw.toml"));
+    /// assert!(!source.is_affected_by("/etc/dfw/.dfw.toml.swp"));
+    ///
+    /// let source = ConfigSource::Path("/etc/dfw/conf.d".to_owned());
+    /// assert!(source.is_affected_by("/etc/dfw/conf.d/web.yaml"));
+    /// assert!(!source.is_affected_by("/etc/dfw/conf.d/README.md"));
+    /// ```
+    pub fn is_affected_by<P: AsRef<Path>>(&self, changed: P) -> bool {
+        let changed = changed.as_ref();
+        match *self {
+            ConfigSource::File(ref file) => changed.file_name() == Path::new(file).file_name(),
+            ConfigSource::Path(_) => Format::from_path(changed).is_ok(),
+        }
+    }
+}
+
+/// Load single TOML-, YAML- or JSON-file from path and deserialize it into type `T`.
+///
+/// The format is chosen by the extension of the file, see
+/// [`Format::from_path`](enum.Format.html#method.from_path). References to the `definitions`
+/// of the configuration are resolved, see the [`definitions`](../definitions/index.html) module.
+///
+/// Environment variables referenced in string values as `${VAR}` or `${VAR:-default}` are
+/// replaced by their values, an unset variable without a default is an error.
+///
+/// Errors within the configuration are returned as
+/// [`ConfigError`](../errors/struct.ConfigError.html), naming the file and the offending value.
+/// The line and column are known for syntax errors and invalid values in all formats.
 pub fn load_file<T>(file: &str) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let mut contents = String::new();
-    let mut file = BufReader::new(File::open(file)?);
-    file.read_to_string(&mut contents)?;
-    Ok(toml::from_str(&contents)?)
+    let file = Path::new(file);
+    let mut value = load_value(file)?;
+    definitions::resolve(&mut value)?;
+
+    deserialize_value(value, &Map::new(), file)
 }
 
-/// Load all TOML-files from a path, concatenate their contents and deserialize the result into
-/// type `T`.
+/// Load all TOML-, YAML- and JSON-files from a path, merge their contents and deserialize the
+/// result into type `T`.
+///
+/// The files are parsed separately and merged in the order of their file names:
+///
+/// * maps (e.g. sections like `container_to_container`) are merged recursively,
+/// * the `rules` of a section and the commands of a table in the `initialization` section are
+///   appended to each other,
+/// * any other value may only be defined by multiple files if they agree on it, a single value
+///   being the same as a list containing only it (e.g. `"eth0"` and `["eth0"]`).
+///
+/// References to the `definitions` of the configuration are resolved after merging, see the
+/// [`definitions`](../definitions/index.html) module. Environment variables are interpolated
+/// for every file before merging, as described for [`load_file`](fn.load_file.html).
+///
+/// Errors are reported like for [`load_file`](fn.load_file.html), naming the file the offending
+/// value was loaded from and the index of the rule within that file. An entry of the path that
+/// cannot be read is an error as well, instead of silently leaving out its rules.
 pub fn load_path<T>(path: &str) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let mut contents = String::new();
-    for entry in glob(&format!("{}/*.toml", path)).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                let mut file = BufReader::new(File::open(path)?);
-                file.read_to_string(&mut contents)?;
+    let mut files: Vec<PathBuf> = Vec::new();
+    for extension in &["toml", "yaml", "yml", "json"] {
+        let pattern = format!("{}/*.{}", path, extension);
+        let entries =
+            glob(&pattern).map_err(|e| format_err!("invalid path `{}`: {}", path, e.msg))?;
+        for entry in entries {
+            match entry {
+                Ok(path) => files.push(path),
+                Err(e) => bail!("failed to read {}: {}", e.path().display(), e.error()),
+            }
+        }
+    }
+    files.sort();
+
+    // Remember which file defined a value, to be able to name it on conflicts.
+    let mut origins: Map<String, PathBuf> = Map::new();
+    let mut merged = Value::Object(JsonMap::new());
+    for file in files {
+        let value = load_value(&file)?;
+        merge_value(&mut merged, value, "", &file, &mut origins)?;
+    }
+
+    definitions::resolve(&mut merged)?;
+
+    deserialize_value(merged, &origins, Path::new(path))
+}
+
+/// Parse a TOML-, YAML- or JSON-file into a generic JSON value, interpolating environment
+/// variables.
+fn load_value(file: &Path) -> Result<Value> {
+    let format = Format::from_path(file)?;
+    let contents = read_file(file)?;
+    let mut value = parse_value(format, &contents).map_err(|mut e| {
+        e.source_line = e
+            .line
+            .and_then(|line| contents.lines().nth(line - 1))
+            .map(str::to_owned);
+        e.file = Some(file.to_owned());
+        *e
+    })?;
+
+    interpolate_value(&mut value, "", &|name| env::var(name).ok())
+        .map_err(|e| locate_error(*e, file, format, &contents))?;
+
+    match value {
+        Value::Object(_) => Ok(value),
+        // An empty YAML-file is deserialized as null.
+        Value::Null => Ok(Value::Object(JsonMap::new())),
+        _ => bail!("configuration is not a map: {}", file.display()),
+    }
+}
+
+/// Parse the contents of a file in the given format, keeping the position of syntax errors.
+fn parse_value(format: Format, contents: &str) -> ::std::result::Result<Value, Box<ConfigError>> {
+    let (message, position) = match format {
+        Format::Toml => match toml::from_str(contents) {
+            Ok(value) => return Ok(value),
+            Err(e) => (
+                e.to_string(),
+                e.line_col().map(|(line, column)| (line + 1, column + 1)),
+            ),
+        },
+        Format::Yaml => match serde_yaml::from_str(contents) {
+            Ok(value) => return Ok(value),
+            Err(e) => (
+                e.to_string(),
+                e.location()
+                    .map(|location| (location.line(), location.column())),
+            ),
+        },
+        Format::Json => match serde_json::from_str(contents) {
+            Ok(value) => return Ok(value),
+            Err(e) => (
+                e.to_string(),
+                if e.line() > 0 {
+                    Some((e.line(), e.column()))
+                } else {
+                    None
+                },
+            ),
+        },
+    };
+
+    Err(Box::new(ConfigError {
+        message: format!("failed to parse configuration: {}", message),
+        line: position.map(|(line, _)| line),
+        column: position.map(|(_, column)| column),
+        ..Default::default()
+    }))
+}
+
+/// Deserialize the (merged) configuration, locating the offending value on errors.
+///
+/// `origins` maps key paths to the files that defined them, `default_file` is used for values
+/// without a known origin.
+fn deserialize_value<T>(
+    value: Value,
+    origins: &Map<String, PathBuf>,
+    default_file: &Path,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let e = match serde_path_to_error::deserialize(value) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    let segments: Vec<KeySegment> = e
+        .path()
+        .iter()
+        .map(|segment| match *segment {
+            serde_path_to_error::Segment::Seq { index } => KeySegment::Index(index),
+            serde_path_to_error::Segment::Map { ref key }
+            | serde_path_to_error::Segment::Enum { variant: ref key } => {
+                KeySegment::Key(key.to_owned())
+            }
+            serde_path_to_error::Segment::Unknown => KeySegment::Key("?".to_owned()),
+        })
+        .collect();
+    let key_path = join_key_path(&segments);
+    let file = get_origin(origins, &key_path)
+        .map(|file| file.as_path())
+        .unwrap_or(default_file);
+
+    // Rules of a section can be spread over multiple files, the index of the rule has to be
+    // relative to the file it was defined in.
+    let mut local_segments = Vec::with_capacity(segments.len());
+    for (index, segment) in segments.iter().enumerate() {
+        local_segments.push(match *segment {
+            KeySegment::Index(global) => {
+                let array_path = join_key_path(&segments[..index]);
+                KeySegment::Index(
+                    (0..global)
+                        .filter(
+                            |i| match get_origin(origins, &format!("{}[{}]", array_path, i)) {
+                                Some(origin) => origin == file,
+                                None => true,
+                            },
+                        )
+                        .count(),
+                )
+            }
+            ref segment => segment.clone(),
+        });
+    }
+
+    let error = ConfigError {
+        message: e.into_inner().to_string(),
+        section: match local_segments.first() {
+            Some(KeySegment::Key(section)) => Some(section.to_owned()),
+            _ => None,
+        },
+        rule: match (local_segments.get(1), local_segments.get(2)) {
+            (Some(KeySegment::Key(key)), Some(KeySegment::Index(rule))) if key == "rules" => {
+                Some(*rule)
+            }
+            _ => None,
+        },
+        key_path: if local_segments.is_empty() {
+            None
+        } else {
+            Some(join_key_path(&local_segments))
+        },
+        ..Default::default()
+    };
+
+    match Format::from_path(file).and_then(|format| Ok((format, read_file(file)?))) {
+        Ok((format, contents)) => Err(locate_error(error, file, format, &contents).into()),
+        Err(_) => Err(ConfigError {
+            file: Some(file.to_owned()),
+            ..error
+        }
+        .into()),
+    }
+}
+
+/// Segment of the path to a value within the configuration.
+#[derive(Debug, Clone)]
+enum KeySegment {
+    Key(String),
+    Index(usize),
+}
+
+fn join_key_path(segments: &[KeySegment]) -> String {
+    let mut key_path = String::new();
+    for segment in segments {
+        match *segment {
+            KeySegment::Key(ref key) => {
+                if !key_path.is_empty() {
+                    key_path.push('.');
+                }
+                key_path.push_str(key);
+            }
+            KeySegment::Index(index) => key_path.push_str(&format!("[{}]", index)),
+        }
+    }
+    key_path
+}
+
+/// Set the file of the error and, if possible, the line and column of its key path.
+fn locate_error(error: ConfigError, file: &Path, format: Format, contents: &str) -> ConfigError {
+    let location = error.key_path.as_ref().and_then(|key_path| {
+        let keys = match format {
+            Format::Toml => toml_keys(contents),
+            Format::Yaml => yaml_keys(contents),
+            Format::Json => json_keys(contents),
+        };
+        closest_key(contents, key_path, keys)
+    });
+
+    ConfigError {
+        file: Some(file.to_owned()),
+        line: location.as_ref().map(|&(line, _, _)| line),
+        column: location.as_ref().map(|&(_, column, _)| column),
+        source_line: location.map(|(_, _, source_line)| source_line),
+        ..error
+    }
+}
+
+/// Find the key defining the value at `key_path` (or its closest parent) among the keys of a
+/// file, returning the line, the column and the line itself.
+///
+/// `keys` are given as the index of their line, their column and their key path.
+fn closest_key(
+    contents: &str,
+    key_path: &str,
+    keys: Vec<(usize, usize, String)>,
+) -> Option<(usize, usize, String)> {
+    let mut best: Option<(usize, usize, usize)> = None;
+    for (index, column, candidate) in keys {
+        let matches = key_path == candidate
+            || key_path.starts_with(&format!("{}.", candidate))
+            || key_path.starts_with(&format!("{}[", candidate));
+        let longer = match best {
+            Some((length, _, _)) => candidate.len() > length,
+            None => true,
+        };
+        if matches && longer {
+            best = Some((candidate.len(), index, column));
+        }
+    }
+
+    best.map(|(_, index, column)| {
+        (
+            index + 1,
+            column,
+            contents.lines().nth(index).unwrap_or("").to_owned(),
+        )
+    })
+}
+
+/// Get the key paths defined by the lines of a TOML-file.
+///
+/// This only understands the subset of TOML used by typical configurations: tables, arrays of
+/// tables and keys on their own line.
+fn toml_keys(contents: &str) -> Vec<(usize, usize, String)> {
+    let mut table = String::new();
+    let mut array_counts: Map<String, usize> = Map::new();
+    let mut keys = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let candidate = if trimmed.starts_with('[') {
+            let name = trimmed
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_owned();
+            table = if trimmed.starts_with("[[") {
+                let count = array_counts.entry(name.clone()).or_insert(0);
+                *count += 1;
+                format!("{}[{}]", name, *count - 1)
+            } else {
+                name
+            };
+            table.clone()
+        } else if trimmed.starts_with('#') {
+            continue;
+        } else if let Some(equals) = trimmed.find('=') {
+            let key = trimmed[..equals].trim().trim_matches('"');
+            if table.is_empty() {
+                key.to_owned()
+            } else {
+                format!("{}.{}", table, key)
+            }
+        } else {
+            continue;
+        };
+
+        keys.push((index, line.len() - trimmed.len() + 1, candidate));
+    }
+
+    keys
+}
+
+/// Get the key paths defined by the lines of a YAML-file.
+///
+/// This only understands the block style used by typical configurations: nested maps with one
+/// key per line and lists with one item per `-`.
+fn yaml_keys(contents: &str) -> Vec<(usize, usize, String)> {
+    // Key paths of the enclosing keys and list items with their indentation, the flag marks
+    // list items.
+    let mut parents: Vec<(usize, String, bool)> = Vec::new();
+    let mut item_counts: Map<String, usize> = Map::new();
+    let mut keys = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let mut indent = line.len() - line.trim_start().len();
+        let mut rest = line.trim_start();
+        if rest.is_empty() || rest.starts_with('#') || rest.starts_with("---") {
+            continue;
+        }
+
+        // Every `-` starts a list item, the rest of the line is indented past it.
+        while rest == "-" || rest.starts_with("- ") {
+            while parents
+                .last()
+                .is_some_and(|&(parent, _, item)| parent > indent || (item && parent == indent))
+            {
+                parents.pop();
             }
-            Err(e) => println!("{:?}", e),
+            let list = parents
+                .last()
+                .map(|(_, path, _)| path.to_owned())
+                .unwrap_or_default();
+            let count = item_counts.entry(list.clone()).or_insert(0);
+            let path = format!("{}[{}]", list, *count);
+            *count += 1;
+            keys.push((index, indent + 1, path.clone()));
+            parents.push((indent, path, true));
+
+            let item = rest[1..].trim_start();
+            indent += rest.len() - item.len();
+            rest = item;
         }
+
+        let key = match rest.find(": ") {
+            Some(colon) => &rest[..colon],
+            None if rest.ends_with(':') => &rest[..rest.len() - 1],
+            None => continue,
+        };
+        while parents
+            .last()
+            .is_some_and(|&(parent, _, _)| parent >= indent)
+        {
+            parents.pop();
+        }
+        let key = key.trim().trim_matches(|c| c == '"' || c == '\'');
+        let path = match parents.last() {
+            Some((_, parent, _)) => format!("{}.{}", parent, key),
+            None => key.to_owned(),
+        };
+        keys.push((index, indent + 1, path.clone()));
+        parents.push((indent, path, false));
     }
 
-    Ok(toml::from_str(&contents)?)
+    keys
+}
+
+/// Get the key paths of the keys and list items of a JSON-file.
+fn json_keys(contents: &str) -> Vec<(usize, usize, String)> {
+    // Key paths of the enclosing objects and lists, with the index of the next item for lists.
+    let mut parents: Vec<(String, Option<usize>)> = Vec::new();
+    // Key path of the value following the last key of an object.
+    let mut key: Option<String> = None;
+    let mut expect_value = true;
+    let mut keys = Vec::new();
+
+    let (mut index, mut column) = (0, 0);
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        column += 1;
+        let position = (index, column);
+
+        // Get the key path of a value starting at the current position.
+        let mut start_value = |keys: &mut Vec<(usize, usize, String)>| -> String {
+            match parents.last_mut() {
+                Some((ref list, Some(ref mut next))) => {
+                    let path = format!("{}[{}]", list, next);
+                    *next += 1;
+                    keys.push((position.0, position.1, path.clone()));
+                    path
+                }
+                Some((_, None)) => key.take().unwrap_or_default(),
+                None => String::new(),
+            }
+        };
+
+        match c {
+            '\n' => {
+                index += 1;
+                column = 0;
+            }
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    column += 1;
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            column += 1;
+                            chars.next();
+                        }
+                        c => string.push(c),
+                    }
+                }
+                if expect_value {
+                    start_value(&mut keys);
+                    expect_value = false;
+                } else if let Some((ref object, None)) = parents.last() {
+                    let path = if object.is_empty() {
+                        string
+                    } else {
+                        format!("{}.{}", object, string)
+                    };
+                    keys.push((position.0, position.1, path.clone()));
+                    key = Some(path);
+                }
+            }
+            ':' => expect_value = true,
+            ',' => expect_value = parents.last().is_some_and(|(_, next)| next.is_some()),
+            '{' | '[' => {
+                let path = start_value(&mut keys);
+                parents.push((path, if c == '[' { Some(0) } else { None }));
+                expect_value = c == '[';
+            }
+            '}' | ']' => {
+                parents.pop();
+                expect_value = false;
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                if expect_value {
+                    start_value(&mut keys);
+                    expect_value = false;
+                }
+            }
+        }
+    }
+
+    keys
+}
+
+/// Interpolate variables in all strings of the value, see
+/// [`interpolate_string`](fn.interpolate_string.html).
+fn interpolate_value<F>(
+    value: &mut Value,
+    key_path: &str,
+    lookup: &F,
+) -> ::std::result::Result<(), Box<ConfigError>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match *value {
+        Value::Object(ref mut object) => {
+            for (key, value) in object.iter_mut() {
+                let key_path = if key_path.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{}.{}", key_path, key)
+                };
+                interpolate_value(value, &key_path, lookup)?;
+            }
+        }
+        Value::Array(ref mut array) => {
+            for (index, value) in array.iter_mut().enumerate() {
+                interpolate_value(value, &format!("{}[{}]", key_path, index), lookup)?;
+            }
+        }
+        Value::String(ref mut string) => {
+            *string = interpolate_string(string, lookup).map_err(|e| {
+                Box::new(ConfigError {
+                    message: e.to_string(),
+                    key_path: Some(key_path.to_owned()),
+                    ..Default::default()
+                })
+            })?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Replace the variables in the string with the values returned by `lookup`.
+///
+/// Variables can be given as `${VAR}`, which requires the variable to be set, or as
+/// `${VAR:-default}`, which uses the default if the variable is unset or empty. `$${` results in a
+/// literal `${`.
+fn interpolate_string<F>(string: &str, lookup: &F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::new();
+    let mut rest = string;
+    while let Some(start) = rest.find("${") {
+        // `$${` escapes the variable syntax.
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("unterminated variable in: {}", string),
+        };
+
+        let variable = &rest[start + 2..end];
+        let (name, default) = match variable.find(":-") {
+            Some(index) => (&variable[..index], Some(&variable[index + 2..])),
+            None => (variable, None),
+        };
+        if name.is_empty() {
+            bail!("empty variable name in: {}", string);
+        }
+
+        match (lookup(name), default) {
+            (Some(ref value), Some(default)) if value.is_empty() => result.push_str(default),
+            (Some(value), _) => result.push_str(&value),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => bail!("environment variable `{}` is not set", name),
+        }
+
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn merge_value(
+    target: &mut Value,
+    source: Value,
+    key_path: &str,
+    file: &Path,
+    origins: &mut Map<String, PathBuf>,
+) -> Result<()> {
+    match (target, source) {
+        (&mut Value::Object(ref mut target), Value::Object(source)) => {
+            for (key, value) in source {
+                let key_path = if key_path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", key_path, key)
+                };
+                match target.get_mut(&key) {
+                    Some(existing) => {
+                        merge_value(existing, value, &key_path, file, origins)?;
+                        continue;
+                    }
+                    None => {
+                        origins.insert(key_path, file.to_owned());
+                    }
+                }
+                target.insert(key, value);
+            }
+        }
+        (&mut Value::Array(ref mut target), Value::Array(source)) if is_appended(key_path) => {
+            // Remember the origin of every appended element, e.g. of every rule.
+            for value in source {
+                origins.insert(format!("{}[{}]", key_path, target.len()), file.to_owned());
+                target.push(value);
+            }
+        }
+        (target, source) => {
+            if !same_value(target, &source) {
+                let error = ConfigError {
+                    message: format!(
+                        "conflicting value, already defined in {}",
+                        get_origin(origins, key_path)
+                            .map(|f| f.display().to_string())
+                            .unwrap_or_default()
+                    ),
+                    key_path: Some(key_path.to_owned()),
+                    ..Default::default()
+                };
+                let error = match read_file(file) {
+                    Ok(contents) => locate_error(error, file, Format::from_path(file)?, &contents),
+                    Err(_) => ConfigError {
+                        file: Some(file.to_owned()),
+                        ..error
+                    },
+                };
+                return Err(error.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the lists at `key_path` are appended to each other when merging, i.e. the `rules` of a
+/// section and the commands of a table in the `initialization` section.
+fn is_appended(key_path: &str) -> bool {
+    match key_path.split('.').collect::<Vec<_>>()[..] {
+        [section, "rules"] => section != "definitions",
+        ["initialization", "v4", _] | ["initialization", "v6", _] => true,
+        _ => false,
+    }
+}
+
+/// Whether two values are the same, treating a single value like a list containing only it, as
+/// fields like `external_network_interfaces` accept both.
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (&Value::Array(ref list), value) | (value, &Value::Array(ref list))
+            if !value.is_array() =>
+        {
+            list.len() == 1 && list[0] == *value
+        }
+        _ => a == b,
+    }
+}
+
+/// Get the file that defined the value at `key_path`, or any of its parents.
+fn get_origin<'a>(origins: &'a Map<String, PathBuf>, key_path: &str) -> Option<&'a PathBuf> {
+    let mut key_path = key_path;
+    loop {
+        if let Some(origin) = origins.get(key_path) {
+            return Some(origin);
+        }
+        match key_path.rfind(&['.', '['][..]) {
+            Some(index) => key_path = &key_path[..index],
+            None => return None,
+        }
+    }
+}
+
+/// Exponential backoff for retrying failed operations.
+///
+/// The delay before the first retry is `initial`, every further retry doubles the delay up to
+/// `max`. After `max_attempts` retries [`next_delay`](#method.next_delay) returns `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    max_attempts: u32,
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Create a new instance of `Backoff`.
+    pub fn new(initial: Duration, max: Duration, max_attempts: u32) -> Backoff {
+        Backoff {
+            initial: initial,
+            max: max,
+            max_attempts: max_attempts,
+            attempts: 0,
+        }
+    }
+
+    /// Get the delay before the next retry, or `None` if all retries are used up.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempts >= self.max_attempts {
+            return None;
+        }
+
+        let delay = 1u32
+            .checked_shl(self.attempts)
+            .and_then(|factor| self.initial.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max));
+        self.attempts += 1;
+
+        Some(delay)
+    }
+
+    /// Number of retries handed out since the last reset.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Start over with the initial delay, e.g. after the operation succeeded.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "IFACE" => Some("eth0".to_owned()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn interpolate_set() {
+        assert_eq!(
+            interpolate_string("-i ${IFACE} -o ${IFACE}", &lookup).unwrap(),
+            "-i eth0 -o eth0"
+        );
+    }
+
+    #[test]
+    fn interpolate_default() {
+        assert_eq!(
+            interpolate_string("${IFACE:-eth1}", &lookup).unwrap(),
+            "eth0"
+        );
+        assert_eq!(
+            interpolate_string("${UNSET:-eth1}", &lookup).unwrap(),
+            "eth1"
+        );
+        assert_eq!(
+            interpolate_string("${EMPTY:-eth1}", &lookup).unwrap(),
+            "eth1"
+        );
+        assert_eq!(interpolate_string("${UNSET:-}", &lookup).unwrap(), "");
+    }
+
+    #[test]
+    fn interpolate_unset() {
+        assert_eq!(
+            interpolate_string("${UNSET}", &lookup)
+                .unwrap_err()
+                .to_string(),
+            "environment variable `UNSET` is not set"
+        );
+    }
+
+    #[test]
+    fn interpolate_escaped() {
+        assert_eq!(
+            interpolate_string("$${IFACE} $IFACE", &lookup).unwrap(),
+            "${IFACE} $IFACE"
+        );
+    }
+
+    #[test]
+    fn interpolate_invalid() {
+        assert!(interpolate_string("${IFACE", &lookup).is_err());
+        assert!(interpolate_string("${}", &lookup).is_err());
+    }
+
+    #[test]
+    fn interpolate_value_key_path() {
+        let mut value: Value =
+            serde_json::from_str(r#"{ "defaults": { "interfaces": ["${UNSET}"] } }"#).unwrap();
+
+        assert_eq!(
+            interpolate_value(&mut value, "", &lookup)
+                .unwrap_err()
+                .to_string(),
+            "in `defaults.interfaces[0]`: environment variable `UNSET` is not set"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5), 5);
+
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(1)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(2)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(4)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(5)));
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(5)));
+        assert_eq!(backoff.attempts(), 5);
+        assert_eq!(backoff.next_delay(), None);
+
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        assert_eq!(backoff.next_delay(), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60), 100);
+        let delays = (0..100)
+            .filter_map(|_| backoff.next_delay())
+            .collect::<Vec<_>>();
+
+        assert_eq!(delays.len(), 100);
+        assert_eq!(delays[99], Duration::from_secs(60));
+    }
+
+    #[test]
+    fn backoff_without_retries() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60), 0);
+        assert_eq!(backoff.next_delay(), None);
+    }
 }
@@ -0,0 +1,304 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+extern crate serde_json;
+extern crate toml;
+
+mod common;
+
+use common::*;
+use dfw::audit::{config_hash, unified_diff, AuditLog, AuditRecord, Rotation, Trigger};
+use dfw::iptables::{IPTables, IPTablesLogger, IPTablesRecorder, IPVersion};
+use dfw::types::DFW;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+fn remove_rotated(path: &PathBuf) {
+    let _ = fs::remove_file(path);
+    for index in 1..5 {
+        let _ = fs::remove_file(format!("{}.{}", path.display(), index));
+    }
+}
+
+fn read_records(path: &PathBuf) -> Vec<AuditRecord> {
+    let mut contents = String::new();
+    File::open(path)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn lines(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn diff_unchanged() {
+    let rules = lines(&["*filter", "-A INPUT -j ACCEPT", "COMMIT"]);
+    assert_eq!(unified_diff(&rules, &rules), "");
+    assert_eq!(unified_diff(&[], &[]), "");
+}
+
+#[test]
+fn diff_from_empty() {
+    let rules = lines(&["*filter", "-A INPUT -j ACCEPT", "COMMIT"]);
+    assert_eq!(
+        unified_diff(&[], &rules),
+        "--- previous\n\
+         +++ current\n\
+         @@ -0,0 +1,3 @@\n\
+         +*filter\n\
+         +-A INPUT -j ACCEPT\n\
+         +COMMIT\n"
+    );
+}
+
+#[test]
+fn diff_hunks() {
+    let old = lines(&[
+        "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12",
+    ]);
+    let new = lines(&[
+        "1", "2a", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13",
+    ]);
+    assert_eq!(
+        unified_diff(&old, &new),
+        "--- previous\n\
+         +++ current\n\
+         @@ -1,5 +1,5 @@\n \
+         1\n\
+         -2\n\
+         +2a\n \
+         3\n \
+         4\n \
+         5\n\
+         @@ -10,3 +10,4 @@\n \
+         10\n \
+         11\n \
+         12\n\
+         +13\n"
+    );
+}
+
+#[test]
+fn config_hash_ignores_formatting() {
+    let first: DFW = toml::from_str(
+        r#"
+[defaults]
+external_network_interfaces = "eth0"
+
+[initialization.v4]
+filter = ["-P INPUT DROP"]
+nat = ["-P PREROUTING ACCEPT"]
+"#,
+    )
+    .unwrap();
+    let reordered: DFW = toml::from_str(
+        r#"
+[initialization.v4]
+nat = [ "-P PREROUTING ACCEPT" ]
+filter = [ "-P INPUT DROP" ]
+
+[defaults]
+external_network_interfaces = [ "eth0" ]
+"#,
+    )
+    .unwrap();
+    let changed: DFW = toml::from_str(
+        r#"
+[defaults]
+external_network_interfaces = "eth1"
+"#,
+    )
+    .unwrap();
+
+    let hash = config_hash(&first).unwrap();
+    assert!(hash.starts_with("sha256:"));
+    assert_eq!(hash.len(), "sha256:".len() + 64);
+    assert_eq!(hash, config_hash(&reordered).unwrap());
+    assert_ne!(hash, config_hash(&changed).unwrap());
+}
+
+#[test]
+fn record_diffs_against_previous() {
+    let path = temp_path("audit", "record");
+    remove_rotated(&path);
+    let rotation = Rotation {
+        max_size: 0,
+        keep: 0,
+    };
+    let mut audit_log = AuditLog::open(&path, rotation).unwrap();
+
+    let first = lines(&["*filter", "-A INPUT -j ACCEPT", "COMMIT"]);
+    let second = lines(&["*filter", "-A INPUT -j DROP", "COMMIT"]);
+    audit_log
+        .record(Trigger::Startup, None, "sha256:config", first.clone())
+        .unwrap();
+    audit_log
+        .record(
+            Trigger::DockerEvent,
+            Some(vec!["abc123".to_owned()]),
+            "sha256:config",
+            second.clone(),
+        )
+        .unwrap();
+    audit_log
+        .record(Trigger::Sighup, None, "sha256:config", second.clone())
+        .unwrap();
+
+    let records = read_records(&path);
+    assert_eq!(records.len(), 3);
+
+    assert_eq!(records[0].trigger, Trigger::Startup);
+    assert_eq!(records[0].affected_containers, None);
+    assert_eq!(records[0].config_hash, "sha256:config");
+    assert_eq!(records[0].diff, unified_diff(&[], &first));
+
+    assert_eq!(records[1].trigger, Trigger::DockerEvent);
+    assert_eq!(
+        records[1].affected_containers,
+        Some(vec!["abc123".to_owned()])
+    );
+    assert_eq!(records[1].diff, unified_diff(&first, &second));
+    assert_ne!(records[0].rules_hash, records[1].rules_hash);
+
+    // Unchanged rules are recorded, too.
+    assert_eq!(records[2].trigger, Trigger::Sighup);
+    assert_eq!(records[2].diff, "");
+    assert_eq!(records[1].rules_hash, records[2].rules_hash);
+
+    // The triggers are serialized in snake case.
+    let mut contents = String::new();
+    File::open(&path)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert!(contents.contains(r#""trigger":"docker_event""#));
+    assert!(!contents
+        .lines()
+        .next()
+        .unwrap()
+        .contains("affected_containers"));
+
+    remove_rotated(&path);
+}
+
+#[test]
+fn record_rotates() {
+    let path = temp_path("audit", "rotate");
+    remove_rotated(&path);
+    let rotation = Rotation {
+        max_size: 1,
+        keep: 2,
+    };
+    let mut audit_log = AuditLog::open(&path, rotation).unwrap();
+
+    let triggers = [
+        Trigger::Startup,
+        Trigger::Interval,
+        Trigger::ConfigChange,
+        Trigger::Sighup,
+    ];
+    for (index, trigger) in triggers.iter().enumerate() {
+        audit_log
+            .record(
+                *trigger,
+                None,
+                "sha256:config",
+                vec![format!("rule {}", index)],
+            )
+            .unwrap();
+    }
+
+    // Every record exceeds the maximum size, so every file holds a single record.
+    let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+    assert_eq!(read_records(&path)[0].trigger, Trigger::Sighup);
+    assert_eq!(read_records(&rotated(1))[0].trigger, Trigger::ConfigChange);
+    assert_eq!(read_records(&rotated(2))[0].trigger, Trigger::Interval);
+    assert!(!rotated(3).exists());
+
+    // The diff spans the rotation.
+    assert_eq!(
+        read_records(&path)[0].diff,
+        unified_diff(&lines(&["rule 2"]), &lines(&["rule 3"]))
+    );
+
+    remove_rotated(&path);
+}
+
+#[test]
+fn recorder_keeps_applied_rules() {
+    let logger = IPTablesLogger::new();
+    let recorder = IPTablesRecorder::new(&logger, IPVersion::IPv4).unwrap();
+
+    assert_eq!(recorder.take_rules(), Vec::<String>::new());
+
+    recorder.new_chain("filter", "DFWRS_INPUT").unwrap();
+    recorder.flush_chain("filter", "DFWRS_INPUT").unwrap();
+    recorder.set_policy("filter", "INPUT", "DROP").unwrap();
+    recorder
+        .append("filter", "DFWRS_INPUT", "-j ACCEPT")
+        .unwrap();
+    recorder
+        .insert("filter", "INPUT", "-j DFWRS_INPUT", 1)
+        .unwrap();
+    assert!(!recorder
+        .exists("filter", "INPUT", "-j DFWRS_INPUT")
+        .unwrap());
+    recorder.commit().unwrap();
+
+    assert_eq!(
+        recorder.take_rules(),
+        lines(&[
+            "*filter",
+            ":DFWRS_INPUT - [0:0]",
+            ":INPUT DROP [0:0]",
+            "-F DFWRS_INPUT",
+            "-A DFWRS_INPUT -j ACCEPT",
+            "-I INPUT 1 -j DFWRS_INPUT",
+            "COMMIT",
+        ])
+    );
+    assert_eq!(recorder.take_rules(), Vec::<String>::new());
+
+    // Every call is passed on to the wrapped implementation.
+    assert_eq!(logger.logs().len(), 7);
+}
+
+#[test]
+fn recorder_discards_uncommitted_rules() {
+    let logger = IPTablesLogger::new();
+    let recorder = IPTablesRecorder::new(&logger, IPVersion::IPv4).unwrap();
+
+    recorder.append("filter", "DFWRS_INPUT", "-j DROP").unwrap();
+    assert_eq!(recorder.take_rules(), Vec::<String>::new());
+
+    recorder
+        .append("filter", "DFWRS_INPUT", "-j ACCEPT")
+        .unwrap();
+    recorder.commit().unwrap();
+    recorder
+        .append("filter", "DFWRS_INPUT", "-j REJECT")
+        .unwrap();
+    assert_eq!(
+        recorder.take_rules(),
+        lines(&[
+            "*filter",
+            ":DFWRS_INPUT - [0:0]",
+            "-A DFWRS_INPUT -j ACCEPT",
+            "COMMIT",
+        ])
+    );
+    assert_eq!(recorder.take_rules(), Vec::<String>::new());
+}
@@ -6,7 +6,17 @@
 // option. This file may not be copied, modified or distributed
 // except according to those terms.
 
-use std::path::PathBuf;
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(unix)]
+use std::time::Duration;
 
 pub fn resource(segment: &str) -> Option<String> {
     let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -15,3 +25,29 @@ pub fn resource(segment: &str) -> Option<String> {
 
     p.to_str().map(|s| s.to_owned())
 }
+
+/// Get a path within the temporary directory, unique to the test process, removing any file left
+/// at it.
+pub fn temp_path(prefix: &str, name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("dfw-{}-{}-{}", prefix, process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Bind a datagram socket to act as a daemon receiving messages, e.g. syslog.
+#[cfg(unix)]
+pub fn bind_datagram(path: &Path) -> UnixDatagram {
+    let socket = UnixDatagram::bind(path).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket
+}
+
+/// Receive a single message from the socket.
+#[cfg(unix)]
+pub fn receive(socket: &UnixDatagram) -> String {
+    let mut buffer = [0; 4096];
+    let len = socket.recv(&mut buffer).unwrap();
+    String::from_utf8(buffer[..len].to_vec()).unwrap()
+}
@@ -22,6 +22,7 @@ mod common;
 mod logs;
 
 use common::*;
+use dfw::inventory::DockerInventory;
 use dfw::iptables::{IPTables, IPTablesLogger, IPTablesRestore, IPVersion};
 use dfw::types::*;
 use dfw::util::load_file;
@@ -33,6 +34,7 @@ use slog::{Drain, Fuse, Logger, OwnedKVList, Record};
 use std::panic;
 use std::panic::{AssertUnwindSafe, UnwindSafe};
 use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
 
 macro_rules! proxy {
     ( $( #[$attr:meta] )* $name:ident ( $( $param:ident : $ty:ty ),* ) -> $ret:ty ) => {
@@ -100,6 +102,7 @@ impl IPTables for IPTablesRestoreProxy {
 
 static PROCESSING_OPTIONS: ProcessingOptions = ProcessingOptions {
     container_filter: ContainerFilter::Running,
+    strict: false,
 };
 
 fn logger() -> Logger {
@@ -118,6 +121,24 @@ fn logger() -> Logger {
     logger
 }
 
+/// Create a logger recording the messages of all log records.
+fn recording_logger() -> (Logger, Arc<Mutex<Vec<String>>>) {
+    struct RecordingDrain(Arc<Mutex<Vec<String>>>);
+    impl Drain for RecordingDrain {
+        type Ok = ();
+        type Err = ();
+
+        fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let drain = Fuse(RecordingDrain(messages.clone()));
+    (Logger::root(drain, o!()), messages)
+}
+
 fn compare_loglines(actual: &Vec<LogLine>, expected: &Vec<LogLine>) {
     // If the logs don't match, include correctly formatted output for comparison.
     if actual != expected {
@@ -200,9 +221,16 @@ fn dc_template<F: FnOnce(&I, &I) -> (), I: IPTables>(
         &format!("dfwtest{}", num),
         || {
             // TODO: only start environment once, then test both IPTablesLogger and IPTablesRestore
-            let process =
-                ProcessDFW::new(&docker, &toml, &*ipt4, &*ipt6, &PROCESSING_OPTIONS, &logger)
-                    .unwrap();
+            let inventory = DockerInventory::new(&docker);
+            let process = ProcessDFW::new(
+                &inventory,
+                &toml,
+                &*ipt4,
+                &*ipt6,
+                &PROCESSING_OPTIONS,
+                &logger,
+            )
+            .unwrap();
 
             // Test if container is available
             let containers = docker.containers();
@@ -275,6 +303,72 @@ fn test_iptables_restore(num: &str) {
     });
 }
 
+fn test_targeted_processing(num: &str) {
+    // Load toml
+    let toml: DFW = load_file(&resource(&format!("docker/{}/conf.toml", num)).unwrap()).unwrap();
+
+    // Create logger recording the messages, to verify which processing path was taken
+    let (logger, messages) = recording_logger();
+
+    // Mark `docker` as `UnwindSafe`, since dependent type type `hyper::http::message::Protocol` is
+    // not `UnwindSafe`.
+    let docker = AssertUnwindSafe(Docker::new());
+
+    with_compose_environment(
+        &resource(&format!("docker/{}/docker-compose.yml", num)).unwrap(),
+        &format!("dfwtest{}", num),
+        || {
+            let inventory = DockerInventory::new(&docker);
+            let cache = ProcessingCache::new();
+            let process = |affected_containers: Option<Vec<String>>| {
+                messages.lock().unwrap().clear();
+                let ipt4 = IPTablesRestoreProxy(IPTablesRestore::new(IPVersion::IPv4).unwrap());
+                let ipt6 = IPTablesRestoreProxy(IPTablesRestore::new(IPVersion::IPv6).unwrap());
+                ProcessDFW::new(
+                    &inventory,
+                    &toml,
+                    &ipt4,
+                    &ipt6,
+                    &PROCESSING_OPTIONS,
+                    &logger,
+                )
+                .unwrap()
+                .with_cache(&cache, affected_containers)
+                .process()
+                .unwrap();
+
+                ipt4.0.get_rules()
+            };
+            let logged = |message: &str| messages.lock().unwrap().iter().any(|m| m == message);
+
+            // Initial full run, populating the cache
+            let full = process(None);
+
+            // Targeted run for a container that is referenced in the configuration, which only
+            // regenerates the rules referencing the container and reuses the cached ones
+            let container_id = docker
+                .containers()
+                .get(&format!("dfwtest{}_a_1", num))
+                .inspect()
+                .unwrap()
+                .Id;
+            assert_eq!(process(Some(vec![container_id])), full);
+            assert!(logged(
+                "Only processing rules referencing affected containers"
+            ));
+
+            // Targeted run for an unknown container, falling back to a full run
+            assert_eq!(process(Some(vec!["unknown".to_owned()])), full);
+            assert!(logged(
+                "Affected container is unknown, processing all rules"
+            ));
+            assert!(!logged(
+                "Only processing rules referencing affected containers"
+            ));
+        },
+    );
+}
+
 fn test_iptables_logger(num: &str) {
     // `IPTablesLogger` uses a `RefCell` to be able to modify its logging-vector across the
     // lifetime of the struct. `RefCell` is not `UnwindSafe`, so we have to force it to be.
@@ -380,3 +474,13 @@ fn test_iptables_restore_05() {
 fn test_iptables_restore_06() {
     test_iptables_restore("06");
 }
+
+#[test]
+fn test_targeted_processing_01() {
+    test_targeted_processing("01");
+}
+
+#[test]
+fn test_targeted_processing_04() {
+    test_targeted_processing("04");
+}
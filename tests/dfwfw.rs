@@ -0,0 +1,149 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+#[macro_use]
+extern crate maplit;
+extern crate toml;
+
+mod common;
+
+use common::resource;
+use dfw::dfwfw::{self, Conversion};
+use dfw::types::*;
+use std::fs::File;
+use std::io::prelude::*;
+
+fn convert_resource(name: &str) -> Conversion {
+    let mut contents = String::new();
+    File::open(resource(name).unwrap())
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    dfwfw::convert(&contents).unwrap()
+}
+
+#[test]
+fn convert_sections() {
+    let conversion = convert_resource("dfwfw/dfwfw.json");
+
+    let expected = DFW {
+        defaults: Some(Defaults {
+            external_network_interfaces: Some(vec!["eth0".to_owned()]),
+        }),
+        initialization: Some(Initialization {
+            v4: Some(hashmap! {
+                "filter".to_owned() => vec![
+                    "-P INPUT DROP".to_owned(),
+                    "-A INPUT -i lo -j ACCEPT".to_owned(),
+                ],
+            }),
+            v6: None,
+        }),
+        container_to_container: Some(ContainerToContainer {
+            default_policy: "DROP".to_owned(),
+            rules: Some(vec![ContainerToContainerRule {
+                network: "backend".to_owned(),
+                src_container: Some("web".to_owned()),
+                dst_container: Some("db".to_owned()),
+                filter: Some("-p tcp --dport 5432".to_owned()),
+                action: "ACCEPT".to_owned(),
+                schedule: None,
+                priority: None,
+            }]),
+        }),
+        container_to_wider_world: Some(ContainerToWiderWorld {
+            default_policy: "ACCEPT".to_owned(),
+            rules: Some(vec![ContainerToWiderWorldRule {
+                network: Some("backend".to_owned()),
+                src_container: Some("db".to_owned()),
+                filter: None,
+                action: "REJECT".to_owned(),
+                external_network_interface: None,
+                schedule: None,
+                priority: None,
+            }]),
+        }),
+        container_to_host: Some(ContainerToHost {
+            default_policy: "DROP".to_owned(),
+            rules: Some(vec![ContainerToHostRule {
+                network: "frontend".to_owned(),
+                src_container: Some("web".to_owned()),
+                filter: Some("-p tcp --dport 53".to_owned()),
+                action: "ACCEPT".to_owned(),
+                schedule: None,
+                priority: None,
+            }]),
+        }),
+        wider_world_to_container: Some(WiderWorldToContainer {
+            rules: Some(vec![WiderWorldToContainerRule {
+                network: "frontend".to_owned(),
+                dst_container: "web".to_owned(),
+                expose_port: vec![
+                    ExposePort {
+                        host_port: 80,
+                        container_port: None,
+                        family: "tcp".to_owned(),
+                    },
+                    ExposePort {
+                        host_port: 443,
+                        container_port: None,
+                        family: "tcp".to_owned(),
+                    },
+                    ExposePort {
+                        host_port: 8080,
+                        container_port: Some(80),
+                        family: "tcp".to_owned(),
+                    },
+                ],
+                external_network_interface: None,
+                schedule: None,
+                priority: None,
+            }]),
+        }),
+        container_dnat: Some(ContainerDNAT {
+            rules: Some(vec![]),
+        }),
+    };
+
+    assert_eq!(conversion.dfw, expected);
+}
+
+#[test]
+fn convert_warnings() {
+    let conversion = convert_resource("dfwfw/dfwfw.json");
+
+    assert_eq!(
+        conversion.warnings,
+        vec![
+            "`container_dnat.rules[0]`: skipped, `src_container` selects by `Id`, DFW can only \
+             select by name",
+            "`container_internals`: skipped, DFW has no equivalent section",
+            "`container_to_container.rules[1]`: skipped, `src_container` uses the regular \
+             expression `^worker-.*`, DFW can only match exact names",
+            "`container_to_host.rules[1]`: skipped, missing field `network`",
+            "`log_path`: ignored, configure this through the command line of DFW",
+        ]
+    );
+}
+
+#[test]
+fn convert_to_toml() {
+    let conversion = convert_resource("dfwfw/dfwfw.json");
+    let toml = conversion.to_toml().unwrap();
+
+    assert!(toml.starts_with("# The following parts of the DFWFW configuration"));
+    assert!(toml.contains("# * `container_internals`: skipped"));
+    assert_eq!(toml::from_str::<DFW>(&toml).unwrap(), conversion.dfw);
+}
+
+#[test]
+fn convert_invalid_json() {
+    assert!(dfwfw::convert("{").is_err());
+    assert!(dfwfw::convert("[]").is_err());
+}
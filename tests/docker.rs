@@ -0,0 +1,253 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+
+use dfw::docker::{Connection, DockerOptions, Tls, DEFAULT_HOST};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+fn resolve(options: &DockerOptions, env: &[(&str, &str)]) -> Result<Connection, String> {
+    let env: HashMap<String, String> = env
+        .iter()
+        .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    options
+        .resolve_with(&|name| env.get(name).cloned())
+        .map_err(|e| format!("{}", e))
+}
+
+fn tls(ca: Option<&str>, cert: &str, key: &str) -> Option<Tls> {
+    Some(Tls {
+        ca: ca.map(PathBuf::from),
+        cert: PathBuf::from(cert),
+        key: PathBuf::from(key),
+    })
+}
+
+#[test]
+fn resolve_default() {
+    assert_eq!(
+        resolve(&DockerOptions::default(), &[]),
+        Ok(Connection {
+            url: DEFAULT_HOST.to_owned(),
+            tls: None,
+        })
+    );
+    // Empty variables are ignored, like the Docker client does.
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[("DOCKER_HOST", ""), ("DOCKER_TLS_VERIFY", "")]
+        ),
+        Ok(Connection {
+            url: DEFAULT_HOST.to_owned(),
+            tls: None,
+        })
+    );
+}
+
+#[test]
+fn resolve_docker_host() {
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[("DOCKER_HOST", "unix:///tmp/docker.sock")]
+        ),
+        Ok(Connection {
+            url: "unix:///tmp/docker.sock".to_owned(),
+            tls: None,
+        })
+    );
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[("DOCKER_HOST", "tcp://10.0.0.1")]
+        ),
+        Ok(Connection {
+            url: "http://10.0.0.1:2375".to_owned(),
+            tls: None,
+        })
+    );
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[("DOCKER_HOST", "tcp://docker.example.com:12375")]
+        ),
+        Ok(Connection {
+            url: "http://docker.example.com:12375".to_owned(),
+            tls: None,
+        })
+    );
+
+    // The URL given in the options takes precedence.
+    let options = DockerOptions {
+        url: Some("unix:///run/docker.sock".to_owned()),
+        ..DockerOptions::default()
+    };
+    assert_eq!(
+        resolve(&options, &[("DOCKER_HOST", "tcp://10.0.0.1")]),
+        Ok(Connection {
+            url: "unix:///run/docker.sock".to_owned(),
+            tls: None,
+        })
+    );
+}
+
+#[test]
+fn resolve_tls_from_environment() {
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[
+                ("DOCKER_HOST", "tcp://10.0.0.1"),
+                ("DOCKER_TLS_VERIFY", "1"),
+                ("DOCKER_CERT_PATH", "/etc/docker/certs"),
+            ]
+        ),
+        Ok(Connection {
+            url: "https://10.0.0.1:2376".to_owned(),
+            tls: tls(
+                Some("/etc/docker/certs/ca.pem"),
+                "/etc/docker/certs/cert.pem",
+                "/etc/docker/certs/key.pem"
+            ),
+        })
+    );
+    // The daemon is only verified if `DOCKER_TLS_VERIFY` is set.
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[
+                ("DOCKER_HOST", "tcp://10.0.0.1:3376"),
+                ("DOCKER_CERT_PATH", "/etc/docker/certs"),
+            ]
+        ),
+        Ok(Connection {
+            url: "https://10.0.0.1:3376".to_owned(),
+            tls: tls(
+                None,
+                "/etc/docker/certs/cert.pem",
+                "/etc/docker/certs/key.pem"
+            ),
+        })
+    );
+    // The certificates are looked up in `~/.docker` by default.
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[
+                ("DOCKER_HOST", "tcp://10.0.0.1"),
+                ("DOCKER_TLS_VERIFY", "1"),
+                ("HOME", "/root"),
+            ]
+        ),
+        Ok(Connection {
+            url: "https://10.0.0.1:2376".to_owned(),
+            tls: tls(
+                Some("/root/.docker/ca.pem"),
+                "/root/.docker/cert.pem",
+                "/root/.docker/key.pem"
+            ),
+        })
+    );
+    // TLS does not apply to Unix sockets.
+    assert_eq!(
+        resolve(
+            &DockerOptions::default(),
+            &[("DOCKER_TLS_VERIFY", "1"), ("HOME", "/root")]
+        ),
+        Ok(Connection {
+            url: DEFAULT_HOST.to_owned(),
+            tls: None,
+        })
+    );
+}
+
+#[test]
+fn resolve_tls_from_options() {
+    let options = DockerOptions {
+        url: Some("tcp://10.0.0.1".to_owned()),
+        tls_ca: Some(PathBuf::from("/tls/ca.crt")),
+        tls_cert: Some(PathBuf::from("/tls/client.crt")),
+        tls_key: Some(PathBuf::from("/tls/client.key")),
+    };
+    // The options take precedence over the environment.
+    assert_eq!(
+        resolve(&options, &[("DOCKER_CERT_PATH", "/etc/docker/certs")]),
+        Ok(Connection {
+            url: "https://10.0.0.1:2376".to_owned(),
+            tls: tls(Some("/tls/ca.crt"), "/tls/client.crt", "/tls/client.key"),
+        })
+    );
+
+    let options = DockerOptions {
+        tls_ca: None,
+        ..options
+    };
+    assert_eq!(
+        resolve(&options, &[]),
+        Ok(Connection {
+            url: "https://10.0.0.1:2376".to_owned(),
+            tls: tls(None, "/tls/client.crt", "/tls/client.key"),
+        })
+    );
+
+    let options = DockerOptions {
+        tls_key: None,
+        ..options
+    };
+    assert_eq!(
+        resolve(&options, &[]),
+        Err("TLS requires the key of the client certificate".to_owned())
+    );
+}
+
+#[test]
+fn resolve_invalid() {
+    let resolve_url = |url: &str, env: &[(&str, &str)]| {
+        let options = DockerOptions {
+            url: Some(url.to_owned()),
+            ..DockerOptions::default()
+        };
+        resolve(&options, env)
+    };
+
+    assert_eq!(
+        resolve_url("ftp://10.0.0.1", &[]),
+        Err("unsupported scheme 'ftp' of Docker URL 'ftp://10.0.0.1'".to_owned())
+    );
+    assert_eq!(
+        resolve_url("http://10.0.0.1", &[("DOCKER_CERT_PATH", "/certs")]),
+        Err("Docker URL 'http://10.0.0.1' uses HTTP, but TLS is configured".to_owned())
+    );
+    assert_eq!(
+        resolve_url("https://10.0.0.1", &[]),
+        Err("Docker URL 'https://10.0.0.1' uses HTTPS, but TLS is not configured".to_owned())
+    );
+    assert!(resolve_url("not a url", &[])
+        .unwrap_err()
+        .starts_with("invalid Docker URL 'not a url'"));
+}
+
+#[test]
+fn connect_missing_tls_file() {
+    let connection = Connection {
+        url: "https://10.0.0.1:2376".to_owned(),
+        tls: tls(
+            None,
+            "/nonexistent/dfw/cert.pem",
+            "/nonexistent/dfw/key.pem",
+        ),
+    };
+    let error = match connection.connect() {
+        Ok(_) => panic!("connecting with a missing TLS file must fail"),
+        Err(e) => format!("{}", e),
+    };
+    assert!(error.starts_with("failed to read TLS file /nonexistent/dfw/cert.pem"));
+}
@@ -0,0 +1,213 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+
+mod common;
+
+use common::*;
+use dfw::iptables::*;
+use std::fs::File;
+use std::io::prelude::*;
+
+fn parse_resource(segment: &str) -> IPTablesSave {
+    let mut contents = String::new();
+    File::open(resource(&format!("iptables-save/{}", segment)).unwrap())
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+
+    IPTablesSave::parse(&contents).unwrap()
+}
+
+fn rules_of(table: &SavedTable, chain: &str) -> Vec<String> {
+    table
+        .rules_of(chain)
+        .iter()
+        .map(|rule| rule.to_string())
+        .collect()
+}
+
+#[test]
+fn parse_tables_and_chains() {
+    let save = parse_resource("counters-v4.txt");
+
+    let tables: Vec<_> = save
+        .tables
+        .iter()
+        .map(|table| table.name.as_str())
+        .collect();
+    assert_eq!(tables, vec!["nat", "filter"]);
+
+    let filter = save.table("filter").unwrap();
+    let chains: Vec<_> = filter
+        .chains
+        .iter()
+        .map(|chain| chain.name.as_str())
+        .collect();
+    assert_eq!(
+        chains,
+        vec!["INPUT", "FORWARD", "OUTPUT", "DFWRS_FORWARD", "DFWRS_INPUT"]
+    );
+    assert!(save.table("mangle").is_none());
+    assert!(filter.chain("DOCKER").is_none());
+}
+
+#[test]
+fn parse_policies_and_counters() {
+    let save = parse_resource("counters-v4.txt");
+    let filter = save.table("filter").unwrap();
+
+    let input = filter.chain("INPUT").unwrap();
+    assert_eq!(input.policy, Some("DROP".to_owned()));
+    assert_eq!(
+        input.counters,
+        Counters {
+            packets: 42,
+            bytes: 2520,
+        }
+    );
+    // User-defined chains have no policy.
+    assert_eq!(filter.chain("DFWRS_INPUT").unwrap().policy, None);
+
+    let rules = filter.rules_of("DFWRS_FORWARD");
+    assert_eq!(rules.len(), 5);
+    assert_eq!(
+        rules[1].counters,
+        Some(Counters {
+            packets: 960,
+            bytes: 80010,
+        })
+    );
+    // Without `-c`, `iptables-save` prints the counters of the chains only.
+    let v6 = parse_resource("v6.txt");
+    let v6_filter = v6.table("filter").unwrap();
+    assert!(v6_filter.rules.iter().all(|rule| rule.counters.is_none()));
+    assert_eq!(
+        v6_filter.chain("FORWARD").unwrap().counters,
+        Counters::default()
+    );
+}
+
+#[test]
+fn parse_rules() {
+    let save = parse_resource("counters-v4.txt");
+
+    let nat = save.table("nat").unwrap();
+    assert_eq!(
+        rules_of(nat, "DFWRS_PREROUTING"),
+        vec![
+            "-A DFWRS_PREROUTING -i eth0 -p tcp --dport 443 -j DNAT \
+             --to-destination 172.18.0.2:443",
+        ]
+    );
+    assert_eq!(
+        rules_of(nat, "DFWRS_POSTROUTING"),
+        vec!["-A DFWRS_POSTROUTING -s 172.18.0.0/16 ! -o br-ba7fa850cf55 -j MASQUERADE"]
+    );
+
+    let filter = save.table("filter").unwrap();
+    assert_eq!(
+        rules_of(filter, "DFWRS_FORWARD"),
+        vec![
+            "-A DFWRS_FORWARD -m state --state INVALID -j DROP",
+            "-A DFWRS_FORWARD -m state --state RELATED,ESTABLISHED -j ACCEPT",
+            "-A DFWRS_FORWARD -d 172.18.0.2/32 -i eth0 -o br-ba7fa850cf55 -p tcp --dport 443 \
+             -m comment --comment \"expose web server\" -j ACCEPT",
+            "-A DFWRS_FORWARD -s 172.18.0.3/32 -i br-ba7fa850cf55 -o eth0 -p udp -m multiport \
+             --dports 53,123 -j ACCEPT",
+            "-A DFWRS_FORWARD -i br-ba7fa850cf55 -j REJECT --reject-with icmp-port-unreachable",
+        ]
+    );
+
+    let comment = filter.rules_of("DFWRS_FORWARD")[2];
+    assert_eq!(comment.target(), Some("ACCEPT"));
+    assert!(comment.args.contains(&"expose web server".to_owned()));
+    assert_eq!(filter.rules_of("INPUT")[0].target(), Some("DFWRS_INPUT"));
+}
+
+#[test]
+fn parse_ipv6_rules() {
+    let save = parse_resource("v6.txt");
+    let filter = save.table("filter").unwrap();
+
+    assert_eq!(
+        rules_of(filter, "DFWRS_FORWARD"),
+        vec![
+            "-A DFWRS_FORWARD -s fd00:dead:beef::2/128 -d 2001:db8::/32 -p tcp \
+             --sport 1024:65535 -j ACCEPT",
+        ]
+    );
+    assert_eq!(
+        rules_of(filter, "DFWRS_INPUT"),
+        vec!["-A DFWRS_INPUT -s fe80::/10 -p ipv6-icmp -j ACCEPT"]
+    );
+}
+
+#[test]
+fn normalize_rules() {
+    // Rules as DFW adds them are normalized to the form printed by `iptables-save`.
+    let normalized = |rule: &str| SavedRule::new("DFWRS_FORWARD", rule).to_string();
+    assert_eq!(
+        normalized("-s 172.18.0.2 -p TCP --dport 80 -j ACCEPT -m comment --comment \"web\""),
+        "-A DFWRS_FORWARD -s 172.18.0.2/32 -p tcp --dport 80 -m comment --comment web -j ACCEPT"
+    );
+    assert_eq!(
+        normalized("--source fd00::2 --in-interface docker0 --jump DROP"),
+        "-A DFWRS_FORWARD -s fd00::2/128 -i docker0 -j DROP"
+    );
+    assert_eq!(
+        normalize_rule("-d db.example.com -j LOG --log-prefix \"dfw: \""),
+        vec!["-d", "db.example.com", "-j", "LOG", "--log-prefix", "dfw: "]
+    );
+    assert_eq!(
+        normalized("-m comment --comment \"say \\\"hi\\\"\" -j RETURN"),
+        "-A DFWRS_FORWARD -m comment --comment \"say \\\"hi\\\"\" -j RETURN"
+    );
+    assert_eq!(
+        normalized("-m time --timestart 08:00 --timestop 17:00 --weekdays Mon -j ACCEPT"),
+        "-A DFWRS_FORWARD -m time --timestart 08:00:00 --timestop 17:00:00 --weekdays Mon -j ACCEPT"
+    );
+    assert_eq!(
+        normalized(
+            "-m time --timestart 08:00:00 --timestop 17:00:00 \
+             --weekdays Mon,Tue,Wed,Thu,Fri,Sat,Sun --datestop 2038-01-19T03:14:07 -j ACCEPT"
+        ),
+        "-A DFWRS_FORWARD -m time --timestart 08:00:00 --timestop 17:00:00 -j ACCEPT"
+    );
+}
+
+#[test]
+fn parse_invalid() {
+    let error = |save: &str| format!("{}", IPTablesSave::parse(save).unwrap_err());
+
+    assert_eq!(
+        error("-A INPUT -j ACCEPT\n"),
+        "line 1 is outside of a table: '-A INPUT -j ACCEPT'"
+    );
+    assert_eq!(
+        error("*filter\n-I INPUT 1 -j ACCEPT\nCOMMIT\n"),
+        "line 2 is not a valid rule: '-I INPUT 1 -j ACCEPT'"
+    );
+    assert_eq!(
+        error("*filter\n:INPUT ACCEPT [1:x]\nCOMMIT\n"),
+        "line 2 has invalid counters: ':INPUT ACCEPT [1:x]'"
+    );
+    assert_eq!(
+        error("*filter\n:INPUT\nCOMMIT\n"),
+        "line 2 is not a valid chain: ':INPUT'"
+    );
+    assert_eq!(
+        error("*filter\n*nat\nCOMMIT\n"),
+        "line 2 starts a table before the previous one is committed: '*nat'"
+    );
+    assert_eq!(
+        error("*filter\n:INPUT ACCEPT [0:0]\n"),
+        "table `filter` is not committed"
+    );
+}
@@ -0,0 +1,149 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+extern crate serde_json;
+#[macro_use]
+extern crate slog;
+extern crate sloggers;
+
+mod common;
+
+use common::*;
+use dfw::logging::{build_logger, syslog_logger, LogDestination, LogFormat, Syslog};
+use serde_json::Value;
+use slog::{Drain, Logger};
+use sloggers::types::Severity;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::process;
+
+#[test]
+fn log_format_from_str() {
+    assert_eq!(
+        "terminal".parse::<LogFormat>().unwrap(),
+        LogFormat::Terminal
+    );
+    assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert!("xml".parse::<LogFormat>().is_err());
+}
+
+#[test]
+fn json_file() {
+    let path = temp_path("logging", "json_file");
+    {
+        let logger = build_logger(
+            LogFormat::Json,
+            &LogDestination::File(path.clone()),
+            Severity::Info,
+        )
+        .unwrap();
+        info!(logger, "Add forward rule";
+              o!("part" => "container_to_container",
+                 "rule" => "-j ACCEPT"));
+        debug!(logger, "Filtered by the severity");
+    }
+
+    let mut contents = String::new();
+    File::open(&path)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    let records: Vec<Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["msg"], "Add forward rule");
+    assert_eq!(records[0]["level"], "INFO");
+    assert_eq!(records[0]["part"], "container_to_container");
+    assert_eq!(records[0]["rule"], "-j ACCEPT");
+    assert!(records[0]["ts"].is_string());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn syslog_json() {
+    let path = temp_path("logging", "syslog_json");
+    let socket = bind_datagram(&path);
+    let drain = Syslog::connect(&path, LogFormat::Json).unwrap();
+    let logger = Logger::root(drain.fuse(), o!("thread" => "main"));
+
+    warn!(logger, "Could not convert DFWFW configuration";
+          o!("warning" => "`container_internals`: skipped"));
+
+    let datagram = receive(&socket);
+    let prefix = format!("<28>dfw[{}]: ", process::id());
+    assert!(datagram.starts_with(&prefix), "{}", datagram);
+    let record: Value = serde_json::from_str(&datagram[prefix.len()..]).unwrap();
+    assert_eq!(record["msg"], "Could not convert DFWFW configuration");
+    assert_eq!(record["level"], "WARN");
+    assert_eq!(record["warning"], "`container_internals`: skipped");
+    assert_eq!(record["thread"], "main");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn syslog_terminal() {
+    let path = temp_path("logging", "syslog_terminal");
+    let socket = bind_datagram(&path);
+    let drain = Syslog::connect(&path, LogFormat::Terminal).unwrap();
+    let logger = Logger::root(drain.fuse(), o!());
+
+    error!(logger, "Encountered error"; o!("error" => "no containers found"));
+    info!(logger, "Committing changes");
+
+    assert_eq!(
+        receive(&socket),
+        format!(
+            "<27>dfw[{}]: ERRO Encountered error, error: no containers found",
+            process::id()
+        )
+    );
+    assert_eq!(
+        receive(&socket),
+        format!("<30>dfw[{}]: INFO Committing changes", process::id())
+    );
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn syslog_unavailable() {
+    let path = temp_path("logging", "syslog_unavailable");
+
+    assert!(Syslog::connect(&path, LogFormat::Json).is_err());
+}
+
+#[test]
+fn syslog_reconnects() {
+    let path = temp_path("logging", "syslog_reconnects");
+    let socket = bind_datagram(&path);
+    let logger = syslog_logger(&path, LogFormat::Terminal, Severity::Info).unwrap();
+
+    // The syslog daemon goes away, the records are dropped without panicking.
+    drop(socket);
+    fs::remove_file(&path).unwrap();
+    info!(logger, "Committing changes");
+
+    // Once it is back, the records are delivered again.
+    let socket = bind_datagram(&path);
+    info!(logger, "Successfully committed changes");
+    assert_eq!(
+        receive(&socket),
+        format!(
+            "<30>dfw[{}]: INFO Successfully committed changes",
+            process::id()
+        )
+    );
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,319 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Tests running against a stand-in for the Docker-compatible API of Podman, which serves the
+//! responses recorded in `resources/test/podman`.
+
+extern crate dfw;
+#[macro_use]
+extern crate serde_json;
+extern crate shiplift;
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::*;
+use dfw::docker::{Connection, DockerOptions, Engine};
+use dfw::inventory::{DockerInventory, Inventory, StaticInventory};
+use dfw::iptables::{IPTablesRestore, IPVersion};
+use dfw::types::DFW;
+use dfw::util::load_file;
+use dfw::*;
+use shiplift::builder::{EventFilter, EventFilterType, EventsOptions};
+use shiplift::Docker;
+use slog::{Discard, Logger};
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process;
+use std::thread;
+
+const WEB_ID: &'static str = "5f6a2e0b7c4d3e8f9a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f6071";
+const DB_ID: &'static str = "a0b1c2d3e4f5061728394a5b6c7d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f9";
+const APP_NETWORK_ID: &'static str =
+    "1a9b0cd14f5e8a2b67c3d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3";
+const BRIDGE_NETWORK_ID: &'static str =
+    "2f259bab93aaaaa2542ba43ef33eb990d0999ee1b9924b557b7be53c0b7a1bb9";
+
+/// Map the path of a request to the file holding the recorded response.
+fn recorded_response(path: &str) -> Option<&'static str> {
+    // Strip the query, e.g. the filters of the container list.
+    let path = path.split('?').next().unwrap();
+    match path {
+        "/version" => Some("version.json"),
+        "/containers/json" => Some("containers.json"),
+        "/networks" => Some("networks.json"),
+        "/events" => Some("events.json"),
+        _ if path == format!("/networks/{}", APP_NETWORK_ID) => Some("network-app.json"),
+        _ if path == format!("/networks/{}", BRIDGE_NETWORK_ID) => Some("network-bridge.json"),
+        _ => None,
+    }
+}
+
+fn respond(stream: UnixStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    // Skip the headers, the requests don't have a body.
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match recorded_response(path) {
+        Some(file) => {
+            let mut body = String::new();
+            File::open(resource(&format!("podman/{}", file)).unwrap())
+                .unwrap()
+                .read_to_string(&mut body)
+                .unwrap();
+            ("200 OK", body)
+        }
+        None => (
+            "404 Not Found",
+            format!("{{\"message\":\"no such path {}\"}}", path),
+        ),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        body.len(),
+        body
+    )
+    .unwrap();
+}
+
+/// Start the stand-in on a new Unix socket, returning the URL to connect to.
+fn start_podman(name: &str) -> String {
+    let socket = env::temp_dir().join(format!("dfw-podman-{}-{}.sock", process::id(), name));
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            respond(stream.unwrap());
+        }
+    });
+
+    format!("unix://{}", socket.display())
+}
+
+fn connection(name: &str) -> Connection {
+    DockerOptions {
+        url: Some(start_podman(name)),
+        ..DockerOptions::default()
+    }
+    .resolve()
+    .unwrap()
+}
+
+fn connect(name: &str) -> Docker {
+    connection(name).connect().unwrap()
+}
+
+#[test]
+fn engine_from_version() {
+    assert_eq!(Engine::from_version("1.13.1"), Engine::Docker);
+    assert_eq!(Engine::from_version("17.03.3-ce"), Engine::Docker);
+    assert_eq!(Engine::from_version("18.06.1-ce"), Engine::Docker);
+    assert_eq!(Engine::from_version("20.10.21"), Engine::Docker);
+    assert_eq!(Engine::from_version("3.4.4"), Engine::Podman);
+    assert_eq!(Engine::from_version("4.3.1"), Engine::Podman);
+    assert_eq!(Engine::from_version("unknown"), Engine::Docker);
+}
+
+#[test]
+fn engine_from_version_info() {
+    let podman: serde_json::Value =
+        serde_json::from_reader(File::open(resource("podman/version.json").unwrap()).unwrap())
+            .unwrap();
+    assert_eq!(Engine::from_version_info(&podman), Engine::Podman);
+
+    // The components take precedence over the version.
+    let podman = json!({
+        "Components": [{ "Name": "Podman Engine", "Version": "20.1.0" }],
+        "Version": "20.1.0"
+    });
+    assert_eq!(Engine::from_version_info(&podman), Engine::Podman);
+    let docker = json!({
+        "Platform": { "Name": "Docker Engine - Community" },
+        "Components": [
+            { "Name": "Engine", "Version": "5.0.0" },
+            { "Name": "containerd", "Version": "1.6.28" }
+        ],
+        "Version": "5.0.0"
+    });
+    assert_eq!(Engine::from_version_info(&docker), Engine::Docker);
+
+    // Without components, the version decides.
+    assert_eq!(
+        Engine::from_version_info(&json!({ "Version": "1.13.1" })),
+        Engine::Docker
+    );
+    assert_eq!(
+        Engine::from_version_info(&json!({ "Version": "3.4.4" })),
+        Engine::Podman
+    );
+}
+
+#[test]
+fn detect_podman() {
+    let connection = connection("detect");
+    let docker = connection.connect().unwrap();
+    assert_eq!(
+        Engine::detect(&connection, &docker).unwrap(),
+        Engine::Podman
+    );
+}
+
+#[test]
+fn inventory_from_podman() {
+    let docker = connect("inventory");
+    let inventory = DockerInventory::new(&docker).with_engine(Engine::Podman);
+
+    let containers = inventory.containers(&ContainerFilter::Running).unwrap();
+    let mut names: Vec<_> = containers
+        .iter()
+        .map(|container| container.names[0].clone())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["/db", "/web"]);
+    assert!(containers.iter().all(|container| container.running));
+
+    let snapshot = StaticInventory::capture(&inventory).unwrap();
+    assert_eq!(snapshot.engine, Engine::Podman);
+    let app = snapshot
+        .networks
+        .iter()
+        .find(|network| network.network.name == "app")
+        .unwrap();
+    assert_eq!(
+        app.network.options["com.docker.network.bridge.name"],
+        "podman1"
+    );
+    assert_eq!(app.containers[WEB_ID].ipv4_address, "10.89.0.2/24");
+    assert_eq!(app.containers[DB_ID].ipv4_address, "10.89.0.3/24");
+
+    // The engine survives the round trip through JSON, and defaults to Docker.
+    let json = snapshot.to_json().unwrap();
+    assert_eq!(
+        StaticInventory::from_json(&json).unwrap().engine(),
+        Engine::Podman
+    );
+    assert_eq!(
+        StaticInventory::from_json("{}").unwrap().engine(),
+        Engine::Docker
+    );
+}
+
+#[test]
+fn process_podman_bridges() {
+    let docker = connect("process");
+    let inventory = DockerInventory::new(&docker).with_engine(Engine::Podman);
+    let toml: DFW = load_file(&resource("podman/conf.toml").unwrap()).unwrap();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: true,
+    };
+
+    let ipt4 = IPTablesRestore::new(IPVersion::IPv4).unwrap();
+    let ipt6 = IPTablesRestore::new(IPVersion::IPv6).unwrap();
+    ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap()
+    .generate()
+    .unwrap();
+
+    let rules = ipt4.get_rules();
+    // The bridges are named as reported by Podman, not after the network IDs.
+    assert!(rules.iter().all(|rule| !rule.contains("br-")));
+    for rule in &[
+        "-A DFWRS_FORWARD -s 10.89.0.2 -d 10.89.0.3 -i podman1 -o podman1 -j ACCEPT",
+        "-A DFWRS_FORWARD -i podman0 -o eth0 -j ACCEPT",
+        "-A DFWRS_INPUT -i podman0 -j ACCEPT",
+    ] {
+        assert!(rules.contains(&rule.to_string()), "missing rule: {}", rule);
+    }
+}
+
+#[test]
+fn process_podman_without_bridge_name() {
+    let mut inventory = StaticInventory::capture(
+        &DockerInventory::new(&connect("unknown-bridge")).with_engine(Engine::Podman),
+    )
+    .unwrap();
+    for network in &mut inventory.networks {
+        network.network.options.clear();
+    }
+    let toml: DFW = load_file(&resource("podman/conf.toml").unwrap()).unwrap();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: true,
+    };
+
+    let ipt4 = IPTablesRestore::new(IPVersion::IPv4).unwrap();
+    let ipt6 = IPTablesRestore::new(IPVersion::IPv6).unwrap();
+    let error = ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap()
+    .generate()
+    .unwrap_err();
+    assert!(format!("{}", error).contains("bridge of network `app` is unknown"));
+}
+
+#[test]
+fn podman_events() {
+    let docker = connect("events");
+    let statuses: Vec<_> = docker
+        .events(
+            &EventsOptions::builder()
+                .filter(vec![EventFilter::Type(EventFilterType::Container)])
+                .build(),
+        )
+        .unwrap()
+        .filter_map(|event| event.status)
+        .collect();
+    assert_eq!(statuses, vec!["init", "start", "died", "cleanup", "remove"]);
+
+    let triggering: Vec<_> = statuses
+        .iter()
+        .filter(|status| Engine::Podman.triggers_processing(status))
+        .collect();
+    assert_eq!(triggering, vec!["start", "died", "remove"]);
+    // Docker doesn't know about the Podman statuses.
+    assert!(!Engine::Docker.triggers_processing("died"));
+    assert!(!Engine::Docker.triggers_processing("remove"));
+}
@@ -0,0 +1,915 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Processing tests that run against a static inventory instead of a Docker daemon.
+//!
+//! The inventories in `resources/test/inventory` mirror the Docker Compose environments used by
+//! the `docker-tests`, which allows reusing their configurations and expected results.
+
+extern crate dfw;
+extern crate eval;
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+#[macro_use]
+extern crate slog;
+extern crate toml;
+
+mod common;
+mod logs;
+
+use common::*;
+use dfw::docker::Engine;
+use dfw::errors::RuleError;
+use dfw::inventory::{Container, Endpoint, Inventory, Network, StaticInventory};
+use dfw::iptables::{IPTablesLogger, IPTablesRestore, IPVersion};
+use dfw::ruleset::{Family, Interface, Placement, Provenance, Ruleset, Target};
+use dfw::types::*;
+use dfw::util::load_file;
+use dfw::*;
+use logs::*;
+use slog::{Discard, Logger};
+use std::cell::Cell;
+use std::collections::HashMap as Map;
+use std::fs::File;
+use std::io::prelude::*;
+
+fn load_inventory(num: &str) -> StaticInventory {
+    let mut contents = String::new();
+    File::open(resource(&format!("inventory/{}.json", num)).unwrap())
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+
+    StaticInventory::from_json(&contents).unwrap()
+}
+
+fn logs_to_loglines(logs: Vec<(String, Option<String>)>) -> Vec<LogLine> {
+    logs.into_iter()
+        .map(|(function, command)| LogLine {
+            function: function,
+            command: command,
+            regex: false,
+            eval: None,
+        })
+        .collect()
+}
+
+fn test_iptables_logger(num: &str) {
+    let toml: DFW = load_file(&resource(&format!("docker/{}/conf.toml", num)).unwrap()).unwrap();
+    let inventory = load_inventory(num);
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: false,
+    };
+
+    let ipt4 = IPTablesLogger::new();
+    let ipt6 = IPTablesLogger::new();
+    ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap()
+    .process()
+    .unwrap();
+
+    let expected4 =
+        load_loglines(&resource(&format!("docker/{}/expected-iptables-v4-logs.txt", num)).unwrap());
+    assert_eq!(logs_to_loglines(ipt4.logs()), expected4);
+
+    let expected6 =
+        load_loglines(&resource(&format!("docker/{}/expected-iptables-v6-logs.txt", num)).unwrap());
+    assert_eq!(logs_to_loglines(ipt6.logs()), expected6);
+}
+
+fn test_generate_iptables_restore(num: &str) {
+    let toml: DFW = load_file(&resource(&format!("docker/{}/conf.toml", num)).unwrap()).unwrap();
+    let inventory = load_inventory(num);
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: false,
+    };
+
+    // `generate` doesn't commit, so `iptables-restore` is never executed.
+    let ipt4 = IPTablesRestore::new(IPVersion::IPv4).unwrap();
+    let ipt6 = IPTablesRestore::new(IPVersion::IPv6).unwrap();
+    ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap()
+    .generate()
+    .unwrap();
+
+    let rules4 = ipt4
+        .get_rules()
+        .into_iter()
+        .map(|rule| ("-".to_owned(), Some(rule)))
+        .collect();
+    let expected4 = load_loglines(
+        &resource(&format!("docker/{}/expected-iptables-restore-v4.txt", num)).unwrap(),
+    );
+    assert_eq!(logs_to_loglines(rules4), expected4);
+}
+
+#[test]
+fn test_iptables_logger_01() {
+    test_iptables_logger("01");
+}
+
+#[test]
+fn test_iptables_logger_02() {
+    test_iptables_logger("02");
+}
+
+#[test]
+fn test_iptables_logger_03() {
+    test_iptables_logger("03");
+}
+
+#[test]
+fn test_iptables_logger_04() {
+    test_iptables_logger("04");
+}
+
+#[test]
+fn test_iptables_logger_05() {
+    test_iptables_logger("05");
+}
+
+#[test]
+fn test_iptables_logger_06() {
+    test_iptables_logger("06");
+}
+
+#[test]
+fn test_generate_iptables_restore_01() {
+    test_generate_iptables_restore("01");
+}
+
+#[test]
+fn test_generate_iptables_restore_02() {
+    test_generate_iptables_restore("02");
+}
+
+#[test]
+fn test_generate_iptables_restore_03() {
+    test_generate_iptables_restore("03");
+}
+
+#[test]
+fn test_generate_iptables_restore_04() {
+    test_generate_iptables_restore("04");
+}
+
+#[test]
+fn test_generate_iptables_restore_05() {
+    test_generate_iptables_restore("05");
+}
+
+#[test]
+fn test_generate_iptables_restore_06() {
+    test_generate_iptables_restore("06");
+}
+
+#[test]
+fn static_inventory_json_roundtrip() {
+    let inventory = load_inventory("06");
+
+    let json = inventory.to_json().unwrap();
+    assert_eq!(StaticInventory::from_json(&json).unwrap(), inventory);
+}
+
+#[test]
+fn static_inventory_capture() {
+    let inventory = load_inventory("06");
+
+    assert_eq!(StaticInventory::capture(&inventory).unwrap(), inventory);
+}
+
+#[test]
+fn static_inventory_container_filter() {
+    let mut inventory = load_inventory("02");
+    inventory.containers[1].running = false;
+
+    assert_eq!(
+        inventory.containers(&ContainerFilter::All).unwrap().len(),
+        2
+    );
+    assert_eq!(
+        inventory
+            .containers(&ContainerFilter::Running)
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn static_inventory_unknown_network() {
+    let inventory = load_inventory("01");
+
+    assert!(inventory.endpoints("unknown").is_err());
+}
+
+/// Calls logged by `IPTablesLogger`.
+type Logs = Vec<(String, Option<String>)>;
+
+fn process_toml(num: &str, toml: &str) -> Result<Logs, String> {
+    process_toml_with(num, toml, false).map(|(logs, _)| logs)
+}
+
+/// Process the configuration, returning the logged IPv4 calls and the errors of the skipped
+/// rules.
+fn process_toml_with(
+    num: &str,
+    toml: &str,
+    strict: bool,
+) -> Result<(Logs, Vec<RuleError>), String> {
+    let toml: DFW = toml::from_str(toml).unwrap();
+    let inventory = load_inventory(num);
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: strict,
+    };
+
+    let ipt4 = IPTablesLogger::new();
+    let ipt6 = IPTablesLogger::new();
+    let rule_errors = ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .and_then(|process| process.process().map(|_| process.rule_errors()))
+    .map_err(|e| e.to_string())?;
+
+    Ok((ipt4.logs(), rule_errors))
+}
+
+#[test]
+fn schedule_time_match() {
+    let logs = process_toml(
+        "03",
+        r#"
+        [container_to_wider_world]
+        default_policy = "DROP"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest03_default"
+        src_container = "dfwtest03_a_1"
+        action = "ACCEPT"
+        external_network_interface = "eni"
+        schedule = { days = "Mon-Fri", time = "22:00-6:00", timezone = "local" }
+        "#,
+    )
+    .unwrap();
+
+    let rule = logs
+        .iter()
+        .filter_map(|(_, command)| command.as_ref())
+        .find(|command| command.contains("-m time"))
+        .unwrap();
+    assert!(
+        rule.contains(
+            "-m time --timestart 22:00:00 --timestop 06:00:00 --weekdays Mon,Tue,Wed,Thu,Fri \
+             --kerneltz --contiguous -j ACCEPT"
+        ),
+        "{}",
+        rule
+    );
+}
+
+#[test]
+fn schedule_invalid() {
+    let error = process_toml_with(
+        "03",
+        r#"
+        [container_to_wider_world]
+        default_policy = "DROP"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest03_default"
+        action = "ACCEPT"
+        schedule = { days = "Mon-Fry" }
+        "#,
+        true,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error,
+        "rule 0 of section `container_to_wider_world`: \
+         invalid schedule: unknown day of the week 'Fry'"
+    );
+}
+
+const PARTIALLY_FAILING_CONFIG: &'static str = r#"
+    [container_to_container]
+    default_policy = "DROP"
+
+    [[container_to_container.rules]]
+    network = "dfwtest03_default"
+    action = "ACCEPT"
+    schedule = { time = "8:00" }
+
+    [[container_to_container.rules]]
+    network = "dfwtest03_default"
+    filter = "-p tcp --dport 80"
+    action = "ACCEPT"
+
+    [container_to_wider_world]
+    default_policy = "DROP"
+
+    [[container_to_wider_world.rules]]
+    network = "dfwtest03_default"
+    action = "ACCEPT"
+    schedule = { days = "Mon-Fry" }
+    "#;
+
+#[test]
+fn failing_rules_are_skipped() {
+    let (logs, rule_errors) = process_toml_with("03", PARTIALLY_FAILING_CONFIG, false).unwrap();
+
+    assert_eq!(
+        rule_errors,
+        vec![
+            RuleError {
+                section: "container_to_container".to_owned(),
+                rule: 0,
+                message: "invalid schedule: \
+                          time range '8:00' has invalid format, expected `HH:MM-HH:MM`"
+                    .to_owned(),
+            },
+            RuleError {
+                section: "container_to_wider_world".to_owned(),
+                rule: 0,
+                message: "invalid schedule: unknown day of the week 'Fry'".to_owned(),
+            },
+        ]
+    );
+
+    // The valid rule is applied and committed, nothing of the failing rules is.
+    let commands = logs
+        .iter()
+        .filter_map(|(_, command)| command.as_ref())
+        .collect::<Vec<_>>();
+    assert!(commands
+        .iter()
+        .any(|command| command.contains("-p tcp --dport 80 -j ACCEPT")));
+    assert!(!commands.iter().any(|command| command.contains("-m time")));
+    assert_eq!(logs.last().unwrap().0, "commit");
+}
+
+#[test]
+fn failing_rules_abort_in_strict_mode() {
+    let error = process_toml_with("03", PARTIALLY_FAILING_CONFIG, true).unwrap_err();
+
+    assert_eq!(
+        error,
+        "rule 0 of section `container_to_container`: \
+         invalid schedule: time range '8:00' has invalid format, expected `HH:MM-HH:MM`"
+    );
+}
+
+#[test]
+fn priority_orders_rules_across_sections() {
+    let logs = process_toml(
+        "03",
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [container_to_container]
+        default_policy = "DROP"
+
+        [[container_to_container.rules]]
+        network = "dfwtest03_default"
+        action = "ACCEPT"
+
+        [container_to_wider_world]
+        default_policy = "ACCEPT"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest03_default"
+        filter = "-p tcp --dport 25"
+        action = "DROP"
+        priority = 10
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest03_default"
+        filter = "-p tcp --dport 23"
+        action = "DROP"
+        priority = -1
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest03_default"
+        filter = "-p tcp --dport 22"
+        action = "REJECT"
+        priority = -1
+        "#,
+    )
+    .unwrap();
+
+    let forward_rules: Vec<&str> = logs
+        .iter()
+        .filter(|(function, _)| function == "append")
+        .filter_map(|(_, command)| command.as_ref())
+        .filter(|command| command.starts_with("filter DFWRS_FORWARD -i"))
+        .map(|command| command.as_str())
+        // The default policy of `container_to_wider_world` follows for every network.
+        .take(4)
+        .collect();
+    let bridge = "br-ba7fa850cf55";
+    assert_eq!(
+        forward_rules,
+        vec![
+            format!(
+                "filter DFWRS_FORWARD -i {} -o eni -p tcp --dport 23 -j DROP",
+                bridge
+            ),
+            format!(
+                "filter DFWRS_FORWARD -i {} -o eni -p tcp --dport 22 -j REJECT",
+                bridge
+            ),
+            format!("filter DFWRS_FORWARD -i {} -o {} -j ACCEPT", bridge, bridge),
+            format!(
+                "filter DFWRS_FORWARD -i {} -o eni -p tcp --dport 25 -j DROP",
+                bridge
+            ),
+        ]
+    );
+}
+
+/// Get the commands of the logged calls to the given function.
+fn commands_of<'a>(logs: &'a Logs, function: &str) -> Vec<&'a str> {
+    logs.iter()
+        .filter(|(f, _)| f == function)
+        .filter_map(|(_, command)| command.as_ref().map(String::as_str))
+        .collect()
+}
+
+#[test]
+fn swarm_published_ports() {
+    let logs = process_toml(
+        "swarm",
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [[wider_world_to_container.rules]]
+        network = "ingress"
+        dst_container = "web"
+        expose_port = 80
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        commands_of(&logs, "insert_unique"),
+        vec!["filter FORWARD -d 172.19.0.2 -i eni -o docker_gwbridge -j DFWRS_INGRESS 1"]
+    );
+    let appends = commands_of(&logs, "append");
+    for rule in &[
+        "filter DFWRS_INGRESS -d 172.19.0.2 -i eni -o docker_gwbridge -p tcp --dport 80 -j ACCEPT",
+        "nat DFWRS_PREROUTING -i eni -p tcp --dport 80 -j DNAT --to-destination 172.19.0.2:80",
+    ] {
+        assert!(appends.contains(rule), "missing rule: {}", rule);
+    }
+    // Published ports that are not exposed are dropped.
+    assert_eq!(
+        appends.iter().rev().find(|c| c.contains("DFWRS_INGRESS")),
+        Some(&"filter DFWRS_INGRESS -j DROP")
+    );
+}
+
+#[test]
+fn swarm_service_tasks() {
+    let logs = process_toml(
+        "swarm",
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [container_to_wider_world]
+        default_policy = "DROP"
+
+        [[container_to_wider_world.rules]]
+        network = "app"
+        src_container = "web"
+        action = "ACCEPT"
+
+        [container_to_host]
+        default_policy = "DROP"
+
+        [[container_to_host.rules]]
+        network = "app"
+        src_container = "db.1.p7ahc4x2n9q0wzk3e8d1r5t6y"
+        filter = "-p udp --dport 53"
+        action = "ACCEPT"
+        "#,
+    )
+    .unwrap();
+
+    let appends = commands_of(&logs, "append");
+    // Overlay networks reach the host through `docker_gwbridge`, every task of the service has a
+    // rule using its address within that network.
+    for rule in &[
+        "filter DFWRS_FORWARD -s 172.19.0.3 -i docker_gwbridge -o eni -j ACCEPT",
+        "filter DFWRS_FORWARD -s 172.19.0.4 -i docker_gwbridge -o eni -j ACCEPT",
+        "filter DFWRS_INPUT -s 172.19.0.5 -i docker_gwbridge -p udp --dport 53 -j ACCEPT",
+        "filter DFWRS_FORWARD -i docker_gwbridge -o eni -j DROP",
+        "filter DFWRS_INPUT -i docker_gwbridge -j DROP",
+    ] {
+        assert!(appends.contains(rule), "missing rule: {}", rule);
+    }
+    // The default policies are applied once for the bridge.
+    assert_eq!(
+        appends
+            .iter()
+            .filter(|c| **c == "filter DFWRS_INPUT -i docker_gwbridge -j DROP")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn swarm_unsupported_rules() {
+    let error = process_toml_with(
+        "swarm",
+        r#"
+        [container_to_container]
+        default_policy = "DROP"
+
+        [[container_to_container.rules]]
+        network = "app"
+        src_container = "web"
+        dst_container = "db"
+        action = "ACCEPT"
+        "#,
+        true,
+    )
+    .unwrap_err();
+    assert_eq!(
+        error,
+        "rule 0 of section `container_to_container`: container-to-container rules are not \
+         supported on overlay network `app`, its traffic does not pass the firewall of the host"
+    );
+
+    let error = process_toml_with(
+        "swarm",
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [[wider_world_to_container.rules]]
+        network = "app"
+        dst_container = "web"
+        expose_port = 80
+        "#,
+        true,
+    )
+    .unwrap_err();
+    assert_eq!(
+        error,
+        "rule 0 of section `wider_world_to_container`: service `web` has 2 tasks on this node, \
+         expose its ports through the `ingress` network"
+    );
+}
+
+/// Inventory counting the lookups of the endpoints of a network, and failing them for a network
+/// that was removed after the networks were listed.
+struct CountingInventory {
+    inner: StaticInventory,
+    endpoint_lookups: Cell<usize>,
+    removed_network: Option<String>,
+}
+
+impl CountingInventory {
+    fn new(num: &str) -> CountingInventory {
+        CountingInventory {
+            inner: load_inventory(num),
+            endpoint_lookups: Cell::new(0),
+            removed_network: None,
+        }
+    }
+
+    fn network_id(&self, name: &str) -> String {
+        self.inner
+            .networks
+            .iter()
+            .find(|network| network.network.name == name)
+            .map(|network| network.network.id.clone())
+            .unwrap()
+    }
+}
+
+impl Inventory for CountingInventory {
+    fn containers(&self, filter: &ContainerFilter) -> dfw::errors::Result<Vec<Container>> {
+        self.inner.containers(filter)
+    }
+
+    fn networks(&self) -> dfw::errors::Result<Vec<Network>> {
+        self.inner.networks()
+    }
+
+    fn endpoints(&self, network_id: &str) -> dfw::errors::Result<Map<String, Endpoint>> {
+        self.endpoint_lookups.set(self.endpoint_lookups.get() + 1);
+        if self.removed_network.as_deref() == Some(network_id) {
+            bail!("no such network: {}", network_id);
+        }
+        self.inner.endpoints(network_id)
+    }
+
+    fn engine(&self) -> Engine {
+        self.inner.engine()
+    }
+}
+
+#[test]
+fn endpoints_are_looked_up_once_per_network() {
+    let inventory = CountingInventory::new("03");
+    let mut toml = String::from(
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [container_to_wider_world]
+        default_policy = "DROP"
+        "#,
+    );
+    for port in 1..=50 {
+        toml.push_str(&format!(
+            r#"
+            [[container_to_wider_world.rules]]
+            network = "dfwtest03_default"
+            src_container = "dfwtest03_a_1"
+            filter = "-p tcp --dport {port}"
+            action = "ACCEPT"
+
+            [[wider_world_to_container.rules]]
+            network = "dfwtest03_default"
+            dst_container = "dfwtest03_a_1"
+            expose_port = ["{port}/tcp", "{port}/udp"]
+            "#,
+            port = port
+        ));
+    }
+    let toml: DFW = toml::from_str(&toml).unwrap();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: true,
+    };
+
+    let ipt4 = IPTablesLogger::new();
+    let ipt6 = IPTablesLogger::new();
+    ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap()
+    .process()
+    .unwrap();
+
+    // Only the network referenced by the rules is looked up, e.g. not `host` or `none`.
+    assert_eq!(inventory.endpoint_lookups.get(), 1);
+    // The rules still resolved the address of the container.
+    let commands = ipt4
+        .logs()
+        .into_iter()
+        .filter_map(|(_, command)| command)
+        .collect::<Vec<_>>();
+    assert!(commands
+        .iter()
+        .any(|command| command.contains("-s 172.") && command.contains("--dport 50 -j ACCEPT")));
+    assert!(commands
+        .iter()
+        .any(|command| command.contains("-p udp --dport 50 -j DNAT --to-destination 172.")));
+}
+
+#[test]
+fn ruleset_provenance_and_targets() {
+    let toml: DFW = load_file(&resource("docker/03/conf.toml").unwrap()).unwrap();
+    let inventory = load_inventory("03");
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: true,
+    };
+
+    let ipt4 = IPTablesRestore::new(IPVersion::IPv4).unwrap();
+    let ipt6 = IPTablesRestore::new(IPVersion::IPv6).unwrap();
+    let process = ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap();
+    let ruleset = process.ruleset().unwrap();
+    let rules = ruleset.rules();
+
+    let jump = rules
+        .iter()
+        .find(|rule| rule.chain == "INPUT")
+        .expect("missing jump to DFWRS_INPUT");
+    assert_eq!(jump.placement, Placement::AppendUnique);
+    assert_eq!(
+        jump.target,
+        Target::Jump {
+            chain: "DFWRS_INPUT".to_owned()
+        }
+    );
+    assert_eq!(jump.provenance, Provenance::Setup);
+
+    let ctww = |index| {
+        rules
+            .iter()
+            .find(|rule| {
+                rule.provenance
+                    == Provenance::Rule {
+                        section: "container_to_wider_world".to_owned(),
+                        index: index,
+                    }
+            })
+            .unwrap_or_else(|| panic!("missing rule {}", index))
+    };
+    assert_eq!(ctww(0).target, Target::Reject);
+    let accept = ctww(1);
+    assert_eq!(accept.family, Family::IPv4);
+    assert_eq!(accept.table, "filter");
+    assert_eq!(accept.chain, "DFWRS_FORWARD");
+    assert_eq!(accept.target, Target::Accept);
+    assert_eq!(accept.matches.out_interface, Some(Interface::new("eni")));
+    assert_eq!(
+        accept.matches.filter,
+        Some("-m state --state RELATED".to_owned())
+    );
+
+    // The ruleset survives the round trip through JSON, and applying it results in the rules
+    // applied by processing.
+    let json = ruleset.to_json().unwrap();
+    let parsed = Ruleset::from_json(&json).unwrap();
+    assert_eq!(parsed, ruleset);
+
+    let applied4 = IPTablesRestore::new(IPVersion::IPv4).unwrap();
+    let applied6 = IPTablesRestore::new(IPVersion::IPv6).unwrap();
+    parsed.apply(&applied4, &applied6, &logger).unwrap();
+    process.generate().unwrap();
+    assert_eq!(applied4.get_rules(), ipt4.get_rules());
+}
+
+#[test]
+fn removed_network_only_fails_its_rules() {
+    let mut inventory = CountingInventory::new("06");
+    inventory.removed_network = Some(inventory.network_id("dfwtest06_other"));
+    let toml: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [container_to_wider_world]
+        default_policy = "DROP"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest06_default"
+        src_container = "dfwtest06_a_1"
+        action = "ACCEPT"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest06_other"
+        src_container = "dfwtest06_b_1"
+        action = "ACCEPT"
+        "#,
+    )
+    .unwrap();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: false,
+    };
+
+    let ipt4 = IPTablesLogger::new();
+    let ipt6 = IPTablesLogger::new();
+    let process = ProcessDFW::new(
+        &inventory,
+        &toml,
+        &ipt4,
+        &ipt6,
+        &processing_options,
+        &logger,
+    )
+    .unwrap();
+    process.process().unwrap();
+
+    let rule_errors = process.rule_errors();
+    assert_eq!(rule_errors.len(), 1);
+    assert_eq!(rule_errors[0].section, "container_to_wider_world");
+    assert_eq!(rule_errors[0].rule, 1);
+    assert!(rule_errors[0].message.contains("no such network"));
+    assert!(commands_of(&ipt4.logs(), "append")
+        .iter()
+        .any(|command| command.contains("-s 172.18.0.2 ") && command.ends_with("-j ACCEPT")));
+}
+
+#[test]
+fn targeted_processing_reuses_cached_rules() {
+    let inventory = CountingInventory::new("06");
+    let toml: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eni"
+
+        [container_to_wider_world]
+        default_policy = "DROP"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest06_default"
+        src_container = "dfwtest06_a_1"
+        action = "ACCEPT"
+
+        [[container_to_wider_world.rules]]
+        network = "dfwtest06_other"
+        src_container = "dfwtest06_b_1"
+        action = "ACCEPT"
+        "#,
+    )
+    .unwrap();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: true,
+    };
+    let cache = ProcessingCache::new();
+    let process = |affected_containers: Option<Vec<String>>| {
+        inventory.endpoint_lookups.set(0);
+        let ipt4 = IPTablesLogger::new();
+        let ipt6 = IPTablesLogger::new();
+        ProcessDFW::new(
+            &inventory,
+            &toml,
+            &ipt4,
+            &ipt6,
+            &processing_options,
+            &logger,
+        )
+        .unwrap()
+        .with_cache(&cache, affected_containers)
+        .process()
+        .unwrap();
+
+        // The default-policy rules are generated per network in unspecified order.
+        let mut logs = ipt4.logs();
+        logs.sort();
+        (logs, inventory.endpoint_lookups.get())
+    };
+    let container_a = inventory
+        .inner
+        .containers
+        .iter()
+        .find(|container| container.names.contains(&"/dfwtest06_a_1".to_owned()))
+        .map(|container| container.id.clone())
+        .unwrap();
+
+    // The initial run populates the cache, resolving the rules on both networks.
+    let (full, lookups) = process(None);
+    assert_eq!(lookups, 2);
+
+    // Only the rule referencing `a` is regenerated, the rule for `b` is taken from the cache
+    // without looking up the endpoints of `dfwtest06_other`.
+    let (targeted, lookups) = process(Some(vec![container_a]));
+    assert_eq!(lookups, 1);
+    assert_eq!(targeted, full);
+
+    // An unknown container falls back to regenerating all rules.
+    let (fallback, lookups) = process(Some(vec!["unknown".to_owned()]));
+    assert_eq!(lookups, 2);
+    assert_eq!(fallback, full);
+}
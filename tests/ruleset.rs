@@ -0,0 +1,219 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+use common::*;
+use dfw::inventory::StaticInventory;
+use dfw::iptables::{IPTablesDummy, IPTablesSave};
+use dfw::ruleset::*;
+use dfw::types::{Schedule, DFW};
+use dfw::util::load_file;
+use dfw::*;
+use slog::{Discard, Logger};
+use std::fs::File;
+use std::io::prelude::*;
+
+fn read_resource(segment: &str) -> String {
+    let mut contents = String::new();
+    File::open(resource(segment).unwrap())
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+
+    contents
+}
+
+fn ruleset(num: &str) -> Ruleset {
+    let toml: DFW = load_file(&resource(&format!("docker/{}/conf.toml", num)).unwrap()).unwrap();
+    let inventory =
+        StaticInventory::from_json(&read_resource(&format!("inventory/{}.json", num))).unwrap();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        strict: true,
+    };
+
+    ProcessDFW::new(
+        &inventory,
+        &toml,
+        &IPTablesDummy,
+        &IPTablesDummy,
+        &processing_options,
+        &logger,
+    )
+    .unwrap()
+    .ruleset()
+    .unwrap()
+}
+
+fn lines(changes: &[Change]) -> Vec<String> {
+    changes
+        .iter()
+        .map(|change| format!("{} {}", change.table, change))
+        .collect()
+}
+
+#[test]
+fn diff_against_installed_rules() {
+    let changes = ruleset("03")
+        .diff(
+            Family::IPv4,
+            &IPTablesSave::parse(&read_resource("docker/03/iptables-save-v4.txt")).unwrap(),
+        )
+        .unwrap();
+
+    assert!(changes.iter().all(|change| change.family == Family::IPv4));
+    // Rules outside of the chains owned by DFW, like the ones of Docker, are not compared.
+    assert_eq!(
+        lines(&changes),
+        vec![
+            "filter - :DFWRS_INGRESS",
+            "filter - -A DFWRS_FORWARD -s 172.18.0.3/32 -i br-ba7fa850cf55 -o eni -m state \
+             --state RELATED -j ACCEPT",
+            "filter + -A DFWRS_FORWARD -s 172.18.0.2/32 -i br-ba7fa850cf55 -o eni -m state \
+             --state RELATED -j ACCEPT",
+            "filter - -A DFWRS_INGRESS -p tcp --dport 80 -j ACCEPT",
+            "filter - -A DFWRS_INGRESS -j DROP",
+            "filter - -A FORWARD -d 172.18.0.9/32 -i eth0 -o docker_gwbridge -j DFWRS_INGRESS",
+        ]
+    );
+}
+
+#[test]
+fn diff_against_installed_ipv6_rules() {
+    let changes = ruleset("03")
+        .diff(
+            Family::IPv6,
+            &IPTablesSave::parse(&read_resource("docker/03/iptables-save-v6.txt")).unwrap(),
+        )
+        .unwrap();
+
+    assert!(changes.iter().all(|change| change.family == Family::IPv6));
+    assert_eq!(
+        lines(&changes),
+        vec![
+            "filter - -A DFWRS_FORWARD -s fd00::3/128 -j ACCEPT",
+            "filter + -A DFWRS_FORWARD -m state --state RELATED,ESTABLISHED -j ACCEPT",
+        ]
+    );
+}
+
+#[test]
+fn diff_against_empty_host() {
+    let changes = ruleset("03")
+        .diff(Family::IPv4, &IPTablesSave::default())
+        .unwrap();
+
+    assert!(changes
+        .iter()
+        .all(|change| change.kind == ChangeKind::Added));
+    assert!(lines(&changes).contains(&"filter + :DFWRS_FORWARD".to_owned()));
+    assert!(lines(&changes).contains(&"filter + -A INPUT -j DFWRS_INPUT".to_owned()));
+    assert!(lines(&changes).contains(&"nat + -A PREROUTING -j DFWRS_PREROUTING".to_owned()));
+}
+
+#[test]
+fn diff_normalizes_rules() {
+    let ruleset = Ruleset {
+        entries: vec![
+            Entry::Chain(Chain {
+                family: Family::IPv4,
+                table: "filter".to_owned(),
+                name: "DFWRS_FORWARD".to_owned(),
+            }),
+            Entry::Rule(Box::new(Rule {
+                family: Family::IPv4,
+                table: "filter".to_owned(),
+                chain: "DFWRS_FORWARD".to_owned(),
+                placement: Placement::Append,
+                matches: Matches {
+                    destination: Some("172.18.0.2".to_owned()),
+                    protocol: Some("tcp".to_owned()),
+                    destination_port: Some("80".to_owned()),
+                    comment: Some("web server".to_owned()),
+                    ..Matches::default()
+                },
+                target: Target::Accept,
+                provenance: Provenance::Setup,
+            })),
+        ],
+    };
+    let installed = "*filter\n\
+                     :DFWRS_FORWARD - [0:0]\n\
+                     -A DFWRS_FORWARD -d 172.18.0.2/32 -p tcp -m tcp --dport 80 \
+                     -m comment --comment \"web server\" -j ACCEPT\n\
+                     COMMIT\n";
+
+    assert_eq!(
+        ruleset
+            .diff(Family::IPv4, &installed.parse().unwrap())
+            .unwrap(),
+        vec![]
+    );
+}
+
+fn scheduled_rule(source: &str, days: &[&str], time: &str, timezone: &str) -> Entry {
+    Entry::Rule(Box::new(Rule {
+        family: Family::IPv4,
+        table: "filter".to_owned(),
+        chain: "DFWRS_FORWARD".to_owned(),
+        placement: Placement::Append,
+        matches: Matches {
+            source: Some(source.to_owned()),
+            schedule: Some(Schedule {
+                days: Some(days.iter().map(|day| (*day).to_owned()).collect()),
+                time: Some(time.to_owned()),
+                timezone: Some(timezone.to_owned()),
+            }),
+            ..Matches::default()
+        },
+        target: Target::Accept,
+        provenance: Provenance::Setup,
+    }))
+}
+
+#[test]
+fn diff_scheduled_rules() {
+    // The installed rules are printed with seconds, the default end date and without the
+    // weekdays if all of them are set.
+    let ruleset = Ruleset {
+        entries: vec![
+            Entry::Chain(Chain {
+                family: Family::IPv4,
+                table: "filter".to_owned(),
+                name: "DFWRS_FORWARD".to_owned(),
+            }),
+            Entry::Rule(Box::new(Rule {
+                family: Family::IPv4,
+                table: "filter".to_owned(),
+                chain: "FORWARD".to_owned(),
+                placement: Placement::AppendUnique,
+                matches: Matches::default(),
+                target: Target::Jump {
+                    chain: "DFWRS_FORWARD".to_owned(),
+                },
+                provenance: Provenance::Setup,
+            })),
+            scheduled_rule("172.18.0.2", &["Mon-Fri"], "08:00-17:00", "local"),
+            scheduled_rule("172.18.0.3", &["Mon-Sun"], "22:00-06:00", "utc"),
+        ],
+    };
+    let installed = read_resource("iptables-save/schedule-v4.txt");
+
+    assert_eq!(
+        ruleset
+            .diff(Family::IPv4, &installed.parse().unwrap())
+            .unwrap(),
+        vec![]
+    );
+}
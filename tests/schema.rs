@@ -0,0 +1,387 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+extern crate dfw;
+extern crate glob;
+#[macro_use]
+extern crate serde_json;
+
+mod common;
+
+use common::resource;
+use dfw::schema::dfw_schema;
+use dfw::types::DFW;
+use dfw::util::Format;
+use glob::glob;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::prelude::*;
+
+/// Validate the instance against the schema, supporting the subset of JSON Schema used by
+/// `dfw_schema`. Returns the JSON pointers of all violations.
+fn validate(root: &Value, schema: &Value, instance: &Value, pointer: &str) -> Vec<String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let target = root
+            .pointer(reference.trim_start_matches('#'))
+            .unwrap_or_else(|| panic!("unresolvable reference: {}", reference));
+        return validate(root, target, instance, pointer);
+    }
+
+    let mut errors = Vec::new();
+
+    if let Some(any_of) = schema["anyOf"].as_array() {
+        if !any_of
+            .iter()
+            .any(|schema| validate(root, schema, instance, pointer).is_empty())
+        {
+            errors.push(format!("{}: matches no alternative", pointer));
+        }
+    }
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match *types {
+            Value::String(ref t) => vec![t],
+            Value::Array(ref ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => panic!("invalid type in schema"),
+        };
+        let matches = types.iter().any(|t| match *t {
+            "null" => instance.is_null(),
+            "string" => instance.is_string(),
+            "integer" => instance.is_i64() || instance.is_u64(),
+            "array" => instance.is_array(),
+            "object" => instance.is_object(),
+            t => panic!("unsupported type in schema: {}", t),
+        });
+        if !matches {
+            errors.push(format!("{}: expected {:?}", pointer, types));
+            return errors;
+        }
+    }
+
+    if let Some(i) = instance.as_i64() {
+        match (schema["minimum"].as_i64(), schema["maximum"].as_i64()) {
+            (Some(minimum), _) if i < minimum => errors.push(format!("{}: below minimum", pointer)),
+            (_, Some(maximum)) if i > maximum => errors.push(format!("{}: above maximum", pointer)),
+            _ => {}
+        }
+    }
+
+    if let Some(object) = instance.as_object() {
+        if let Some(required) = schema["required"].as_array() {
+            for key in required.iter().filter_map(|key| key.as_str()) {
+                if !object.contains_key(key) {
+                    errors.push(format!("{}: missing `{}`", pointer, key));
+                }
+            }
+        }
+        for (key, value) in object {
+            let pointer = format!("{}/{}", pointer, key);
+            match (
+                schema["properties"].get(key),
+                schema.get("additionalProperties"),
+            ) {
+                (Some(schema), _) => errors.extend(validate(root, schema, value, &pointer)),
+                (None, Some(&Value::Bool(false))) => {
+                    errors.push(format!("{}: unknown property", pointer))
+                }
+                (None, Some(schema)) if schema.is_object() => {
+                    errors.extend(validate(root, schema, value, &pointer))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let (Some(array), Some(items)) = (instance.as_array(), schema.get("items")) {
+        for (index, value) in array.iter().enumerate() {
+            errors.extend(validate(
+                root,
+                items,
+                value,
+                &format!("{}/{}", pointer, index),
+            ));
+        }
+    }
+
+    errors
+}
+
+fn validate_file(path: &str) -> Vec<String> {
+    let mut contents = String::new();
+    File::open(path)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    let instance: Value = Format::from_path(path)
+        .unwrap()
+        .deserialize(&contents)
+        .unwrap();
+
+    let schema = dfw_schema();
+    validate(&schema, &schema, &instance, "")
+}
+
+#[test]
+fn schema_accepts_test_configurations() {
+    // The files of configuration directories are fragments, which don't have to be valid on
+    // their own.
+    let patterns = ["*.toml", "*.yaml", "*.json", "docker/*/conf.toml"];
+    let mut validated = 0;
+    for pattern in &patterns {
+        for path in glob(&resource(pattern).unwrap()).unwrap() {
+            let path = path.unwrap().to_str().unwrap().to_owned();
+            // These files are intentionally invalid.
+            if path.contains("invalid") {
+                continue;
+            }
+            assert_eq!(validate_file(&path), Vec::<String>::new(), "{}", path);
+            validated += 1;
+        }
+    }
+
+    assert!(validated >= 10);
+}
+
+#[test]
+fn schema_accepts_examples() {
+    let pattern = format!(
+        "{}/examples/full-single-file/*.toml",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    for path in glob(&pattern).unwrap() {
+        let path = path.unwrap().to_str().unwrap().to_owned();
+        assert_eq!(validate_file(&path), Vec::<String>::new(), "{}", path);
+    }
+}
+
+#[test]
+fn schema_expose_port_forms() {
+    let schema = dfw_schema();
+    let expose_port = json!({ "$ref": "#/definitions/ExposePorts" });
+
+    for valid in &[
+        json!(80),
+        json!("53/udp"),
+        json!({ "host_port": 8080, "container_port": 80, "family": "tcp" }),
+        json!([80, 443]),
+        json!(["80/tcp", "53/udp"]),
+        json!([{ "host_port": 80 }, { "host_port": 53, "family": "udp" }]),
+        json!([80, "53/udp", { "host_port": 443 }]),
+    ] {
+        assert!(
+            validate(&schema, &expose_port, valid, "").is_empty(),
+            "{}",
+            valid
+        );
+    }
+
+    for invalid in &[
+        json!(70000),
+        json!(true),
+        json!({ "container_port": 80 }),
+        json!({ "host_port": 80, "protocol": "tcp" }),
+        json!([[80]]),
+    ] {
+        assert!(
+            !validate(&schema, &expose_port, invalid, "").is_empty(),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn schema_external_network_interfaces_forms() {
+    let schema = dfw_schema();
+
+    for valid in &[json!("eth0"), json!(["eth0", "eth1"]), Value::Null] {
+        let config = json!({ "defaults": { "external_network_interfaces": valid } });
+        assert!(validate(&schema, &schema, &config, "").is_empty());
+    }
+
+    let config = json!({ "defaults": { "external_network_interfaces": 1 } });
+    assert!(!validate(&schema, &schema, &config, "").is_empty());
+}
+
+#[test]
+fn schema_rejects_unknown_fields() {
+    let schema = dfw_schema();
+    let config = json!({
+        "container_to_container": {
+            "default_policy": "DROP",
+            "rules": [{ "network": "frontend", "dst_contaner": "db", "action": "ACCEPT" }]
+        }
+    });
+
+    assert_eq!(
+        validate(&schema, &schema, &config, ""),
+        vec!["/container_to_container/rules/0/dst_contaner: unknown property".to_owned()]
+    );
+}
+
+#[test]
+fn schema_schedule_forms() {
+    let schema = dfw_schema();
+    let schedule = json!({ "$ref": "#/definitions/Schedule" });
+
+    for valid in &[
+        json!({ "days": "Mon-Fri" }),
+        json!({ "days": ["Sat", "Sun"], "time": "22:00-02:00", "timezone": "local" }),
+        json!({ "time": "08:00-17:00" }),
+    ] {
+        assert!(
+            validate(&schema, &schedule, valid, "").is_empty(),
+            "{}",
+            valid
+        );
+    }
+
+    for invalid in &[
+        json!("Mon-Fri"),
+        json!({ "days": 1 }),
+        json!({ "weekdays": "Mon" }),
+    ] {
+        assert!(
+            !validate(&schema, &schedule, invalid, "").is_empty(),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn schema_definitions_accept_expose_ports() {
+    let schema = dfw_schema();
+    let config = json!({
+        "definitions": {
+            "ports": {
+                "web": [80, "443/tcp", { "host_port": 8080, "container_port": 80 }],
+                "dns": { "host_port": 53, "family": "udp" }
+            }
+        }
+    });
+
+    assert_eq!(
+        validate(&schema, &schema, &config, ""),
+        Vec::<String>::new()
+    );
+}
+
+/// Names of the properties the schema defines for an object.
+fn schema_properties(schema: &Value) -> BTreeSet<String> {
+    schema["properties"]
+        .as_object()
+        .unwrap_or_else(|| panic!("no properties: {}", schema))
+        .keys()
+        .cloned()
+        .collect()
+}
+
+/// Names of the fields of a serialized struct.
+fn serialized_fields(value: &Value) -> BTreeSet<String> {
+    value.as_object().unwrap().keys().cloned().collect()
+}
+
+#[test]
+fn schema_properties_match_types() {
+    let schema = dfw_schema();
+    let definition = |name: &str| &schema["definitions"][name];
+
+    // Deserializing a configuration and serializing it again yields all fields of the types,
+    // including the ones that aren't set.
+    let schedule = json!({ "days": "Mon" });
+    let expose_port = json!([{ "host_port": 80 }]);
+    let dfw: DFW = serde_json::from_value(json!({
+        "defaults": {},
+        "initialization": {},
+        "container_to_container": {
+            "default_policy": "DROP",
+            "rules": [{
+                "network": "n", "action": "ACCEPT", "schedule": schedule
+            }]
+        },
+        "container_to_wider_world": {
+            "default_policy": "DROP",
+            "rules": [{ "action": "ACCEPT" }]
+        },
+        "container_to_host": {
+            "default_policy": "DROP",
+            "rules": [{ "network": "n", "action": "ACCEPT" }]
+        },
+        "wider_world_to_container": {
+            "rules": [{ "network": "n", "dst_container": "c", "expose_port": expose_port }]
+        },
+        "container_dnat": {
+            "rules": [{ "dst_network": "n", "dst_container": "c", "expose_port": expose_port }]
+        }
+    }))
+    .unwrap();
+    let dfw = serde_json::to_value(&dfw).unwrap();
+
+    // The `definitions` are resolved before deserializing, so they aren't part of the types.
+    let mut properties = schema_properties(&schema);
+    assert!(properties.remove("definitions"));
+    assert_eq!(properties, serialized_fields(&dfw));
+
+    for &(field, section, rule) in &[
+        ("defaults", "Defaults", None),
+        ("initialization", "Initialization", None),
+        (
+            "container_to_container",
+            "ContainerToContainer",
+            Some("ContainerToContainerRule"),
+        ),
+        (
+            "container_to_wider_world",
+            "ContainerToWiderWorld",
+            Some("ContainerToWiderWorldRule"),
+        ),
+        (
+            "container_to_host",
+            "ContainerToHost",
+            Some("ContainerToHostRule"),
+        ),
+        (
+            "wider_world_to_container",
+            "WiderWorldToContainer",
+            Some("WiderWorldToContainerRule"),
+        ),
+        ("container_dnat", "ContainerDNAT", Some("ContainerDNATRule")),
+    ] {
+        assert_eq!(
+            schema_properties(definition(section)),
+            serialized_fields(&dfw[field]),
+            "{}",
+            section
+        );
+        if let Some(rule) = rule {
+            assert_eq!(
+                schema_properties(definition(rule)),
+                serialized_fields(&dfw[field]["rules"][0]),
+                "{}",
+                rule
+            );
+        }
+    }
+
+    assert_eq!(
+        schema_properties(definition("Schedule")),
+        serialized_fields(&dfw["container_to_container"]["rules"][0]["schedule"])
+    );
+    // The struct form of a port is the last alternative of `ExposePort`.
+    let expose_port = definition("ExposePort")["anyOf"]
+        .as_array()
+        .unwrap()
+        .last()
+        .unwrap();
+    assert_eq!(
+        schema_properties(expose_port),
+        serialized_fields(&dfw["wider_world_to_container"]["rules"][0]["expose_port"][0])
+    );
+}
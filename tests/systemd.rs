@@ -0,0 +1,91 @@
+// Copyright 2017, 2018 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(unix)]
+
+extern crate dfw;
+#[macro_use]
+extern crate lazy_static;
+
+mod common;
+
+use common::*;
+use dfw::systemd;
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+lazy_static! {
+    // The tests modify the environment of the process, which is shared by all tests.
+    static ref ENV_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[test]
+fn notify_without_socket() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    env::remove_var("NOTIFY_SOCKET");
+
+    assert!(!systemd::notify(systemd::READY).unwrap());
+}
+
+#[test]
+fn notify_sends_state() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    let path = temp_path("notify", "state");
+    let socket = bind_datagram(&path);
+    env::set_var("NOTIFY_SOCKET", &path);
+
+    assert!(systemd::notify(systemd::READY).unwrap());
+    assert_eq!(receive(&socket), "READY=1");
+
+    let status = systemd::status("Failed to apply rules:\nsome error");
+    assert!(systemd::notify(&status).unwrap());
+    assert_eq!(receive(&socket), "STATUS=Failed to apply rules: some error");
+
+    assert!(systemd::notify(systemd::WATCHDOG).unwrap());
+    assert_eq!(receive(&socket), "WATCHDOG=1");
+
+    env::remove_var("NOTIFY_SOCKET");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn notify_unsupported_socket() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    env::set_var("NOTIFY_SOCKET", "@dfw-notify");
+
+    assert!(systemd::notify(systemd::READY).is_err());
+
+    env::remove_var("NOTIFY_SOCKET");
+}
+
+#[test]
+fn watchdog_interval() {
+    let _lock = ENV_LOCK.lock().unwrap();
+    env::remove_var("WATCHDOG_PID");
+    env::remove_var("WATCHDOG_USEC");
+    assert_eq!(systemd::watchdog_interval(), None);
+
+    env::set_var("WATCHDOG_USEC", "30000000");
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(15)));
+
+    env::set_var("WATCHDOG_PID", process::id().to_string());
+    assert_eq!(systemd::watchdog_interval(), Some(Duration::from_secs(15)));
+
+    // The watchdog is meant for another process.
+    env::set_var("WATCHDOG_PID", (process::id() + 1).to_string());
+    assert_eq!(systemd::watchdog_interval(), None);
+
+    env::remove_var("WATCHDOG_PID");
+    env::set_var("WATCHDOG_USEC", "0");
+    assert_eq!(systemd::watchdog_interval(), None);
+
+    env::remove_var("WATCHDOG_USEC");
+}
@@ -14,8 +14,10 @@ extern crate toml;
 mod common;
 
 use common::resource;
+use dfw::errors::ConfigError;
 use dfw::types::*;
 use dfw::util::*;
+use std::path::PathBuf;
 
 #[test]
 fn parse_conf_file() {
@@ -38,6 +40,8 @@ fn parse_conf_file() {
             dst_container: Some("dst_container".to_owned()),
             filter: Some("FILTER".to_owned()),
             action: "ACCEPT".to_owned(),
+            schedule: None,
+            priority: None,
         }]),
     };
     let container_to_wider_world = ContainerToWiderWorld {
@@ -48,6 +52,8 @@ fn parse_conf_file() {
             filter: Some("FILTER".to_owned()),
             action: "ACCEPT".to_owned(),
             external_network_interface: Some("eni".to_owned()),
+            schedule: None,
+            priority: None,
         }]),
     };
     let container_to_host = ContainerToHost {
@@ -57,6 +63,8 @@ fn parse_conf_file() {
             src_container: Some("src_container".to_owned()),
             filter: Some("FILTER".to_owned()),
             action: "ACCEPT".to_owned(),
+            schedule: None,
+            priority: None,
         }]),
     };
     let wider_world_to_container = WiderWorldToContainer {
@@ -69,6 +77,8 @@ fn parse_conf_file() {
                 family: "tcp".to_owned(),
             }],
             external_network_interface: Some("eni".to_owned()),
+            schedule: None,
+            priority: None,
         }]),
     };
     let container_dnat = ContainerDNAT {
@@ -82,6 +92,8 @@ fn parse_conf_file() {
                 container_port: None,
                 family: "tcp".to_owned(),
             }],
+            schedule: None,
+            priority: None,
         }]),
     };
 
@@ -121,6 +133,8 @@ fn parse_conf_path() {
             dst_container: Some("dst_container".to_owned()),
             filter: Some("FILTER".to_owned()),
             action: "ACCEPT".to_owned(),
+            schedule: None,
+            priority: None,
         }]),
     };
     let container_to_wider_world = ContainerToWiderWorld {
@@ -131,6 +145,8 @@ fn parse_conf_path() {
             filter: Some("FILTER".to_owned()),
             action: "ACCEPT".to_owned(),
             external_network_interface: Some("eni".to_owned()),
+            schedule: None,
+            priority: None,
         }]),
     };
     let container_to_host = ContainerToHost {
@@ -140,6 +156,8 @@ fn parse_conf_path() {
             src_container: Some("src_container".to_owned()),
             filter: Some("FILTER".to_owned()),
             action: "ACCEPT".to_owned(),
+            schedule: None,
+            priority: None,
         }]),
     };
     let wider_world_to_container = WiderWorldToContainer {
@@ -152,6 +170,8 @@ fn parse_conf_path() {
                 family: "tcp".to_owned(),
             }],
             external_network_interface: Some("eni".to_owned()),
+            schedule: None,
+            priority: None,
         }]),
     };
     let container_dnat = ContainerDNAT {
@@ -165,6 +185,8 @@ fn parse_conf_path() {
                 container_port: None,
                 family: "tcp".to_owned(),
             }],
+            schedule: None,
+            priority: None,
         }]),
     };
 
@@ -183,6 +205,175 @@ fn parse_conf_path() {
     assert_eq!(expected, actual);
 }
 
+#[test]
+fn parse_conf_file_yaml() {
+    let expected: DFW = load_file(&resource("conf-file.toml").unwrap()).unwrap();
+    let actual: DFW = load_file(&resource("conf-file.yaml").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_conf_file_json() {
+    let expected: DFW = load_file(&resource("conf-file.toml").unwrap()).unwrap();
+    let actual: DFW = load_file(&resource("conf-file.json").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_conf_path_mixed() {
+    let expected: DFW = load_path(&resource("conf_path").unwrap()).unwrap();
+    let actual: DFW = load_path(&resource("conf_path_mixed").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_conf_path_merge() {
+    let expected = DFW {
+        defaults: Some(Defaults {
+            external_network_interfaces: Some(vec!["eni".to_owned()]),
+        }),
+        initialization: None,
+        container_to_container: Some(ContainerToContainer {
+            default_policy: "DROP".to_owned(),
+            rules: Some(vec![
+                ContainerToContainerRule {
+                    network: "network".to_owned(),
+                    src_container: Some("src_container".to_owned()),
+                    dst_container: Some("dst_container".to_owned()),
+                    filter: None,
+                    action: "ACCEPT".to_owned(),
+                    schedule: None,
+                    priority: None,
+                },
+                ContainerToContainerRule {
+                    network: "network".to_owned(),
+                    src_container: Some("web".to_owned()),
+                    dst_container: Some("db".to_owned()),
+                    filter: None,
+                    action: "ACCEPT".to_owned(),
+                    schedule: None,
+                    priority: None,
+                },
+                ContainerToContainerRule {
+                    network: "network".to_owned(),
+                    src_container: Some("prometheus".to_owned()),
+                    dst_container: None,
+                    filter: None,
+                    action: "ACCEPT".to_owned(),
+                    schedule: None,
+                    priority: None,
+                },
+            ]),
+        }),
+        container_to_wider_world: None,
+        container_to_host: None,
+        wider_world_to_container: None,
+        container_dnat: None,
+    };
+
+    let actual: DFW = load_path(&resource("conf_path_merge").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_conf_path_merge_lists() {
+    // Only the commands of the `initialization` section are appended, other lists have to agree.
+    let actual: DFW = load_path(&resource("conf_path_merge_lists").unwrap()).unwrap();
+
+    assert_eq!(
+        actual.defaults.unwrap().external_network_interfaces,
+        Some(vec!["eth0".to_owned(), "eth1".to_owned()])
+    );
+    assert_eq!(
+        actual.initialization.unwrap().v4.unwrap()["filter"],
+        vec!["-P INPUT DROP".to_owned(), "-P FORWARD DROP".to_owned()]
+    );
+}
+
+#[test]
+fn parse_conf_path_conflict() {
+    let error = load_path::<DFW>(&resource("conf_path_conflict").unwrap())
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("container_to_container.default_policy"));
+    assert!(error.contains("10-drop.toml"));
+    assert!(error.contains("20-accept.toml"));
+}
+
+#[test]
+fn parse_conf_path_conflict_lists() {
+    let error = load_path::<DFW>(&resource("conf_path_conflict_lists").unwrap())
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("defaults.external_network_interfaces"));
+    assert!(error.contains("10-defaults.toml"));
+    assert!(error.contains("20-web.toml"));
+}
+
+#[test]
+fn parse_conf_path_invalid() {
+    let error = load_path::<DFW>(&resource("conf_path_invalid").unwrap())
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("broken.toml"));
+}
+
+#[test]
+fn parse_conf_file_definitions() {
+    let port = |host_port, family: &str| ExposePort {
+        host_port: host_port,
+        container_port: None,
+        family: family.to_owned(),
+    };
+    let expected = DFW {
+        defaults: Some(Defaults {
+            external_network_interfaces: Some(vec!["eni".to_owned()]),
+        }),
+        initialization: None,
+        container_to_container: None,
+        container_to_wider_world: None,
+        container_to_host: Some(ContainerToHost {
+            default_policy: "DROP".to_owned(),
+            rules: Some(vec![ContainerToHostRule {
+                network: "project_frontend".to_owned(),
+                src_container: Some("project_proxy_1".to_owned()),
+                filter: Some("-s 192.0.2.0/24,198.51.100.0/24".to_owned()),
+                action: "ACCEPT".to_owned(),
+                schedule: None,
+                priority: None,
+            }]),
+        }),
+        wider_world_to_container: Some(WiderWorldToContainer {
+            rules: Some(vec![WiderWorldToContainerRule {
+                network: "project_frontend".to_owned(),
+                dst_container: "project_proxy_1".to_owned(),
+                expose_port: vec![port(80, "tcp"), port(443, "tcp"), port(8080, "tcp")],
+                external_network_interface: Some("eni".to_owned()),
+                schedule: None,
+                priority: None,
+            }]),
+        }),
+        container_dnat: None,
+    };
+
+    let actual: DFW = load_file(&resource("conf-definitions.toml").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+#[should_panic(expected = "unsupported configuration format")]
+fn parse_conf_file_unsupported_format() {
+    load_file::<DFW>(&resource("conf_path").unwrap()).unwrap();
+}
+
 #[test]
 fn parse_expose_port_single_int() {
     let fragment = r#"
@@ -200,6 +391,8 @@ fn parse_expose_port_single_int() {
             family: "tcp".to_owned(),
         }],
         external_network_interface: None,
+        schedule: None,
+        priority: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -230,6 +423,8 @@ fn parse_expose_port_seq_int() {
             },
         ],
         external_network_interface: None,
+        schedule: None,
+        priority: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -257,6 +452,8 @@ fn parse_expose_port_single_string() {
                 family: family.to_owned(),
             }],
             external_network_interface: None,
+            schedule: None,
+            priority: None,
         };
         let actual: WiderWorldToContainerRule = toml::from_str(&fragment).unwrap();
 
@@ -293,6 +490,8 @@ fn parse_expose_port_seq_string() {
             },
         ],
         external_network_interface: None,
+        schedule: None,
+        priority: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -323,6 +522,8 @@ fn parse_expose_port_single_struct() {
                 family: "tcp".to_owned(),
             }],
             external_network_interface: None,
+            schedule: None,
+            priority: None,
         };
         let actual: WiderWorldToContainerRule = toml::from_str(&fragment).unwrap();
 
@@ -369,6 +570,8 @@ fn parse_expose_port_seq_struct() {
             },
         ],
         external_network_interface: None,
+        schedule: None,
+        priority: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -434,3 +637,372 @@ fn parse_external_network_interfaces_seq() {
 
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn parse_external_network_interfaces_null_yaml() {
+    let fragment = r#"external_network_interfaces: ~"#;
+
+    let expected = Defaults {
+        external_network_interfaces: None,
+    };
+    let actual: Defaults = Format::Yaml.deserialize(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_expose_port_yaml() {
+    let fragment = r#"
+        network: network
+        dst_container: dst_container
+        expose_port:
+            - 80
+            - 53/udp
+            - { host_port: 8080, container_port: 80 }
+        "#;
+
+    let expected = WiderWorldToContainerRule {
+        network: "network".to_owned(),
+        dst_container: "dst_container".to_owned(),
+        expose_port: vec![
+            ExposePort {
+                host_port: 80,
+                container_port: None,
+                family: "tcp".to_owned(),
+            },
+            ExposePort {
+                host_port: 53,
+                container_port: None,
+                family: "udp".to_owned(),
+            },
+            ExposePort {
+                host_port: 8080,
+                container_port: Some(80),
+                family: "tcp".to_owned(),
+            },
+        ],
+        external_network_interface: None,
+        schedule: None,
+        priority: None,
+    };
+    let actual: WiderWorldToContainerRule = Format::Yaml.deserialize(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_expose_port_json() {
+    let fragment = r#"{
+        "network": "network",
+        "dst_container": "dst_container",
+        "expose_port": 80
+    }"#;
+
+    let expected = WiderWorldToContainerRule {
+        network: "network".to_owned(),
+        dst_container: "dst_container".to_owned(),
+        expose_port: vec![ExposePort {
+            host_port: 80,
+            container_port: None,
+            family: "tcp".to_owned(),
+        }],
+        external_network_interface: None,
+        schedule: None,
+        priority: None,
+    };
+    let actual: WiderWorldToContainerRule = Format::Json.deserialize(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_conf_path_error_location() {
+    let error = load_path::<DFW>(&resource("conf_path_errors").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    // The second rule of `20-second.toml` is the fourth rule after merging.
+    assert!(error.file.unwrap().ends_with("20-second.toml"));
+    assert_eq!(error.line, Some(12));
+    assert_eq!(error.column, Some(3));
+    assert_eq!(error.section, Some("container_to_container".to_owned()));
+    assert_eq!(error.rule, Some(1));
+    assert_eq!(
+        error.key_path,
+        Some("container_to_container.rules[1].dst_contaner".to_owned())
+    );
+    assert_eq!(
+        error.source_line,
+        Some("  dst_contaner = \"db\"".to_owned())
+    );
+    assert!(error.message.contains("unknown field `dst_contaner`"));
+}
+
+#[test]
+fn parse_conf_path_invalid_location() {
+    let error = load_path::<DFW>(&resource("conf_path_invalid").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    assert!(error.file.unwrap().ends_with("broken.toml"));
+    assert_eq!(error.line, Some(1));
+    assert_eq!(error.column, Some(24));
+}
+
+#[test]
+fn parse_conf_file_invalid_yaml_location() {
+    let error = load_file::<DFW>(&resource("conf-invalid.yaml").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    assert!(error.file.unwrap().ends_with("conf-invalid.yaml"));
+    // The unterminated list is only noticed at the end of the file.
+    assert_eq!(error.line, Some(6));
+    assert_eq!(error.column, Some(1));
+}
+
+#[test]
+fn parse_conf_file_invalid_json_location() {
+    let error = load_file::<DFW>(&resource("conf-invalid.json").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    assert!(error.file.unwrap().ends_with("conf-invalid.json"));
+    assert_eq!(error.line, Some(4));
+    assert_eq!(error.column, Some(3));
+    assert_eq!(error.source_line, Some("  }".to_owned()));
+}
+
+#[test]
+fn parse_conf_file_unknown_field_yaml_location() {
+    let error = load_file::<DFW>(&resource("conf-invalid-field.yaml").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    assert!(error.file.unwrap().ends_with("conf-invalid-field.yaml"));
+    assert_eq!(error.line, Some(8));
+    assert_eq!(error.column, Some(7));
+    assert_eq!(
+        error.key_path,
+        Some("container_to_container.rules[1].dst_contaner".to_owned())
+    );
+    assert_eq!(error.source_line, Some("      dst_contaner: db".to_owned()));
+    assert!(error.message.contains("unknown field `dst_contaner`"));
+}
+
+#[test]
+fn parse_conf_file_missing_field_yaml_location() {
+    let error = load_file::<DFW>(&resource("conf-invalid-missing-action.yaml").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    // Missing fields are located at the rule lacking them.
+    assert_eq!(error.line, Some(6));
+    assert_eq!(error.column, Some(3));
+    assert_eq!(error.source_line, Some("  - network: network".to_owned()));
+    assert!(error.message.contains("missing field `action`"));
+}
+
+#[test]
+fn parse_conf_file_unknown_field_json_location() {
+    let error = load_file::<DFW>(&resource("conf-invalid-field.json").unwrap())
+        .unwrap_err()
+        .downcast::<ConfigError>()
+        .unwrap();
+
+    assert!(error.file.unwrap().ends_with("conf-invalid-field.json"));
+    assert_eq!(error.line, Some(12));
+    assert_eq!(error.column, Some(9));
+    assert_eq!(
+        error.key_path,
+        Some("container_to_container.rules[1].dst_contaner".to_owned())
+    );
+    assert_eq!(
+        error.source_line,
+        Some("        \"dst_contaner\": \"db\",".to_owned())
+    );
+    assert!(error.message.contains("unknown field `dst_contaner`"));
+}
+
+#[test]
+fn config_error_render() {
+    let error = ConfigError {
+        message: "unknown field `dst_contaner`".to_owned(),
+        file: Some("conf.toml".into()),
+        line: Some(12),
+        column: Some(3),
+        section: Some("container_to_container".to_owned()),
+        rule: Some(1),
+        key_path: Some("container_to_container.rules[1].dst_contaner".to_owned()),
+        source_line: Some("  dst_contaner = \"db\"".to_owned()),
+    };
+
+    assert_eq!(
+        error.to_string(),
+        "conf.toml:12:3: in `container_to_container.rules[1].dst_contaner`: unknown field \
+         `dst_contaner`"
+    );
+    assert_eq!(
+        error.render(),
+        "error: unknown field `dst_contaner`
+  --> conf.toml:12:3
+   |
+12 |   dst_contaner = \"db\"
+   |   ^
+   = in `container_to_container.rules[1].dst_contaner`
+"
+    );
+}
+
+#[test]
+fn config_source_load() {
+    let file: DFW = ConfigSource::File(resource("conf-file.toml").unwrap())
+        .load()
+        .unwrap();
+    assert_eq!(
+        file,
+        load_file(&resource("conf-file.toml").unwrap()).unwrap()
+    );
+
+    let path: DFW = ConfigSource::Path(resource("conf_path").unwrap())
+        .load()
+        .unwrap();
+    assert_eq!(path, load_path(&resource("conf_path").unwrap()).unwrap());
+}
+
+#[test]
+fn config_source_watch_directory() {
+    assert_eq!(
+        ConfigSource::File("/etc/dfw/dfw.toml".to_owned()).watch_directory(),
+        PathBuf::from("/etc/dfw")
+    );
+    assert_eq!(
+        ConfigSource::File("dfw.toml".to_owned()).watch_directory(),
+        PathBuf::from(".")
+    );
+    assert_eq!(
+        ConfigSource::Path("/etc/dfw/conf.d".to_owned()).watch_directory(),
+        PathBuf::from("/etc/dfw/conf.d")
+    );
+}
+
+fn schedule(days: Option<&[&str]>, time: Option<&str>, timezone: Option<&str>) -> Schedule {
+    Schedule {
+        days: days.map(|days| days.iter().map(|&day| day.to_owned()).collect()),
+        time: time.map(|time| time.to_owned()),
+        timezone: timezone.map(|timezone| timezone.to_owned()),
+    }
+}
+
+#[test]
+fn schedule_to_iptables() {
+    assert_eq!(
+        schedule(Some(&["Sat", "sunday"]), None, None)
+            .to_iptables()
+            .unwrap(),
+        "-m time --weekdays Sat,Sun"
+    );
+    assert_eq!(
+        schedule(
+            Some(&["Fri-Mon", "Sun"]),
+            Some("8:00-17:30:15"),
+            Some("UTC")
+        )
+        .to_iptables()
+        .unwrap(),
+        "-m time --timestart 08:00:00 --timestop 17:30:15 --weekdays Mon,Fri,Sat,Sun"
+    );
+    assert_eq!(
+        schedule(None, Some("22:00-02:00"), Some("local"))
+            .to_iptables()
+            .unwrap(),
+        "-m time --timestart 22:00:00 --timestop 02:00:00 --kerneltz"
+    );
+    assert_eq!(
+        schedule(Some(&["Fri"]), Some("22:00-02:00"), None)
+            .to_iptables()
+            .unwrap(),
+        "-m time --timestart 22:00:00 --timestop 02:00:00 --weekdays Fri --contiguous"
+    );
+}
+
+#[test]
+fn schedule_to_nft() {
+    assert_eq!(
+        schedule(Some(&["Mon"]), None, None).to_nft().unwrap(),
+        "meta day \"Monday\""
+    );
+    assert_eq!(
+        schedule(Some(&["Mon-Wed"]), Some("08:00-17:00"), None)
+            .to_nft()
+            .unwrap(),
+        "meta day { \"Monday\", \"Tuesday\", \"Wednesday\" } meta hour \"08:00\"-\"17:00\""
+    );
+    assert_eq!(
+        schedule(None, Some("22:00-02:00"), None).to_nft().unwrap(),
+        "meta hour != \"02:00\"-\"22:00\""
+    );
+    assert!(schedule(Some(&["Fri"]), Some("22:00-02:00"), None)
+        .to_nft()
+        .is_err());
+}
+
+#[test]
+fn schedule_invalid() {
+    for (schedule, error) in &[
+        (
+            schedule(None, None, None),
+            "schedule must define `days` or `time`",
+        ),
+        (schedule(Some(&[]), None, None), "`days` must not be empty"),
+        (
+            schedule(Some(&["Mon-Fry"]), None, None),
+            "unknown day of the week 'Fry'",
+        ),
+        (
+            schedule(None, Some("08:00"), None),
+            "time range '08:00' has invalid format, expected `HH:MM-HH:MM`",
+        ),
+        (
+            schedule(None, Some("08:00-24:00"), None),
+            "time '24:00' is not a valid time of day",
+        ),
+        (
+            schedule(None, Some("8am-5pm"), None),
+            "time '8am' has invalid format, expected `HH:MM`",
+        ),
+        (
+            schedule(None, Some("08:00-8:00"), None),
+            "time range '08:00-8:00' is empty",
+        ),
+        (
+            schedule(None, Some("08:00-17:00"), Some("Europe/Berlin")),
+            "unsupported timezone 'Europe/Berlin', expected `utc` or `local`",
+        ),
+    ] {
+        assert_eq!(schedule.to_iptables(), Err((*error).to_owned()));
+    }
+}
+
+#[test]
+fn parse_schedule() {
+    let rule: ContainerToHostRule = toml::from_str(
+        r#"
+        network = "network"
+        action = "ACCEPT"
+        schedule = { days = "Mon-Fri", time = "08:00-17:00" }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        rule.schedule,
+        Some(schedule(Some(&["Mon-Fri"]), Some("08:00-17:00"), None))
+    );
+}